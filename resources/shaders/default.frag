@@ -1,21 +1,275 @@
 #version 330 core
 
+//variants: DEFERRED_GEOMETRY writes the surfaces in the g-buffer of the deferred renderer instead of lighting them,
+//DEFERRED_LIGHTING lights the pixels of the g-buffer (base pass or light volume)
+
+#ifdef DEFERRED_LIGHTING
+vec3 pos; //reconstructed from the depth
+#else
 in vec3 pos;
 in vec3 color;
 in vec2 uv;
 in vec3 normal;
 flat in int f;
 flat in int material;
+flat in float fade;
+#endif
+
+layout (location = 0) out vec4 output_color; //g-buffer: unlit color (ambient, emission and tints)
+layout (location = 1) out vec4 emissive_output; //light emitted by the surface, extracted by the bloom
+#ifdef DEFERRED_GEOMETRY
+layout (location = 2) out vec4 gbuffer_diffuse; //w: scale of the lights (0: unlit)
+layout (location = 3) out vec4 gbuffer_normal; //w: specular exponent
+layout (location = 4) out vec4 gbuffer_specular; //w: view depth (precise reconstruction of the position)
+#else
+layout (location = 2) out vec4 oit_weight; //weighted blended transparency only
+#endif
+
+#define MAX_MATERIALS 64
+#define MAX_TEXTURES 16
+
+struct Material {
+	vec4 ambient;
+	vec4 diffuse; //w: transparency
+	vec4 specular; //w: specular exponent
+	vec4 emissive;
+	ivec4 maps; //texture units of the ambient, diffuse, specular and emissive maps
+	ivec4 alpha_map; //x: texture unit of the transparency map (-1: none)
+};
+
+layout (std140) uniform Materials {
+	Material materials[MAX_MATERIALS];
+};
+
+uniform sampler2D textures[MAX_TEXTURES];
+
+//gl 3.3 only indexes sampler arrays with constants, the gradients are taken before branching to keep the mipmaps
+vec4 sample_map(int unit, vec2 uv) {
+	vec2 dx = dFdx(uv);
+	vec2 dy = dFdy(uv);
+	#define MAP(i) case i: return textureGrad(textures[i], uv, dx, dy);
+	switch (unit) {
+		MAP(0) MAP(1) MAP(2) MAP(3) MAP(4) MAP(5) MAP(6) MAP(7)
+		MAP(8) MAP(9) MAP(10) MAP(11) MAP(12) MAP(13) MAP(14) MAP(15)
+	}
+	return vec4(1);
+}
+
+//color maps are stored in srgb, the lighting is computed in linear space (encoded by the gamma of the post processing)
+vec3 to_linear(vec3 color) {
+	return pow(color, vec3(2.2));
+}
+
+#define MAX_LIGHTS 32
+#define MAX_SHADOW_MAPS 4
+#define MAX_CASCADES 4
+#define MAX_CUBE_SHADOW_MAPS 4
+
+struct Light {
+	vec4 position; //w: 0 directional, 1 spot, 2 point
+	vec4 direction; //w: cosine of the half aperture
+	vec4 color; //w: falloff distance (0: no attenuation)
+	vec4 parameters; //x: cosine of the inner cone
+	vec4 shadow; //x: shadow map (-1: none, cube map for point lights), y: radius of the filter in texels, z: range of a cube map, w: its resolution
+};
+
+struct Shadow {
+	mat4 light_space[MAX_CASCADES];
+	vec4 splits; //view depth of the end of each cascade
+	vec4 parameters; //x: cascades, y: fraction of a cascade blended with the next
+};
+
+layout (std140) uniform Lights {
+	vec4 ambient_light;
+	ivec4 light_count; //x
+	Light lights[MAX_LIGHTS];
+	Shadow shadows[MAX_SHADOW_MAPS];
+};
+
+uniform mat4 camera;
+uniform vec3 eye;
+uniform bool debug_cascades;
+uniform bool weighted_transparency;
+uniform bool use_ambient_occlusion;
+uniform sampler2D ambient_occlusion; //screen space, 1: unoccluded
+uniform sampler2DArrayShadow shadow_maps[MAX_SHADOW_MAPS];
+uniform samplerCubeShadow cube_shadow_maps[MAX_CUBE_SHADOW_MAPS];
+
+//percentage closer filtering: average of the hardware filtered comparisons around the fragment in a layer of the map
+float pcf(sampler2DArrayShadow map, vec3 coord, int layer, int radius) {
+	vec2 texel = 1. / vec2(textureSize(map, 0).xy);
+	float lit = 0.;
+	for (int x = -radius; x <= radius; ++x) {
+		for (int y = -radius; y <= radius; ++y) {
+			lit += textureGrad(map, vec4(coord.xy + vec2(x, y) * texel, layer, coord.z), vec2(0), vec2(0));
+		}
+	}
+	return lit / float((2 * radius + 1) * (2 * radius + 1));
+}
+
+//offsets spread around the direction to the light for the soft point light shadows
+const vec3 cube_offsets[20] = vec3[](
+	vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+	vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1),
+	vec3(1, 1, 0), vec3(1, -1, 0), vec3(-1, -1, 0), vec3(-1, 1, 0),
+	vec3(1, 0, 1), vec3(-1, 0, 1), vec3(1, 0, -1), vec3(-1, 0, -1),
+	vec3(0, 1, 1), vec3(0, -1, 1), vec3(0, -1, -1), vec3(0, 1, -1)
+);
+
+//average of the hardware filtered comparisons of the linear distance around the direction to the fragment
+float cube_pcf(samplerCubeShadow map, vec3 d, float depth, float radius) {
+	if (radius == 0.) {
+		return textureGrad(map, vec4(d, depth), vec3(0), vec3(0));
+	}
+	float lit = 0.;
+	for (int i = 0; i < 20; ++i) {
+		lit += textureGrad(map, vec4(d + cube_offsets[i] * radius, depth), vec3(0), vec3(0));
+	}
+	return lit / 20.;
+}
+
+//fraction of a point light reaching the fragment, the filter radius grows with the distance like the texels of the map
+float cube_shadow_factor(Light light) {
+	vec3 d = pos - light.position.xyz;
+	float distance = length(d);
+	if (distance > light.shadow.z) {
+		return 1.;
+	}
+	float radius = light.shadow.y * 2. * distance / light.shadow.w;
+	#define CUBE_SHADOW(i) case i: return cube_pcf(cube_shadow_maps[i], d, distance / light.shadow.z, radius);
+	switch (int(light.shadow.x)) {
+		CUBE_SHADOW(0) CUBE_SHADOW(1) CUBE_SHADOW(2) CUBE_SHADOW(3)
+	}
+	return 1.;
+}
 
-out vec4 output_color;
+//fraction of a light reaching the fragment in a cascade, same constant index restriction as sample_map (no gradients needed with textureGrad)
+float cascade_factor(int map, int cascade, int radius) {
+	vec4 p = shadows[map].light_space[cascade] * vec4(pos, 1);
+	vec3 coord = p.xyz / p.w * 0.5 + 0.5;
+	if (coord.z > 1.) { //behind the far plane of the light
+		return 1.;
+	}
+	#define SHADOW(i) case i: return pcf(shadow_maps[i], coord, cascade, radius);
+	switch (map) {
+		SHADOW(0) SHADOW(1) SHADOW(2) SHADOW(3)
+	}
+	return 1.;
+}
 
-uniform float fade;
+//cascade containing the fragment (MAX_CASCADES past the last one) and the weight of the next one at the end of the cascade
+int cascade_of(Shadow shadow, out float blend) {
+	float depth = -(camera * vec4(pos, 1)).z;
+	int count = int(shadow.parameters.x);
+	int cascade = 0;
+	while (cascade < count && depth > shadow.splits[cascade]) {
+		++cascade;
+	}
+	blend = 0.;
+	if (cascade == count) {
+		return MAX_CASCADES;
+	}
+	float start = cascade == 0 ? 0. : shadow.splits[cascade - 1];
+	float band = (shadow.splits[cascade] - start) * shadow.parameters.y;
+	blend = clamp((depth - shadow.splits[cascade] + band) / max(band, 0.0001), 0., 1.);
+	return cascade;
+}
+
+float shadow_factor(Light light) {
+	int map = int(light.shadow.x);
+	float blend;
+	int cascade = cascade_of(shadows[map], blend);
+	int count = int(shadows[map].parameters.x);
+	if (cascade >= count) {
+		return 1.;
+	}
+	int radius = int(light.shadow.y);
+	float lit = cascade_factor(map, cascade, radius);
+	if (blend > 0.) { //the last cascade fades out
+		lit = mix(lit, cascade + 1 < count ? cascade_factor(map, cascade + 1, radius) : 1., blend);
+	}
+	return lit;
+}
+
+//blinn-phong contribution of a light, without the ambient term
+vec3 shade(Light light, vec3 n, vec3 v, vec3 kd, vec3 ks, float ns) {
+	vec3 l;
+	float attenuation = 1.;
+	if (light.position.w == 0.) {
+		l = -light.direction.xyz;
+	} else {
+		vec3 d = light.position.xyz - pos;
+		float distance = length(d);
+		l = d / max(distance, 0.0001);
+		if (light.color.w > 0.) {
+			float x = clamp(1. - distance / light.color.w, 0., 1.);
+			attenuation = x * x;
+		}
+		if (light.position.w == 1.) { //soft edge of the spot cone
+			attenuation *= smoothstep(light.direction.w, light.parameters.x, dot(-l, light.direction.xyz));
+		}
+	}
+	float lambert = max(dot(n, l), 0.);
+	if (lambert == 0. || attenuation == 0.) {
+		return vec3(0);
+	}
+	if (light.shadow.x >= 0.) {
+		attenuation *= light.position.w == 2. ? cube_shadow_factor(light) : shadow_factor(light);
+	}
+	float specular = pow(max(dot(n, normalize(l + v)), 0.), max(ns, 1.));
+	return light.color.rgb * attenuation * (kd * lambert + ks * specular);
+}
 
-uniform sampler2D ambient[128];
+#ifdef DEFERRED_LIGHTING
+uniform sampler2D gbuffer_color;
+uniform sampler2D gbuffer_emissive;
+uniform sampler2D gbuffer_diffuse;
+uniform sampler2D gbuffer_normal;
+uniform sampler2D gbuffer_specular;
+uniform sampler2D gbuffer_depth;
+uniform mat4 inverse_projection;
+uniform mat4 inverse_camera;
+uniform ivec2 origin; //bottom left of the viewport
+uniform int light_index; //-1: base pass (unlit color and lights without falloff), else light of the volume
 
-uniform int light_count;
-uniform vec3 lights[128];
+//lights bounded by their falloff are drawn with their own volume
+bool bounded(Light light) {
+	return light.position.w != 0. && light.color.w > 0.;
+}
 
+void main() {
+	ivec2 texel = ivec2(gl_FragCoord.xy) - origin;
+	float depth = texelFetch(gbuffer_depth, texel, 0).r;
+	if (depth == 1.) { //background
+		discard;
+	}
+	vec4 kd = texelFetch(gbuffer_diffuse, texel, 0);
+	vec4 n = texelFetch(gbuffer_normal, texel, 0);
+	vec4 ks = texelFetch(gbuffer_specular, texel, 0);
+	//point of the far plane seen by the pixel, scaled to the view depth
+	vec4 far = inverse_projection * vec4((vec2(texel) + 0.5) / vec2(textureSize(gbuffer_depth, 0)) * 2. - 1., 1., 1.);
+	pos = (inverse_camera * vec4(far.xyz / far.w * ks.w / (-far.z / far.w), 1)).xyz;
+	vec3 v = normalize(eye - pos);
+	vec3 accumulated_light = vec3(0);
+	if (light_index < 0) {
+		gl_FragDepth = depth;
+		output_color = texelFetch(gbuffer_color, texel, 0);
+		emissive_output = texelFetch(gbuffer_emissive, texel, 0);
+		for (int i = 0; i < min(light_count.x, MAX_LIGHTS) && kd.w > 0.; ++i) {
+			if (!bounded(lights[i])) {
+				accumulated_light += shade(lights[i], n.xyz, v, kd.rgb, ks.rgb, n.w);
+			}
+		}
+		output_color.rgb += accumulated_light * kd.w;
+	} else {
+		if (kd.w > 0.) {
+			accumulated_light = shade(lights[light_index], n.xyz, v, kd.rgb, ks.rgb, n.w);
+		}
+		output_color = vec4(accumulated_light * kd.w, 0);
+		emissive_output = vec4(0);
+	}
+}
+#else
 void main() {
 	int depth = 32;
 	float scale = float(depth - 1);
@@ -25,22 +279,69 @@ void main() {
 	float g = float(face * 2 % depth) / scale;
 	float b = float(face % depth) / scale;
 	vec4 geo_color = vec4(r, r, r, 1.);
+	emissive_output = vec4(0, 0, 0, 1);
+#ifdef DEFERRED_GEOMETRY
+	gbuffer_diffuse = vec4(0);
+	gbuffer_normal = vec4(0, 0, 1, 1);
+	gbuffer_specular = vec4(0, 0, 0, -(camera * vec4(pos, 1)).z);
+#endif
 
 	if ((f & 1) == 1) { //light dot
 		output_color = vec4(color, 1);
+		emissive_output = vec4(color, 1);
 	} else if ((f & 2) == 2) { //debug normals
 		output_color = vec4(normal * 0.5 + 0.5, 1);
 	} else { //default renderer
-//		vec3 accumulated_light = vec3(0.07, 0.07, 0.07); //ambient, initial luminance
-		vec3 accumulated_light = vec3(0.5, 0.5, 0.5);
-		for (int i = 0; i < light_count; ++i) {
-			float power = 1. - clamp(distance(lights[i * 2], pos) / 500., 0., 1.); //linear light fallof over 500 units
-			vec3 light_dir = normalize(lights[i * 2] - pos);
-			accumulated_light += power * lights[i * 2 + 1] * max(dot(normal, light_dir), 0.);
+		Material m = materials[material];
+		vec3 albedo = mix(geo_color.rgb, to_linear(sample_map(m.maps.y, uv).rgb), fade);
+		vec3 kd = m.diffuse.rgb * albedo;
+		//the palette of unit 0 only stands for a missing diffuse map, sampled outside of the conditions to keep the gradients
+		vec3 specular_map = sample_map(m.maps.z, uv).rgb;
+		vec3 emissive_map = to_linear(sample_map(m.maps.w, uv).rgb);
+		vec4 alpha_map = sample_map(m.alpha_map.x, uv); //grey levels or alpha channel
+		vec3 ks = m.specular.rgb * (m.maps.z == 0 ? vec3(1) : specular_map);
+		vec3 ke = m.emissive.rgb * (m.maps.w == 0 ? vec3(1) : emissive_map);
+		//models without normals are lit with the normal of the face (facing the camera), back faces are lit when culling is disabled
+		vec3 face_normal = normalize(cross(dFdx(pos), dFdy(pos)));
+		vec3 n = dot(normal, normal) > 0.000001 ? normalize(gl_FrontFacing ? normal : -normal) : face_normal;
+		float occlusion = use_ambient_occlusion ? texelFetch(ambient_occlusion, ivec2(gl_FragCoord.xy), 0).r : 1.;
+		vec3 accumulated_light = ambient_light.rgb * m.ambient.rgb * albedo * occlusion + ke;
+#ifdef DEFERRED_GEOMETRY
+		gbuffer_diffuse = vec4(kd, 1);
+		gbuffer_normal = vec4(n, m.specular.w);
+		gbuffer_specular.rgb = ks;
+#else
+		vec3 v = normalize(eye - pos);
+		for (int i = 0; i < min(light_count.x, MAX_LIGHTS); ++i) {
+			accumulated_light += shade(lights[i], n, v, kd, ks, m.specular.w);
 		}
-		output_color = (vec4(texture(ambient[material], uv).rgb, 1) * fade + /*vec4(color, 1)*/geo_color * (1 - fade)) * vec4(min(accumulated_light, 1), 1);
+#endif
+		output_color = vec4(accumulated_light, m.diffuse.w * alpha_map.r * alpha_map.a);
+		emissive_output = vec4(ke, output_color.a);
+	}
+	if (debug_cascades) { //cascade of the first shadow map: red, green, blue, yellow
+		float blend;
+		int cascade = cascade_of(shadows[0], blend);
+		const vec3 cascade_colors[MAX_CASCADES + 1] = vec3[](vec3(1, 0, 0), vec3(0, 1, 0), vec3(0, 0, 1), vec3(1, 1, 0), vec3(1));
+		output_color.rgb = output_color.rgb * 0.5 + cascade_colors[min(cascade, MAX_CASCADES)] * 0.5;
+#ifdef DEFERRED_GEOMETRY
+		gbuffer_diffuse.w *= 0.5;
+#endif
 	}
 	if ((f & 4) == 4) {
 		output_color = output_color * 0.5 + vec4(0.5, 0.5, 0., 0.5);
+#ifdef DEFERRED_GEOMETRY
+		gbuffer_diffuse.w *= 0.5;
+#endif
+	}
+#ifndef DEFERRED_GEOMETRY
+	if (weighted_transparency) { //weight decreasing with the view depth (mcguire and bavoil)
+		float z = -(camera * vec4(pos, 1)).z;
+		float a = output_color.a;
+		float w = a * clamp(10. / (0.00001 + pow(z / 5., 2.) + pow(z / 200., 6.)), 0.01, 3000.);
+		oit_weight = vec4(w, 0, 0, 0);
+		output_color = vec4(output_color.rgb * w, a);
 	}
-}
\ No newline at end of file
+#endif
+}
+#endif
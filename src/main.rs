@@ -6,41 +6,77 @@ use std::ops::Add;
 use gl::types::GLint;
 use winit::dpi::PhysicalPosition;
 use winit::event;
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::ControlFlow;
 use winit::window::{Fullscreen, WindowBuilder};
-use crate::maths::matrix::{Mat4, Matrix};
+use crate::maths::matrix::Matrix;
 use crate::maths::quaternion::Quaternion;
 use crate::maths::transform::Transform;
 use crate::maths::vector::{Vec3, Vector};
 use crate::opengl::buffers::{GPUBuffers, VertexType};
 use crate::opengl::enums::{RenderMode, Shaders, Side};
-use crate::opengl::frustrum::{Frustrum, Volume};
-use crate::opengl::object::MultiPartModel;
+use crate::opengl::lights::Light;
+use crate::opengl::lights::shadows::ShadowSettings;
+use crate::opengl::object::{LodSettings, MultiPartModel};
+use crate::opengl::post::antialiasing::AntiAliasing;
+use crate::opengl::post::bloom::Bloom;
 use crate::opengl::safe_calls;
 use crate::opengl::scene::{ObjectData, Scene};
 use crate::opengl::shader::{Drawable, ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::ssao::SsaoSettings;
+use crate::opengl::transparency::TransparencyMode;
 use crate::other::inputs::Inputs;
 use crate::other::resource_manager::ResourceManager;
 use crate::other::window;
 use crate::other::window::GlWindow;
+use crate::parser::{RepairOptions, SubdivisionOptions};
 
 mod parser;
 mod opengl;
 mod maths;
 mod other;
+mod mesh;
 
 fn main() {
+    //--aa <none|msaa|msaa8|fxaa|taa> or --aa=<mode>, --no-post
+    let mut antialiasing = AntiAliasing::None;
+    let mut post_processing = true;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--no-post" {
+            post_processing = false;
+            continue;
+        }
+        let value = if arg == "--aa" { args.next() } else { arg.strip_prefix("--aa=").map(str::to_string) };
+        if let Some(value) = value {
+            match value.parse() {
+                Ok(mode) => antialiasing = mode,
+                Err(()) => eprintln!("unknown anti-aliasing mode: {value} (none, msaa, msaa2-16, fxaa or taa)")
+            }
+        }
+    }
+    //the post processing multisamples its own targets, the window only needs samples when the scene is drawn directly to it
+    let samples = match antialiasing {
+        AntiAliasing::Msaa(samples) if !post_processing => samples as u16,
+        _ => 0
+    };
     if let Some((mut window, event_loop)) = GlWindow::new(WindowBuilder::new()
         .with_title("Scop")
         .with_visible(true)
-        .with_fullscreen(Some(Fullscreen::Borderless(None)))
+        .with_fullscreen(Some(Fullscreen::Borderless(None))),
+        samples
     ) {
 
         println!("{}", safe_calls::get_int(gl::MAX_VERTEX_UNIFORM_COMPONENTS));
         
         let mut resources = ResourceManager::default();
         resources.register_hints(&["resources", "resources/objs", "resources/materials", "resources/textures", "resources/shaders"]);
+        resources.set_repair_options(Some(RepairOptions {
+            drop_degenerates: true,
+            drop_duplicates: true,
+            ..Default::default()
+        }));
+        resources.set_lod_settings(Some(LodSettings::default()));
 
         let mut program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
         program.set_active();
@@ -55,8 +91,12 @@ fn main() {
         // let mut obj = Object::new(&mut resources, &obj42);
 
         let mut scene = Scene::new(program);
+        scene.set_post_processing(post_processing);
+        scene.set_antialiasing(antialiasing);
+        scene.set_ambient_occlusion(Some(SsaoSettings::default()));
 
         let (id, _) = resources.load_multipart_model("42").unwrap();
+        print_report(&resources, id, "42");
         // let (t, _) = resources.load_multipart_model("objs/42").unwrap();
         // let (o, _) = resources.load_multipart_model("cube").unwrap();
         // let (to, _) = resources.load_multipart_model("dragon").unwrap();
@@ -71,12 +111,18 @@ fn main() {
         
         //stress test: got >144 fps with ~109k (330*330) instance of "42" rotating on my gtx1070 (uncaped with a single object i get 2000~2300 fps)
         //>144 fps with 900 (30*30) "dragon" rotating
+        //the rotating instances (flag 4) are listed, the others are never visited by the frame loop
+        let mut animated = HashSet::new();
         for i in 0..330 {
             for j in 0..330 {
-                scene.spawn_object(id, ObjectData::from(o2 + Vec3::X * i as f32 + Vec3::Y * j as f32).with_flags(4));
+                animated.insert(scene.spawn_object(id, ObjectData::from(o2 + Vec3::X * i as f32 + Vec3::Y * j as f32).with_flags(4)));
             }
         }
         
+        scene.spawn_light(Light::directional(Vec3::new(-1., -2., -3.), Vec3::new(0.8, 0.8, 0.7)).with_shadow(ShadowSettings::default()));
+        scene.spawn_light(Light::point(Vec3::new(165., 165., 20.), Vec3::new(1., 0.4, 0.2), 200.).with_shadow(ShadowSettings { resolution: 512, ..ShadowSettings::default() }));
+        scene.spawn_light(Light::spot(Vec3::new(0., 0., 30.), -Vec3::Z, 60f32.to_radians(), Vec3::new(0.2, 0.4, 1.), 100.));
+
         scene.set_camera(Transform::from_look_at(Vec3::Z * 10., Vec3::default()));
         scene.set_projection(80., 16./9.);
         
@@ -90,33 +136,15 @@ fn main() {
         };
         let mut process_picking = false;
         let mut destroy_picking = false;
+        let mut selection_start = None;
+        let mut box_selection = false;
         
         let mut frames = -1;
 
         let mut timer = std::time::Instant::now();
 
-        let mut shadow_map_buffer = 0;
-        let mut shadow_map = 0;
-        unsafe {
-            gl::GenFramebuffers(1, &mut shadow_map_buffer);
-            gl::GenTextures(1, &mut shadow_map);
-            gl::BindTexture(gl::TEXTURE_2D, shadow_map);
-            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT as GLint, 1024, 1024, 0, gl::DEPTH_COMPONENT, gl::FLOAT, 0 as *const c_void);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
-            gl::BindFramebuffer(gl::FRAMEBUFFER, shadow_map_buffer);
-            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, shadow_map, 0);
-            gl::DrawBuffer(gl::NONE);
-            gl::ReadBuffer(gl::NONE);
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-        }
-        
         let uncapped = true;
         
-        let mut visible_set = HashSet::new();
-        
         event_loop.run(move |event, _target, control_flow| {
                 match event {
                     Event::WindowEvent {
@@ -132,11 +160,68 @@ fn main() {
                             if state == ElementState::Pressed {
                                 destroy_picking = button == MouseButton::Right;
                                 process_picking = button == MouseButton::Left;
-                                
+                                if button == MouseButton::Middle {
+                                    selection_start = Some(mouse_pos);
+                                }
+                            } else if button == MouseButton::Middle {
+                                box_selection = true;
+                            }
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::V), .. }, .. } = event {
+                            //toggle subdivision of the loaded models
+                            let options = if resources.subdivision().is_some() {
+                                None
+                            } else {
+                                Some(SubdivisionOptions { levels: 2, crease_materials: true, ..Default::default() })
+                            };
+                            resources.set_subdivision(options);
+                            resources.rebuild_multipart_models();
+                            scene.invalidate();
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::F), .. }, .. } = event {
+                            scene.run_on_instances(|_, _, data| data.set_fade(1. - data.fade()));
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::I), .. }, .. } = event {
+                            scene.set_indirect(scene.indirect().is_none());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::G), .. }, .. } = event {
+                            scene.set_gpu_culling(scene.gpu_culling().is_none());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::C), .. }, .. } = event {
+                            scene.set_cascade_debug(!scene.cascade_debug());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::T), .. }, .. } = event {
+                            scene.set_transparency(if scene.transparency() == TransparencyMode::Sorted { TransparencyMode::WeightedBlended } else { TransparencyMode::Sorted });
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::O), .. }, .. } = event {
+                            scene.set_ambient_occlusion(scene.ambient_occlusion().is_none().then(SsaoSettings::default));
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::X), .. }, .. } = event {
+                            scene.set_antialiasing(scene.antialiasing().cycle());
+                            println!("anti-aliasing: {:?}", scene.antialiasing());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::L), .. }, .. } = event {
+                            scene.set_renderer(&mut resources, scene.renderer().toggle());
+                            println!("renderer: {:?}", scene.renderer());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::P), .. }, .. } = event {
+                            scene.set_post_processing(scene.post_processing().is_none());
+                        }
+                        if let WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key @ (VirtualKeyCode::B | VirtualKeyCode::LBracket | VirtualKeyCode::RBracket | VirtualKeyCode::Minus | VirtualKeyCode::Equals)), .. }, .. } = event {
+                            if let Some(bloom) = scene.post_processing_mut().and_then(|post| post.effect_mut::<Bloom>()) {
+                                match key {
+                                    VirtualKeyCode::B => bloom.enabled = !bloom.enabled,
+                                    VirtualKeyCode::LBracket => bloom.intensity /= 1.25,
+                                    VirtualKeyCode::RBracket => bloom.intensity *= 1.25,
+                                    VirtualKeyCode::Minus => bloom.threshold = (bloom.threshold - 0.1).max(0.),
+                                    _ => bloom.threshold += 0.1
+                                }
+                                println!("bloom {}: intensity {}, threshold {}", if bloom.enabled { "on" } else { "off" }, bloom.intensity, bloom.threshold);
                             }
                         }
                         if let WindowEvent::DroppedFile(path) = event {
                             if let Some((id, _)) = resources.load_multipart_model(path.to_str().unwrap()) {
+                                print_report(&resources, id, path.to_str().unwrap());
                                 scene.spawn_object(id, ObjectData::from(Transform::default()));
                             }
                         }
@@ -145,47 +230,55 @@ fn main() {
                         let elapsed = timer.elapsed();
                         if uncapped || elapsed.as_secs_f64() >= 1. / 60. {
                             timer = std::time::Instant::now();
-                            if frames == -1 {
-                                let frustrum = Frustrum::from_vp(&(scene.get_projection() * Mat4::from(scene.get_camera())));
-                                scene.run_on_instances(|model, id, data| {
-                                    if resources.get_multipart_model(model).unwrap().visible(&data.transform, &frustrum) {
-                                        visible_set.insert(id);
-                                    }
-                                });
-                                // scene.debug();
-                            }
                             frames += 1;
                             if frames >= 144 {
                                 frames = 0;
+                                let stats = scene.stats();
+                                println!("{} draw calls, {} instances, {} triangles{}", stats.draw_calls, stats.instances, stats.triangles, if scene.gpu_culling().is_some() { " (gpu culling)" } else if scene.indirect().is_some() { " (indirect)" } else { "" });
+                                let state = safe_calls::take_state_counters();
+                                println!("per frame: {} state changes issued, {} skipped", state.issued / 144, state.skipped / 144);
+                            }
+                            for id in &animated {
+                                scene.run_on_instance(*id, |_, _, data| {
+                                    data.transform_mut().rotate_absolute(Vec3::Y, 0.1f32.to_radians());
+                                });
                             }
-                            scene.run_on_instances(|model, id, data| {
-                                if data.flags & 4 == 4 {
-                                    data.transform.rotate_absolute(Vec3::Y, 0.1f32.to_radians());
-                                    Mat4::from(data.transform).raw_copy(&mut data.raw_mat);
-                                }
-                            });
                             if process_picking || destroy_picking {
                                 process_picking = false;
-                                if let Some(t) = scene.pick(&resources, mouse_pos.x as usize, (safe_calls::get_size().1 as f64 - mouse_pos.y) as usize, /*Some(&visible_set)*/None) {
+                                if let Some(t) = scene.pick_ray(&resources, mouse_pos.x as f32, (safe_calls::get_size().1 as f64 - mouse_pos.y) as f32).map(|hit| hit.instance) {
                                     if destroy_picking {
                                         // scene.despawn_object(t);
                                         scene.run_on_instance(t, |_, _, data| {
-                                            data.visible ^= true;
+                                            data.set_visible(!data.visible());
                                         });
                                     } else {
                                         scene.run_on_instance(t, |_, _, data| {
-                                            if data.flags & 4 == 0 {
-                                                data.flags |= 4
-                                            } else {
-                                                data.flags &= !4
-                                            }
+                                            data.set_flags(data.flags() ^ 4);
                                         });
+                                        if !animated.remove(&t) {
+                                            animated.insert(t);
+                                        }
                                     }
                                 }
                                 destroy_picking = false;
                             }
+                            if box_selection {
+                                box_selection = false;
+                                if let Some(start) = selection_start.take() {
+                                    let height = safe_calls::get_size().1 as f64;
+                                    let corner = |p: PhysicalPosition<f64>| (p.x.max(0.) as usize, (height - p.y).max(0.) as usize);
+                                    for id in scene.pick_rect(&resources, corner(start), corner(mouse_pos)) {
+                                        scene.run_on_instance(id, |_, _, data| {
+                                            data.set_flags(data.flags() ^ 4);
+                                        });
+                                        if !animated.remove(&id) {
+                                            animated.insert(id);
+                                        }
+                                    }
+                                }
+                            }
                             safe_calls::clear_screen();
-                            scene.draw(&resources, /*Some(&visible_set)*/None);
+                            scene.draw(&resources);
                             window.refresh();
                         }
                     }
@@ -195,6 +288,13 @@ fn main() {
     }
 }
 
+///print the issues found in the object of a freshly loaded model
+fn print_report(resources: &ResourceManager, model: usize, name: &str) {
+    if let Some(report) = resources.get_validation_report(model).filter(|r| !r.is_clean()) {
+        println!("{name}:\n{report}");
+    }
+}
+
 /*
 fn main() {
     let mut resources = ResourceManager::default();
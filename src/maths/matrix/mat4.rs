@@ -32,20 +32,59 @@ impl Mat4 {
             [s / ratio, 0., 0., 0.],
             [0., s, 0., 0.],
             [0., 0., (far + near) / l, 2. * near * far / l],
-            [0., 0., -1., 1.],
+            [0., 0., -1., 0.],
         ])
     }
 
     pub fn orthographic(width: f32, ratio: f32, near: f32, far: f32) -> Self {
         let height = width / ratio;
+        Self::orthographic_box(-width / 2., width / 2., -height / 2., height / 2., near, far)
+    }
+
+    ///maps the view space box [left, right] x [bottom, top] x [-near, -far] to the clip cube
+    pub fn orthographic_box(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
         Self::from([
-            [2. / width, 0., 0., 0.],
-            [0., 2. / height, 0., 0.],
-            [0., 0., 2. / (far - near), 0.],
+            [2. / (right - left), 0., 0., -(right + left) / (right - left)],
+            [0., 2. / (top - bottom), 0., -(top + bottom) / (top - bottom)],
+            [0., 0., -2. / (far - near), -(far + near) / (far - near)],
             [0., 0., 0., 1.],
         ])
     }
 
+    ///gauss-jordan elimination with partial pivoting (in f64), None if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = [[0f64; 8]; 4];
+        for (r, row) in a.iter_mut().enumerate() {
+            for (c, v) in row[..4].iter_mut().enumerate() {
+                *v = self.0[r][c] as f64;
+            }
+            row[4 + r] = 1.;
+        }
+        for c in 0..4 {
+            let pivot = (c..4).max_by(|x, y| a[*x][c].abs().total_cmp(&a[*y][c].abs())).unwrap();
+            if a[pivot][c].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(c, pivot);
+            let p = a[c][c];
+            a[c].iter_mut().for_each(|v| *v /= p);
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    let row = a[c];
+                    a[r].iter_mut().zip(row).for_each(|(v, p)| *v -= f * p);
+                }
+            }
+        }
+        let mut out = Self::default();
+        for (r, row) in a.iter().enumerate() {
+            for (c, v) in row[4..].iter().enumerate() {
+                out.0[r][c] = *v as f32;
+            }
+        }
+        Some(out)
+    }
+
     pub fn raw_array(&self) -> [f32; 16] {
         let mut out = [0f32; 16];
         for c in 0..4 {
@@ -55,4 +94,22 @@ impl Mat4 {
         }
         out
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::maths::matrix::Mat4;
+    use crate::maths::vector::Vec4;
+
+    #[test]
+    fn projection() {
+        let projection = Mat4::projection(90f32.to_radians(), 2., 1., 3.);
+        let clip = projection * Vec4::from([2., 1., -2., 1.]);
+        assert_eq!([clip[0], clip[1], clip[2], clip[3]], [1., 1., 1., 2.]);
+        //the near and far planes end at -1 and 1 once divided by w (the distance in front of the camera)
+        for (z, ndc) in [(-1., -1.), (-3., 1.)] {
+            let clip = projection * Vec4::from([0., 0., z, 1.]);
+            assert_eq!(clip[3], -z);
+            assert!((clip[2] / clip[3] - ndc).abs() < 1e-6);
+        }
+    }
+}
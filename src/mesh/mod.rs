@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use crate::parser::ParsedObject;
+
+pub mod simplify;
+
+//processed (indexed) geometry of a single material part, kept cpu side to derive lods and other processing
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub colors: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+//helper to build a mesh by pushing corners, corners with identical attributes are shared
+#[derive(Default)]
+pub struct MeshBuilder {
+    mesh: Mesh,
+    shared: HashMap<[u32; 12], u32>,
+}
+
+impl Mesh {
+    ///triangulate (fan) a range of faces of a parsed object, missing or invalid references default to 0
+    pub fn from_faces(parsed: &ParsedObject, faces: RangeInclusive<usize>) -> Self {
+        let mut builder = MeshBuilder::default();
+        let mut corners = Vec::new();
+        for face in &parsed.faces[faces] {
+            corners.clear();
+            for vf in face {
+                let (v, c) = if vf[0] > 0 && vf[0] <= parsed.vertexes.len() {
+                    (parsed.vertexes[vf[0] - 1].pos, parsed.vertexes[vf[0] - 1].color)
+                } else {
+                    ([0., 0., 0.], [0., 0., 0.])
+                };
+                let u = if vf[1] > 0 && vf[1] <= parsed.uvs.len() {
+                    parsed.uvs[vf[1] - 1]
+                } else {
+                    [0., 0., 0.]
+                };
+                let n = if vf[2] > 0 && vf[2] <= parsed.normals.len() {
+                    parsed.normals[vf[2] - 1]
+                } else {
+                    [0., 0., 0.]
+                };
+                corners.push(builder.corner(v, c, u, n));
+            }
+            for step in 0..(corners.len() - 2) {
+                builder.triangle(corners[0], corners[step + 1], corners[step + 2]);
+            }
+        }
+        builder.build()
+    }
+
+    pub fn vertex_count(&self) -> usize { self.positions.len() }
+
+    pub fn triangle_count(&self) -> usize { self.indices.len() / 3 }
+
+    pub fn triangle(&self, index: usize) -> [usize; 3] {
+        [self.indices[index * 3] as usize, self.indices[index * 3 + 1] as usize, self.indices[index * 3 + 2] as usize]
+    }
+}
+
+impl MeshBuilder {
+    ///push a corner (position, color, uv, normal) and return it's index
+    pub fn corner(&mut self, position: [f32; 3], color: [f32; 3], uv: [f32; 3], normal: [f32; 3]) -> u32 {
+        let mut key = [0u32; 12];
+        for (i, v) in position.iter().chain(color.iter()).chain(uv.iter()).chain(normal.iter()).enumerate() {
+            key[i] = v.to_bits();
+        }
+        let mesh = &mut self.mesh;
+        *self.shared.entry(key).or_insert_with(|| {
+            mesh.positions.push(position);
+            mesh.colors.push(color);
+            mesh.uvs.push(uv);
+            mesh.normals.push(normal);
+            mesh.positions.len() as u32 - 1
+        })
+    }
+
+    pub fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.mesh.indices.extend([a, b, c]);
+    }
+
+    pub fn build(self) -> Mesh {
+        self.mesh
+    }
+}
@@ -0,0 +1,419 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use super::Mesh;
+
+//Garland & Heckbert edge collapse decimation (https://www.cs.cmu.edu/~garland/Papers/quadrics.pdf)
+//vertices sharing a position with different attributes (uv/color/normal seams) and border vertices (material seams, holes)
+//are locked: edges can collapse into them but they never move, so seams stay intact
+
+//symmetric 4x4 error matrix, upper triangle stored row by row
+#[derive(Debug, Default, Copy, Clone)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(n: [f64; 3], d: f64, weight: f64) -> Self {
+        let [a, b, c] = n;
+        Self([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|v| v * weight))
+    }
+
+    fn add(&mut self, other: &Self) {
+        for i in 0..10 {
+            self.0[i] += other.0[i];
+        }
+    }
+
+    fn sum(&self, other: &Self) -> Self {
+        let mut out = *self;
+        out.add(other);
+        out
+    }
+
+    fn error(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let q = &self.0;
+        q[0] * x * x + 2. * q[1] * x * y + 2. * q[2] * x * z + 2. * q[3] * x
+            + q[4] * y * y + 2. * q[5] * y * z + 2. * q[6] * y
+            + q[7] * z * z + 2. * q[8] * z
+            + q[9]
+    }
+
+    ///position minimizing the error (solve the 3x3 system with cramer's rule), none if the matrix is singular
+    fn optimal(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let m = [[q[0], q[1], q[2]], [q[1], q[4], q[5]], [q[2], q[5], q[7]]];
+        let r = [-q[3], -q[6], -q[8]];
+        let det = det3(m);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let mut out = [0.; 3];
+        for (c, o) in out.iter_mut().enumerate() {
+            let mut t = m;
+            for row in 0..3 {
+                t[row][c] = r[row];
+            }
+            *o = det3(t) / det;
+        }
+        Some(out)
+    }
+}
+
+fn det3(m: [[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] { [a[0] - b[0], a[1] - b[1], a[2] - b[2]] }
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+//collapse of point b into point a (b is never locked), min-heap ordered on cost
+struct Candidate {
+    cost: f64,
+    a: usize,
+    b: usize,
+    stamps: [u32; 2],
+    target: [f64; 3],
+    t: f32, //position of the target along a -> b, used to interpolate attributes
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool { self.cost == other.cost }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering { other.cost.total_cmp(&self.cost) }
+}
+
+struct Simplifier<'m> {
+    mesh: &'m Mesh,
+    positions: Vec<[f64; 3]>, //per point
+    point_of: Vec<usize>, //per vertex
+    vertex_of: Vec<usize>, //per point, only meaningful for unlocked points
+    locked: Vec<bool>,
+    alive: Vec<bool>,
+    stamps: Vec<u32>,
+    quadrics: Vec<Quadric>,
+    point_triangles: Vec<Vec<usize>>,
+    triangles: Vec<Option<[usize; 3]>>, //vertex indexes
+    colors: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl<'m> Simplifier<'m> {
+    fn new(mesh: &'m Mesh) -> Self {
+        let mut points: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut positions = Vec::new();
+        let mut vertex_of = Vec::new();
+        let mut locked = Vec::new();
+        let point_of = mesh.positions.iter().enumerate().map(|(v, p)| {
+            *points.entry(p.map(f32::to_bits)).and_modify(|point| locked[*point] = true).or_insert_with(|| {
+                positions.push(p.map(|c| c as f64));
+                vertex_of.push(v);
+                locked.push(false);
+                positions.len() - 1
+            })
+        }).collect::<Vec<usize>>();
+        let mut out = Self {
+            mesh,
+            point_triangles: vec![Vec::new(); positions.len()],
+            quadrics: vec![Quadric::default(); positions.len()],
+            alive: vec![true; positions.len()],
+            stamps: vec![0; positions.len()],
+            positions,
+            point_of,
+            vertex_of,
+            locked,
+            triangles: Vec::with_capacity(mesh.triangle_count()),
+            colors: mesh.colors.clone(),
+            uvs: mesh.uvs.clone(),
+            normals: mesh.normals.clone(),
+            heap: BinaryHeap::new(),
+        };
+        let mut edges: HashMap<[usize; 2], usize> = HashMap::new();
+        for t in 0..mesh.triangle_count() {
+            let tri = mesh.triangle(t);
+            let p = tri.map(|v| out.point_of[v]);
+            if p[0] == p[1] || p[1] == p[2] || p[0] == p[2] {
+                continue;
+            }
+            let n = cross(sub(out.positions[p[1]], out.positions[p[0]]), sub(out.positions[p[2]], out.positions[p[0]]));
+            let area = dot(n, n).sqrt();
+            if area > 0. {
+                let n = n.map(|c| c / area);
+                let q = Quadric::from_plane(n, -dot(n, out.positions[p[0]]), area * 0.5);
+                p.iter().for_each(|p| out.quadrics[*p].add(&q));
+            }
+            for i in 0..3 {
+                let (a, b) = (p[i], p[(i + 1) % 3]);
+                *edges.entry([a.min(b), a.max(b)]).or_default() += 1;
+                out.point_triangles[p[i]].push(out.triangles.len());
+            }
+            out.triangles.push(Some(tri));
+        }
+        for (&[a, b], &count) in &edges {
+            if count != 2 { //border or non manifold edge
+                out.locked[a] = true;
+                out.locked[b] = true;
+            }
+        }
+        let mut edges = edges.into_keys().collect::<Vec<_>>();
+        edges.sort_unstable(); //keeps the result independent of the hash order
+        for [a, b] in edges {
+            out.push_candidate(a, b);
+        }
+        out
+    }
+
+    fn push_candidate(&mut self, u: usize, v: usize) {
+        let (a, b) = match (self.locked[u], self.locked[v]) {
+            (true, true) => return,
+            (false, true) => (v, u),
+            _ => (u, v),
+        };
+        let q = self.quadrics[a].sum(&self.quadrics[b]);
+        let (pa, pb) = (self.positions[a], self.positions[b]);
+        let target = if self.locked[a] {
+            pa
+        } else {
+            let mid = [(pa[0] + pb[0]) * 0.5, (pa[1] + pb[1]) * 0.5, (pa[2] + pb[2]) * 0.5];
+            let mut best = [pa, pb, mid].into_iter().min_by(|x, y| q.error(*x).total_cmp(&q.error(*y))).unwrap();
+            if let Some(o) = q.optimal() {
+                //the optimal position is only trusted if it stays near the edge
+                let d = sub(pb, pa);
+                let l = dot(d, d);
+                let t = if l > 0. { dot(sub(o, pa), d) / l } else { 0. };
+                if (-0.5..=1.5).contains(&t) && q.error(o) <= q.error(best) {
+                    best = o;
+                }
+            }
+            best
+        };
+        let d = sub(pb, pa);
+        let l = dot(d, d);
+        let t = if l > 0. { (dot(sub(target, pa), d) / l).clamp(0., 1.) } else { 0. };
+        self.heap.push(Candidate {
+            cost: q.error(target).max(0.),
+            a,
+            b,
+            stamps: [self.stamps[a], self.stamps[b]],
+            target,
+            t: t as f32,
+        });
+    }
+
+    fn live_triangles(&self, point: usize) -> impl Iterator<Item = (usize, [usize; 3])> + '_ {
+        self.point_triangles[point].iter().filter_map(|t| self.triangles[*t].map(|tri| (*t, tri)))
+    }
+
+    fn neighbours(&self, point: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        for (_, tri) in self.live_triangles(point) {
+            for v in tri {
+                let p = self.point_of[v];
+                if p != point && !out.contains(&p) {
+                    out.push(p);
+                }
+            }
+        }
+        out
+    }
+
+    fn normal(&self, tri: [usize; 3], moved: usize, target: [f64; 3]) -> [f64; 3] {
+        let p = tri.map(|v| {
+            let p = self.point_of[v];
+            if p == moved { target } else { self.positions[p] }
+        });
+        cross(sub(p[1], p[0]), sub(p[2], p[0]))
+    }
+
+    ///would moving point to target flip (or flatten) one of the triangles not containing other
+    fn flips(&self, point: usize, other: usize, target: [f64; 3]) -> bool {
+        self.live_triangles(point).any(|(_, tri)| {
+            if tri.iter().any(|v| self.point_of[*v] == other) {
+                return false;
+            }
+            let before = self.normal(tri, point, self.positions[point]);
+            let after = self.normal(tri, point, target);
+            dot(before, after) <= 1e-3 * dot(before, before)
+        })
+    }
+
+    ///collapse b into a, returns the amount of triangles removed
+    fn collapse(&mut self, candidate: &Candidate) -> usize {
+        let Candidate { a, b, target, t, .. } = *candidate;
+        let mut va = None;
+        let mut shared = 0;
+        for (_, tri) in self.live_triangles(b) {
+            if let Some(v) = tri.iter().find(|v| self.point_of[**v] == a) {
+                va = Some(*v);
+                shared += 1;
+            }
+        }
+        let va = match va {
+            Some(va) => va,
+            None => return 0,
+        };
+        //link condition: a and b must only share the neighbours of the collapsed triangles (prevents pinching the surface)
+        let na = self.neighbours(a);
+        if self.neighbours(b).iter().filter(|n| na.contains(n)).count() != shared {
+            return 0;
+        }
+        if self.flips(b, a, target) || (!self.locked[a] && self.flips(a, b, target)) {
+            return 0;
+        }
+        let vb = self.vertex_of[b];
+        if !self.locked[a] {
+            self.colors[va] = lerp(self.colors[va], self.colors[vb], t);
+            self.uvs[va] = lerp(self.uvs[va], self.uvs[vb], t);
+            let n = lerp(self.normals[va], self.normals[vb], t);
+            let l = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            self.normals[va] = if l > 0. { n.map(|c| c / l) } else { n };
+            self.positions[a] = target;
+        }
+        let mut removed = 0;
+        for (index, tri) in self.live_triangles(b).collect::<Vec<_>>() {
+            if tri.iter().any(|v| self.point_of[*v] == a) {
+                self.triangles[index] = None;
+                removed += 1;
+            } else {
+                self.triangles[index] = Some(tri.map(|v| if v == vb { va } else { v }));
+                self.point_triangles[a].push(index);
+            }
+        }
+        self.point_of[vb] = a;
+        let qb = self.quadrics[b];
+        self.quadrics[a].add(&qb);
+        self.alive[b] = false;
+        self.point_triangles[b].clear();
+        self.stamps[a] += 1;
+        self.point_triangles[a].retain(|t| self.triangles[*t].is_some());
+        for n in self.neighbours(a) {
+            self.push_candidate(a, n);
+        }
+        removed
+    }
+
+    fn run(mut self, target_triangles: usize) -> Mesh {
+        let mut count = self.triangles.iter().filter(|t| t.is_some()).count();
+        //rejected collapses are retried once the heap is empty, as long as the previous pass still collapsed something
+        let mut rejected: Vec<[usize; 2]> = Vec::new();
+        let mut progressed = false;
+        while count > target_triangles {
+            let candidate = match self.heap.pop() {
+                Some(c) => c,
+                None if progressed && !rejected.is_empty() => {
+                    progressed = false;
+                    for [a, b] in std::mem::take(&mut rejected) {
+                        if self.alive[a] && self.alive[b] {
+                            self.push_candidate(a, b);
+                        }
+                    }
+                    continue;
+                }
+                None => break,
+            };
+            if !self.alive[candidate.a] || !self.alive[candidate.b] || candidate.stamps != [self.stamps[candidate.a], self.stamps[candidate.b]] {
+                continue;
+            }
+            match self.collapse(&candidate) {
+                0 => rejected.push([candidate.a, candidate.b]),
+                removed => {
+                    count -= removed;
+                    progressed = true;
+                }
+            }
+        }
+        let mut remap = vec![u32::MAX; self.mesh.vertex_count()];
+        let mut out = Mesh::default();
+        for tri in self.triangles.iter().flatten() {
+            for &v in tri {
+                if remap[v] == u32::MAX {
+                    remap[v] = out.positions.len() as u32;
+                    out.positions.push(self.positions[self.point_of[v]].map(|c| c as f32));
+                    out.colors.push(self.colors[v]);
+                    out.uvs.push(self.uvs[v]);
+                    out.normals.push(self.normals[v]);
+                }
+                out.indices.push(remap[v]);
+            }
+        }
+        out
+    }
+}
+
+impl Mesh {
+    ///reduce this mesh to at most target_triangles (or as close as possible without breaking seams and borders)
+    pub fn simplify(&self, target_triangles: usize) -> Mesh {
+        Simplifier::new(self).run(target_triangles)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mesh::{Mesh, MeshBuilder};
+
+    //flat grid of size * size quads in the xy plane, uvs following the positions
+    fn grid(size: usize) -> Mesh {
+        let mut builder = MeshBuilder::default();
+        let mut corner = |x: usize, y: usize| {
+            let p = [x as f32, y as f32, 0.];
+            builder.corner(p, [1.; 3], [x as f32 / size as f32, y as f32 / size as f32, 0.], [0., 0., 1.])
+        };
+        let mut quads = Vec::new();
+        for x in 0..size {
+            for y in 0..size {
+                quads.push([corner(x, y), corner(x + 1, y), corner(x + 1, y + 1), corner(x, y + 1)]);
+            }
+        }
+        for [a, b, c, d] in quads {
+            builder.triangle(a, b, c);
+            builder.triangle(a, c, d);
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn flat_grid_collapses_to_its_border() {
+        let mesh = grid(8);
+        assert_eq!(mesh.triangle_count(), 128);
+        let simple = mesh.simplify(0);
+        assert!(simple.triangle_count() < 64, "{}", simple.triangle_count());
+        for p in &simple.positions {
+            //border vertices are locked, interior vertices should all be gone
+            assert!(p[0] == 0. || p[0] == 8. || p[1] == 0. || p[1] == 8., "{p:?}");
+        }
+        for t in 0..simple.triangle_count() {
+            let [a, b, c] = simple.triangle(t).map(|v| simple.positions[v]);
+            let z = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+            assert!(z > 0., "flipped or degenerate triangle");
+        }
+        for (p, uv) in simple.positions.iter().zip(&simple.uvs) {
+            assert_eq!(p[0] / 8., uv[0]);
+            assert_eq!(p[1] / 8., uv[1]);
+        }
+    }
+
+    #[test]
+    fn respects_target() {
+        let mesh = grid(16);
+        let simple = mesh.simplify(256);
+        assert!(simple.triangle_count() <= 256 && simple.triangle_count() > 200, "{}", simple.triangle_count());
+    }
+}
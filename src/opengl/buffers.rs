@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use std::mem::size_of;
+use std::mem::{size_of, size_of_val};
 use std::os::raw::c_void;
 use gl::types::{GLenum, GLint, GLsizei, GLsizeiptr, GLuint};
 use crate::opengl::safe_calls;
@@ -87,10 +87,8 @@ impl GPUBuffers {
 
     ///bind this object (vao) to be in use by the shader/gpu or for manipulation/declaration cpu side
     pub fn bind(&self) {
-        if self.vao != 0 && safe_calls::get_vao() != self.vao {
-            unsafe {
-                gl::BindVertexArray(self.vao);
-            }
+        if self.vao != 0 {
+            safe_calls::bind_vao(self.vao);
         }
     }
 
@@ -113,12 +111,16 @@ impl GPUBuffers {
         true
     }
 
-    ///create a simple vertex buffer without mingle (meaning: all the data uploaded will be used only by this location)
+    ///create a simple vertex buffer without mingle (meaning: all the data uploaded will be used only by this location), integer kinds stay integers in the shader
     pub fn new_vbo(&mut self, index: usize, kind: VertexType) {
         if self.ensure_vbo(index) {
             unsafe {
                 let VertexTypeLayout { count, kind, size } = kind.layout();
-                gl::VertexAttribPointer(index as GLuint, count, kind, gl::FALSE, size, 0 as *const _);
+                if kind == gl::INT {
+                    gl::VertexAttribIPointer(index as GLuint, count, kind, size, std::ptr::null());
+                } else {
+                    gl::VertexAttribPointer(index as GLuint, count, kind, gl::FALSE, size, 0 as *const _);
+                }
                 gl::EnableVertexAttribArray(index as GLuint);
             }
         }
@@ -135,7 +137,23 @@ impl GPUBuffers {
         }
     }
 
-    ///create a mingled vertex buffer (meaning: multiple locations will be bound to this single buffer using offsets, as if the data of this buffer was a vector of structs)
+    ///bind a location to a buffer owned by someone else (not deleted with this vao), read once per instance, integer kinds stay integers in the shader
+    pub fn new_external_instanced_vbo(&self, vbo: GLuint, index: usize, kind: VertexType, stride: usize, offset: usize) {
+        self.bind();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let VertexTypeLayout { count, kind, .. } = kind.layout();
+            if kind == gl::INT {
+                gl::VertexAttribIPointer(index as GLuint, count, kind, stride as GLsizei, offset as *const _);
+            } else {
+                gl::VertexAttribPointer(index as GLuint, count, kind, gl::FALSE, stride as GLsizei, offset as *const _);
+            }
+            gl::EnableVertexAttribArray(index as GLuint);
+            gl::VertexAttribDivisor(index as GLuint, 1);
+        }
+    }
+
+    ///create a mingled vertex buffer (meaning: multiple locations will be bound to this single buffer using offsets, as if the data of this buffer was a vector of structs), integer kinds stay integers in the shader
     ///ex: we want to use a single buffer to send position (vec3) and uv (vec2), the mingle size will be 20 (5 floats)
     ///(position): new_mingled_vbo(0, 0, Vec3, 20, 0);
     ///(uv): new_mingled_vbo(0, 1, Vec2, 20, 12); (at index 1, we use the same buffer as index 0, and since we already pushed vec3, the offset will be 12 or 3 floats)
@@ -146,19 +164,23 @@ impl GPUBuffers {
             }
             unsafe {
                 let VertexTypeLayout { count, kind, .. } = kind.layout();
-                gl::VertexAttribPointer(index as GLuint, count, kind, gl::FALSE, mingle_size as GLsizei, offset as *const _);
+                if kind == gl::INT {
+                    gl::VertexAttribIPointer(index as GLuint, count, kind, mingle_size as GLsizei, offset as *const _);
+                } else {
+                    gl::VertexAttribPointer(index as GLuint, count, kind, gl::FALSE, mingle_size as GLsizei, offset as *const _);
+                }
                 gl::EnableVertexAttribArray(index as GLuint);
             }
         }
     }
 
-    ///set the contend of a vbo by copying a vec as raw data to the gpu
-    pub fn set_vbo<T>(&self, index: usize, data: Vec<T>) {
+    ///set the contend of a vbo by copying a slice as raw data to the gpu
+    pub fn set_vbo<T>(&self, index: usize, data: &[T]) {
         if !self.vbos.contains_key(&index) { return; }
         self.bind();
         unsafe {
             gl::BindBuffer(gl::ARRAY_BUFFER, *self.vbos.get(&index).unwrap());
-            gl::BufferData(gl::ARRAY_BUFFER, (size_of::<T>() * data.len()) as GLsizeiptr, data.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            gl::BufferData(gl::ARRAY_BUFFER, size_of_val(data) as GLsizeiptr, data.as_ptr() as *const c_void, gl::STATIC_DRAW);
         }
     }
     
@@ -179,7 +201,7 @@ impl GPUBuffers {
     }
     
     ///set an ebo for this vao, meaning the vbos will be read in the order of the given indices instead of all sequentially (useful to reuse multiple vertices)
-    pub fn set_ebo(&mut self, indices: Vec<u32>) {
+    pub fn set_ebo(&mut self, indices: &[u32]) {
         if self.ebo == 0 {
             self.bind();
             unsafe {
@@ -191,7 +213,7 @@ impl GPUBuffers {
         }
         unsafe {
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
-            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (size_of::<u32>() * indices.len()) as GLsizeiptr, indices.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, size_of_val(indices) as GLsizeiptr, indices.as_ptr() as *const c_void, gl::STATIC_DRAW);
         }
     }
     
@@ -232,4 +254,24 @@ impl GPUBuffers {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for GPUBuffers {
+    fn drop(&mut self) {
+        let mut vbos = self.vbos.values().copied().collect::<Vec<_>>();
+        vbos.sort_unstable();
+        vbos.dedup(); //mingled vbos share the same buffer
+        unsafe {
+            if !vbos.is_empty() {
+                gl::DeleteBuffers(vbos.len() as GLsizei, vbos.as_ptr());
+            }
+            if self.ebo != 0 {
+                gl::DeleteBuffers(1, &self.ebo);
+            }
+            if self.vao != 0 {
+                gl::DeleteVertexArrays(1, &self.vao);
+                safe_calls::forget_vao(self.vao);
+            }
+        }
+    }
+}
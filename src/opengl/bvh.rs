@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use crate::maths::vector::Vec3;
+use crate::opengl::frustrum::{Containment, Frustrum};
+use crate::opengl::volume::Aabb;
+
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    aabb: Aabb, //leaves store the enlarged (loose) box, so small moves do not touch the tree
+    parent: usize,
+    children: [usize; 2], //NONE for leaves
+    id: usize //leaves only
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool { self.children[0] == NONE }
+}
+
+//dynamic aabb tree over ids (instances), leaves are inserted where they increase the surface of the tree the least
+//https://box2d.org/files/ErinCatto_DynamicBVH_Full.pdf
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    root: usize,
+    leaves: HashMap<usize, usize>, //id -> node
+    margin: f32 //fraction of the size of a box added on each side of the leaves
+}
+
+impl Default for Bvh {
+    fn default() -> Self {
+        Self::new(0.1)
+    }
+}
+
+impl Bvh {
+    pub fn new(margin: f32) -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NONE,
+            leaves: HashMap::new(),
+            margin
+        }
+    }
+
+    pub fn len(&self) -> usize { self.leaves.len() }
+
+    pub fn contains(&self, id: usize) -> bool { self.leaves.contains_key(&id) }
+
+    ///loose box of every leaf
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.get(self.root).map(|node| node.aabb)
+    }
+
+    fn allocate(&mut self, node: Node) -> usize {
+        if let Some(i) = self.free.pop() {
+            self.nodes[i] = node;
+            i
+        } else {
+            self.nodes.push(node);
+            self.nodes.len() - 1
+        }
+    }
+
+    fn loose(&self, aabb: &Aabb) -> Aabb {
+        let e = aabb.half_extents();
+        aabb.expanded(e[0].max(e[1]).max(e[2]) * 2. * self.margin)
+    }
+
+    ///insert or move an id, returns true if the tree had to be modified (the new box left the loose box of the leaf)
+    pub fn update(&mut self, id: usize, aabb: &Aabb) -> bool {
+        if let Some(&leaf) = self.leaves.get(&id) {
+            if self.nodes[leaf].aabb.contains(aabb) {
+                return false;
+            }
+            self.remove(id);
+        }
+        let leaf = self.allocate(Node {
+            aabb: self.loose(aabb),
+            parent: NONE,
+            children: [NONE; 2],
+            id
+        });
+        self.leaves.insert(id, leaf);
+        self.insert_leaf(leaf);
+        true
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let leaf = match self.leaves.remove(&id) {
+            Some(leaf) => leaf,
+            None => return false,
+        };
+        self.free.push(leaf);
+        let parent = self.nodes[leaf].parent;
+        if parent == NONE {
+            self.root = NONE;
+            return true;
+        }
+        //the sibling takes the place of the parent
+        let sibling = self.nodes[parent].children[if self.nodes[parent].children[0] == leaf { 1 } else { 0 }];
+        let grand = self.nodes[parent].parent;
+        self.nodes[sibling].parent = grand;
+        self.free.push(parent);
+        if grand == NONE {
+            self.root = sibling;
+        } else {
+            let slot = if self.nodes[grand].children[0] == parent { 0 } else { 1 };
+            self.nodes[grand].children[slot] = sibling;
+            self.refit(grand);
+        }
+        true
+    }
+
+    fn insert_leaf(&mut self, leaf: usize) {
+        if self.root == NONE {
+            self.root = leaf;
+            return;
+        }
+        //descend towards the child whose cost (increase of surface) is the lowest, stop when creating a new parent here is cheaper
+        let aabb = self.nodes[leaf].aabb;
+        let mut index = self.root;
+        while !self.nodes[index].is_leaf() {
+            let node = &self.nodes[index];
+            let area = node.aabb.surface_area();
+            let combined = node.aabb.union(&aabb).surface_area();
+            let cost = 2. * combined;
+            let inheritance = 2. * (combined - area);
+            let child_cost = |child: &Node| {
+                let union = child.aabb.union(&aabb).surface_area();
+                if child.is_leaf() {
+                    union + inheritance
+                } else {
+                    union - child.aabb.surface_area() + inheritance
+                }
+            };
+            let (c0, c1) = (child_cost(&self.nodes[node.children[0]]), child_cost(&self.nodes[node.children[1]]));
+            if cost < c0 && cost < c1 {
+                break;
+            }
+            index = if c0 <= c1 { node.children[0] } else { node.children[1] };
+        }
+        let sibling = index;
+        let old_parent = self.nodes[sibling].parent;
+        let parent = self.allocate(Node {
+            aabb: self.nodes[sibling].aabb.union(&aabb),
+            parent: old_parent,
+            children: [sibling, leaf],
+            id: NONE
+        });
+        self.nodes[sibling].parent = parent;
+        self.nodes[leaf].parent = parent;
+        if old_parent == NONE {
+            self.root = parent;
+        } else {
+            let slot = if self.nodes[old_parent].children[0] == sibling { 0 } else { 1 };
+            self.nodes[old_parent].children[slot] = parent;
+            self.refit(old_parent);
+        }
+    }
+
+    ///recompute the boxes from a node up to the root
+    fn refit(&mut self, mut index: usize) {
+        while index != NONE {
+            let [a, b] = self.nodes[index].children;
+            self.nodes[index].aabb = self.nodes[a].aabb.union(&self.nodes[b].aabb);
+            index = self.nodes[index].parent;
+        }
+    }
+
+    fn query<T: FnMut(&Aabb) -> Containment, F: FnMut(usize, bool)>(&self, mut test: T, mut found: F) {
+        if self.root == NONE {
+            return;
+        }
+        let mut stack = vec![(self.root, false)];
+        while let Some((index, inside)) = stack.pop() {
+            let node = &self.nodes[index];
+            let inside = inside || match test(&node.aabb) {
+                Containment::Outside => continue,
+                Containment::Inside => true,
+                Containment::Intersecting => false,
+            };
+            if node.is_leaf() {
+                found(node.id, inside);
+            } else {
+                stack.push((node.children[0], inside));
+                stack.push((node.children[1], inside));
+            }
+        }
+    }
+
+    ///ids whose loose box intersects the frustum, the flag is true when the box is fully inside (no finer test needed)
+    pub fn query_frustum<F: FnMut(usize, bool)>(&self, frustrum: &Frustrum, found: F) {
+        self.query(|aabb| frustrum.test_aabb(aabb), found);
+    }
+
+    ///ids whose loose box intersects the box
+    pub fn query_box<F: FnMut(usize)>(&self, aabb: &Aabb, mut found: F) {
+        self.query(|node| if aabb.intersects(node) { Containment::Intersecting } else { Containment::Outside }, |id, _| found(id));
+    }
+
+    ///ids whose loose box intersects the sphere
+    pub fn query_sphere<F: FnMut(usize)>(&self, center: &Vec3, radius: f32, mut found: F) {
+        self.query(|node| if node.distance_squared(center) <= radius * radius { Containment::Intersecting } else { Containment::Outside }, |id, _| found(id));
+    }
+
+    ///ids whose loose box is crossed by the ray before max_distance, with the distance of entry in the box (sorted by the caller if needed)
+    pub fn query_ray<F: FnMut(usize, f32)>(&self, origin: &Vec3, direction: &Vec3, max_distance: f32, mut found: F) {
+        if self.root == NONE {
+            return;
+        }
+        let mut stack = vec![self.root];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match node.aabb.ray_distance(origin, direction) {
+                Some(d) if d <= max_distance => {
+                    if node.is_leaf() {
+                        found(node.id, d);
+                    } else {
+                        stack.extend(node.children);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use crate::maths::matrix::Mat4;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::bvh::{Bvh, Node, NONE};
+    use crate::opengl::frustrum::{Containment, Frustrum};
+    use crate::opengl::volume::Aabb;
+
+    fn boxes(count: usize, seed: u64) -> Vec<Aabb> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        (0..count).map(|_| {
+            let min = Vec3::new(next() * 200. - 100., next() * 200. - 100., next() * 200. - 100.);
+            Aabb { min, max: min + Vec3::new(next() * 5., next() * 5., next() * 5.) }
+        }).collect()
+    }
+
+    //every node contains its children and the parent links are consistent
+    fn check(bvh: &Bvh) {
+        let mut leaves = 0;
+        let mut stack = vec![bvh.root];
+        while let Some(i) = stack.pop() {
+            if i == NONE {
+                continue;
+            }
+            let node: &Node = &bvh.nodes[i];
+            if node.is_leaf() {
+                leaves += 1;
+                assert_eq!(bvh.leaves[&node.id], i);
+            } else {
+                for c in node.children {
+                    assert_eq!(bvh.nodes[c].parent, i);
+                    assert!(node.aabb.contains(&bvh.nodes[c].aabb));
+                    stack.push(c);
+                }
+            }
+        }
+        assert_eq!(leaves, bvh.len());
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let mut current = boxes(500, 1);
+        let mut bvh = Bvh::new(0.1);
+        for (id, aabb) in current.iter().enumerate() {
+            bvh.update(id, aabb);
+        }
+        check(&bvh);
+        //move some boxes a little (inside their loose box) and some a lot, remove a few
+        for (id, aabb) in current.iter_mut().enumerate() {
+            let offset = if id % 3 == 0 { Vec3::X * 0.1 } else if id % 3 == 1 { Vec3::Y * 50. } else { Vec3::default() };
+            *aabb = Aabb { min: aabb.min + offset, max: aabb.max + offset };
+            bvh.update(id, aabb);
+        }
+        let removed = (0..500).filter(|i| i % 7 == 0).collect::<HashSet<_>>();
+        removed.iter().for_each(|i| assert!(bvh.remove(*i)));
+        check(&bvh);
+        let alive = || current.iter().enumerate().filter(|(id, _)| !removed.contains(id));
+
+        //the queries are conservative (loose boxes): every real hit must be found, and every result must be near the query
+        let query = Aabb { min: Vec3::splat(-20.), max: Vec3::splat(30.) };
+        let mut found = HashSet::new();
+        bvh.query_box(&query, |id| { found.insert(id); });
+        for (id, aabb) in alive() {
+            if query.intersects(aabb) {
+                assert!(found.contains(&id));
+            }
+        }
+        assert!(found.iter().all(|id| query.expanded(2.).intersects(&current[*id])));
+
+        let center = Vec3::new(10., -5., 3.);
+        let mut found = HashSet::new();
+        bvh.query_sphere(&center, 40., |id| { found.insert(id); });
+        for (id, aabb) in alive() {
+            if aabb.distance_squared(&center) <= 1600. {
+                assert!(found.contains(&id), "{id}");
+            }
+        }
+
+        let (origin, direction) = (Vec3::new(-150., 1., 2.), Vec3::new(1., 0.05, -0.02));
+        let mut found = HashSet::new();
+        bvh.query_ray(&origin, &direction, f32::INFINITY, |id, _| { found.insert(id); });
+        for (id, aabb) in alive() {
+            if aabb.ray_distance(&origin, &direction).is_some() {
+                assert!(found.contains(&id));
+            }
+        }
+
+        let vp = Mat4::projection(60f32.to_radians(), 1., 1., 100.) * Transform::from_look_at(Vec3::Z * 120., Vec3::default()).as_view_matrix();
+        let frustrum = Frustrum::from_vp(&vp);
+        let mut found = HashSet::new();
+        bvh.query_frustum(&frustrum, |id, inside| {
+            found.insert(id);
+            if inside {
+                assert_eq!(frustrum.test_aabb(&current[id]), Containment::Inside);
+            }
+        });
+        for (id, aabb) in alive() {
+            if frustrum.test_aabb(aabb) != Containment::Outside {
+                assert!(found.contains(&id));
+            }
+        }
+        assert!(found.len() < alive().count());
+    }
+}
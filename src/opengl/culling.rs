@@ -0,0 +1,448 @@
+use std::mem::{offset_of, size_of, size_of_val};
+use std::ops::Range;
+use std::os::raw::c_void;
+use gl::types::{GLsizeiptr, GLsync, GLuint};
+use crate::maths::vector::Vec3;
+use crate::opengl::buffers::{GPUBuffers, VertexType};
+use crate::opengl::enums::Shaders;
+use crate::opengl::frustrum::Frustrum;
+use crate::opengl::indirect::DrawElementsIndirectCommand;
+use crate::opengl::instances::{InstanceBuffer, InstanceData};
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+
+pub const MAX_LOD_SIZES: usize = 64; //size of the lod threshold table shared by all the models
+const GROUP_SIZE: usize = 64; //local size of culling.comp
+
+//an instance as read by the culling pass (compute: 32 words of a storage buffer, feedback: vertex attributes)
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CullInstance {
+    pub sphere: [f32; 4], //world bounding sphere: center, radius
+    pub origin: [f32; 4], //position and scaled radius used to select the lod (same as the cpu path)
+    pub data: InstanceData,
+    pub command: u32, //command of the lod 0 of the model, filled by the upload
+    pub lods: [u32; 2], //first threshold in the lod table and amount of thresholds, filled by the upload
+    padding: [u32; 2]
+}
+
+impl CullInstance {
+    pub fn new(sphere: [f32; 4], origin: [f32; 4], data: InstanceData) -> Self {
+        Self { sphere, origin, data, ..Default::default() }
+    }
+}
+
+//instances of a model to cull, with the commands drawing each of its lod levels
+#[derive(Debug)]
+pub struct CullModel<'a> {
+    pub batch: usize, //batch of the shared geometry, the commands of a batch are kept together
+    pub levels: Vec<DrawElementsIndirectCommand>, //instance_count and base_instance are filled by the culling
+    pub lod_sizes: &'a [f32],
+    pub instances: Vec<CullInstance>
+}
+
+//a lod level of a model culled by the feedback path: the whole model is tested, only the instances at this level are captured
+#[derive(Debug, Clone)]
+struct FeedbackPass {
+    source: Range<usize>,
+    lod: i32
+}
+
+#[derive(Debug)]
+struct CullUniforms {
+    planes: Uniform,
+    eye: Uniform,
+    lod_scale: Uniform,
+    lod_sizes: Uniform
+}
+
+impl CullUniforms {
+    fn new(program: &ShaderProgram) -> Self {
+        Self {
+            planes: program.uniform("planes"),
+            eye: program.uniform("eye"),
+            lod_scale: program.uniform("lod_scale"),
+            lod_sizes: program.uniform("lod_sizes")
+        }
+    }
+
+    fn set(&self, frustrum: &Frustrum, eye: &Vec3, lod_scale: f32, lod_sizes: &[f32]) {
+        self.planes.array_vec4(frustrum.planes());
+        self.eye.vec3(*eye);
+        self.lod_scale.float(lod_scale);
+        self.lod_sizes.array_float(lod_sizes);
+    }
+}
+
+//frustum culling and lod selection of every instance on the gpu, writes the kept instances compacted per command (model and lod level)
+//compute shader on gl 4.3, transform feedback (one pass per command, counts read back by queries) on gl 3.3
+#[derive(Debug)]
+pub struct GpuCulling {
+    compute: Option<(ShaderProgram, CullUniforms, Uniform)>, //program, shared uniforms, count
+    feedback: (ShaderProgram, CullUniforms, Uniform), //program, shared uniforms, target_lod
+    use_compute: bool,
+    source: GPUBuffers, //vbo 0: the CullInstances, read as attributes by the feedback path
+    source_len: usize,
+    output: InstanceBuffer,
+    templates: Vec<DrawElementsIndirectCommand>, //commands with no instance, base_instance pointing to their region of the output
+    batches: Vec<(usize, Range<usize>)>, //commands of each batch of the shared geometry
+    commands: Vec<DrawElementsIndirectCommand>, //feedback path: filled by the queries
+    command_buffer: GLuint,
+    readback: GLuint, //compute path: copy of the commands read once the gpu is done with them
+    readback_len: usize,
+    readback_fence: Option<GLsync>,
+    counters: Vec<DrawElementsIndirectCommand>, //compute path: commands of a previous frame, for the statistics
+    passes: Vec<FeedbackPass>, //feedback path: one per command
+    queries: Vec<GLuint>,
+    lod_sizes: Vec<f32>,
+    generation: usize
+}
+
+impl GpuCulling {
+    pub fn new() -> Self {
+        let version = (safe_calls::get_int(gl::MAJOR_VERSION), safe_calls::get_int(gl::MINOR_VERSION));
+        let compute = if version >= (4, 3) {
+            ShaderProgramBuilder::default()
+                .add_shader(Shaders::Compute, include_str!("culling.comp"))
+                .build()
+                .map(|program| {
+                    let uniforms = CullUniforms::new(&program);
+                    let count = program.uniform("count");
+                    (program, uniforms, count)
+                })
+        } else {
+            None
+        };
+        let program = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("culling.vert"))
+            .add_shader(Shaders::Geometry, include_str!("culling.geom"))
+            .feedback_varyings(&["out_mat", "out_flags", "out_fade", "out_id"])
+            .build().unwrap();
+        let uniforms = CullUniforms::new(&program);
+        let target_lod = program.uniform("target_lod");
+        let mut source = GPUBuffers::new().unwrap();
+        let stride = size_of::<CullInstance>();
+        let data = offset_of!(CullInstance, data);
+        source.new_mingled_vbo(0, 0, VertexType::Vec4, stride, offset_of!(CullInstance, sphere));
+        source.new_mingled_vbo(0, 1, VertexType::Vec4, stride, offset_of!(CullInstance, origin));
+        for column in 0..4 {
+            source.new_mingled_vbo(0, 2 + column, VertexType::Vec4, stride, data + offset_of!(InstanceData, mat) + column * size_of::<[f32; 4]>());
+        }
+        source.new_mingled_vbo(0, 6, VertexType::Int, stride, data + offset_of!(InstanceData, flags));
+        source.new_mingled_vbo(0, 7, VertexType::Float, stride, data + offset_of!(InstanceData, fade));
+        source.new_mingled_vbo(0, 8, VertexType::Int, stride, data + offset_of!(InstanceData, id));
+        source.new_mingled_vbo(0, 9, VertexType::Int, stride, offset_of!(CullInstance, command));
+        source.new_mingled_vbo(0, 10, VertexType::Int, stride, offset_of!(CullInstance, lods));
+        source.new_mingled_vbo(0, 11, VertexType::Int, stride, offset_of!(CullInstance, lods) + size_of::<u32>());
+        let (mut command_buffer, mut readback) = (0, 0);
+        unsafe {
+            gl::GenBuffers(1, &mut command_buffer);
+            gl::GenBuffers(1, &mut readback);
+        }
+        Self {
+            use_compute: compute.is_some(),
+            compute,
+            feedback: (program, uniforms, target_lod),
+            source,
+            source_len: 0,
+            output: InstanceBuffer::new(),
+            templates: Vec::new(),
+            batches: Vec::new(),
+            commands: Vec::new(),
+            command_buffer,
+            readback,
+            readback_len: 0,
+            readback_fence: None,
+            counters: Vec::new(),
+            passes: Vec::new(),
+            queries: Vec::new(),
+            lod_sizes: Vec::new(),
+            generation: 0
+        }
+    }
+
+    ///false if the transform feedback path is used
+    pub fn compute(&self) -> bool { self.use_compute }
+
+    ///force the transform feedback path (ex: to compare both paths)
+    pub fn set_compute(&mut self, enabled: bool) {
+        self.use_compute = enabled && self.compute.is_some();
+    }
+
+    ///generation of the shared geometry the commands were built from
+    pub fn generation(&self) -> usize { self.generation }
+
+    pub fn output(&self) -> &InstanceBuffer { &self.output }
+
+    pub fn command_buffer(&self) -> GLuint { self.command_buffer }
+
+    ///commands as uploaded, before the culling fills their instance counts (the compute path draws all of them)
+    pub fn templates(&self) -> &[DrawElementsIndirectCommand] { &self.templates }
+
+    ///range of the commands of each batch of the shared geometry (in the order of the batches)
+    pub fn batches(&self) -> &[(usize, Range<usize>)] { &self.batches }
+
+    ///replace the instances to cull, each model gets a region of the output per lod level as large as its amount of instances
+    pub fn upload(&mut self, mut models: Vec<CullModel>, generation: usize) {
+        self.generation = generation;
+        models.sort_by_key(|model| model.batch);
+        self.templates.clear();
+        self.batches.clear();
+        self.passes.clear();
+        self.lod_sizes.clear();
+        let mut source = Vec::new();
+        let mut output = 0;
+        for CullModel { batch, levels, lod_sizes, mut instances } in models {
+            if levels.is_empty() || instances.is_empty() {
+                continue;
+            }
+            //models past the end of the table never switch lod
+            let sizes = if self.lod_sizes.len() + lod_sizes.len() <= MAX_LOD_SIZES { &lod_sizes[..lod_sizes.len().min(levels.len() - 1)] } else { &[] };
+            let lods = [self.lod_sizes.len() as u32, sizes.len() as u32];
+            self.lod_sizes.extend_from_slice(sizes);
+            let command = self.templates.len() as u32;
+            match self.batches.last_mut() {
+                Some((last, commands)) if *last == batch => commands.end += levels.len().min(sizes.len() + 1),
+                _ => self.batches.push((batch, self.templates.len()..self.templates.len() + levels.len().min(sizes.len() + 1)))
+            }
+            let range = source.len()..source.len() + instances.len();
+            for (lod, level) in levels.iter().take(sizes.len() + 1).enumerate() {
+                self.templates.push(DrawElementsIndirectCommand {
+                    instance_count: 0,
+                    base_instance: (output + lod * range.len()) as u32,
+                    ..*level
+                });
+                self.passes.push(FeedbackPass { source: range.clone(), lod: lod as i32 });
+            }
+            for instance in &mut instances {
+                instance.command = command;
+                instance.lods = lods;
+            }
+            //the regions of the lods of a model follow each other, each large enough to keep all its instances
+            output += range.len() * (sizes.len() + 1);
+            source.extend(instances);
+        }
+        self.source_len = source.len();
+        self.source.set_vbo(0, &source);
+        self.output.reserve(output);
+        let missing = self.passes.len().saturating_sub(self.queries.len());
+        if missing > 0 {
+            let mut queries = vec![0; missing];
+            unsafe {
+                gl::GenQueries(missing as i32, queries.as_mut_ptr());
+            }
+            self.queries.extend(queries);
+        }
+    }
+
+    ///test every instance against a frustum, the commands and the output are ready to be drawn after this call
+    pub fn cull(&mut self, frustrum: &Frustrum, eye: &Vec3, lod_scale: f32) {
+        if self.templates.is_empty() {
+            self.commands.clear();
+            return;
+        }
+        unsafe {
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.command_buffer);
+            gl::BufferData(gl::DRAW_INDIRECT_BUFFER, size_of_val(self.templates.as_slice()) as GLsizeiptr, self.templates.as_ptr() as *const c_void, gl::DYNAMIC_COPY);
+            gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+        }
+        match &self.compute {
+            Some((program, uniforms, count)) if self.use_compute => {
+                program.set_active();
+                uniforms.set(frustrum, eye, lod_scale, &self.lod_sizes);
+                count.int(self.source_len as i32);
+                unsafe {
+                    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.source.vbo(0));
+                    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, self.output.vbo());
+                    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, self.command_buffer);
+                    gl::DispatchCompute(self.source_len.div_ceil(GROUP_SIZE) as GLuint, 1, 1);
+                    //the commands are read by the draws (or a read back), the instances as vertex attributes
+                    gl::MemoryBarrier(gl::COMMAND_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::BUFFER_UPDATE_BARRIER_BIT);
+                }
+                self.read_counters();
+            }
+            _ => {
+                let (program, uniforms, target_lod) = &self.feedback;
+                program.set_active();
+                uniforms.set(frustrum, eye, lod_scale, &self.lod_sizes);
+                self.source.bind();
+                let stride = size_of::<InstanceData>();
+                unsafe {
+                    gl::Enable(gl::RASTERIZER_DISCARD);
+                    for ((pass, template), query) in self.passes.iter().zip(&self.templates).zip(&self.queries) {
+                        target_lod.int(pass.lod);
+                        gl::BindBufferRange(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.output.vbo(), (template.base_instance as usize * stride) as isize, (pass.source.len() * stride) as GLsizeiptr);
+                        gl::BeginQuery(gl::TRANSFORM_FEEDBACK_PRIMITIVES_WRITTEN, *query);
+                        gl::BeginTransformFeedback(gl::POINTS);
+                        gl::DrawArrays(gl::POINTS, pass.source.start as i32, pass.source.len() as i32);
+                        gl::EndTransformFeedback();
+                        gl::EndQuery(gl::TRANSFORM_FEEDBACK_PRIMITIVES_WRITTEN);
+                    }
+                    gl::Disable(gl::RASTERIZER_DISCARD);
+                    gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, 0);
+                }
+                //the draws of gl 3.3 need the counts on the cpu (waits for the passes)
+                self.commands = self.templates.clone();
+                for (command, query) in self.commands.iter_mut().zip(&self.queries) {
+                    unsafe {
+                        gl::GetQueryObjectuiv(*query, gl::QUERY_RESULT, &mut command.instance_count);
+                    }
+                }
+            }
+        }
+    }
+
+    ///compute path: collect the copy of a previous frame if the gpu is done with it, then copy the commands of this frame if none is pending
+    fn read_counters(&mut self) {
+        if let Some(fence) = self.readback_fence {
+            let status = unsafe { gl::ClientWaitSync(fence, 0, 0) };
+            if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+                return;
+            }
+            let mut counters = vec![DrawElementsIndirectCommand::default(); self.readback_len];
+            unsafe {
+                gl::DeleteSync(fence);
+                gl::BindBuffer(gl::COPY_READ_BUFFER, self.readback);
+                gl::GetBufferSubData(gl::COPY_READ_BUFFER, 0, size_of_val(counters.as_slice()) as GLsizeiptr, counters.as_mut_ptr() as *mut c_void);
+                gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            }
+            self.counters = counters;
+            self.readback_fence = None;
+        }
+        self.readback_len = self.templates.len();
+        let size = (self.readback_len * size_of::<DrawElementsIndirectCommand>()) as GLsizeiptr;
+        unsafe {
+            gl::BindBuffer(gl::COPY_READ_BUFFER, self.command_buffer);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.readback);
+            gl::BufferData(gl::COPY_WRITE_BUFFER, size, std::ptr::null(), gl::STREAM_READ);
+            gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0, size);
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            self.readback_fence = Some(gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0));
+        }
+    }
+
+    ///instance counts for the statistics, never waits for the gpu: the compute path returns the last commands it could read back (a frame or more late)
+    pub fn counters(&self) -> &[DrawElementsIndirectCommand] {
+        if self.use_compute { &self.counters } else { &self.commands }
+    }
+
+    ///commands of the last culling (read back from the gpu on the compute path, waits for the culling to be done)
+    pub fn commands(&self) -> Vec<DrawElementsIndirectCommand> {
+        if !self.use_compute {
+            return self.commands.clone();
+        }
+        let mut out = vec![DrawElementsIndirectCommand::default(); self.templates.len()];
+        if !out.is_empty() {
+            unsafe {
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.command_buffer);
+                gl::GetBufferSubData(gl::DRAW_INDIRECT_BUFFER, 0, size_of_val(out.as_slice()) as GLsizeiptr, out.as_mut_ptr() as *mut c_void);
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+            }
+        }
+        out
+    }
+
+    ///ids of the instances kept by the last culling, sorted (reads back the output)
+    pub fn visible(&self) -> Vec<usize> {
+        let mut out = Vec::new();
+        for command in self.commands() {
+            let mut instances = vec![InstanceData::default(); command.instance_count as usize];
+            if instances.is_empty() {
+                continue;
+            }
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.output.vbo());
+                gl::GetBufferSubData(gl::ARRAY_BUFFER, (command.base_instance as usize * size_of::<InstanceData>()) as isize, size_of_val(instances.as_slice()) as GLsizeiptr, instances.as_mut_ptr() as *mut c_void);
+            }
+            out.extend(instances.iter().map(|i| i.id as usize));
+        }
+        out.sort_unstable();
+        out
+    }
+}
+
+impl Drop for GpuCulling {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.command_buffer);
+            gl::DeleteBuffers(1, &self.readback);
+            if let Some(fence) = self.readback_fence {
+                gl::DeleteSync(fence);
+            }
+            if !self.queries.is_empty() {
+                gl::DeleteQueries(self.queries.len() as i32, self.queries.as_ptr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::matrix::Mat4;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::culling::{CullInstance, CullModel, GpuCulling};
+    use crate::opengl::frustrum::{Containment, Frustrum};
+    use crate::opengl::indirect::DrawElementsIndirectCommand;
+    use crate::opengl::instances::InstanceData;
+    use crate::opengl::volume::Sphere;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn same_visible_set() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        let eye = Vec3::Z * 20.;
+        let vp = Mat4::projection(60f32.to_radians(), 1., 0.1, 50.) * Transform::from_look_at(eye, Vec3::default()).as_view_matrix();
+        let frustrum = Frustrum::from_vp(&vp);
+        let mut spheres = Vec::new();
+        for x in -15i32..15 {
+            for y in -15..15 {
+                let center = Vec3::new(x as f32 * 1.7, y as f32 * 1.3, ((x * y) % 7) as f32 * 3.1);
+                spheres.push(Sphere { center, radius: 0.2 + (x + y).rem_euclid(4) as f32 * 0.3 });
+            }
+        }
+        //cpu reference: the same sphere test and lod selection (a single threshold for the second model)
+        let expected = (0..spheres.len()).filter(|i| frustrum.test_sphere(&spheres[*i]) != Containment::Outside).collect::<Vec<_>>();
+        assert!(!expected.is_empty() && expected.len() < spheres.len());
+        let lod = |s: &Sphere| {
+            let d = s.center - eye;
+            let d = d.dot(&d).sqrt();
+            (d > s.radius && s.radius / d < 0.05) as usize
+        };
+        let mut levels = [0; 2];
+        for i in expected.iter().filter(|i| **i >= 400) {
+            levels[lod(&spheres[*i])] += 1;
+        }
+        assert!(levels[0] > 0 && levels[1] > 0);
+        let instances = |first: usize, last: usize| (first..last).map(|i| {
+            let s = [spheres[i].center[0], spheres[i].center[1], spheres[i].center[2], spheres[i].radius];
+            CullInstance::new(s, s, InstanceData { id: i as u32, ..Default::default() })
+        }).collect::<Vec<_>>();
+        let level = DrawElementsIndirectCommand { count: 3, ..Default::default() };
+        let mut culling = GpuCulling::new();
+        for compute in [true, false] {
+            culling.set_compute(compute);
+            if culling.compute() != compute {
+                eprintln!("no compute shaders, only the transform feedback path is tested");
+                continue;
+            }
+            culling.upload(vec![
+                CullModel { batch: 0, levels: vec![level], lod_sizes: &[], instances: instances(0, 400) },
+                CullModel { batch: 0, levels: vec![level, level], lod_sizes: &[0.05], instances: instances(400, spheres.len()) }
+            ], 0);
+            culling.cull(&frustrum, &eye, 1.);
+            assert_eq!(culling.visible(), expected);
+            let commands = culling.commands();
+            assert_eq!([commands[1].instance_count, commands[2].instance_count], levels.map(|l| l as u32));
+            assert_eq!(commands[2].base_instance, 400 + (spheres.len() - 400) as u32);
+            //the counters of the compute path are read back without waiting, by the next culling once the gpu is done
+            unsafe {
+                gl::Finish();
+            }
+            culling.cull(&frustrum, &eye, 1.);
+            assert_eq!(culling.counters(), commands.as_slice());
+        }
+    }
+}
@@ -0,0 +1,53 @@
+#version 330 core
+
+layout (location = 0) in vec4 sphere;
+layout (location = 1) in vec4 origin;
+layout (location = 2) in mat4 i_mat;
+layout (location = 6) in int i_flags;
+layout (location = 7) in float i_fade;
+layout (location = 8) in int i_id;
+layout (location = 10) in int lod_first;
+layout (location = 11) in int lod_levels;
+
+out Instance {
+    flat int keep;
+    mat4 mat;
+    flat int flags;
+    float fade;
+    flat uint id;
+} v;
+
+uniform vec4 planes[6];
+uniform vec3 eye;
+uniform float lod_scale;
+uniform float lod_sizes[64];
+uniform int target_lod;
+
+bool visible(vec4 sphere) {
+    for (int i = 0; i < 6; ++i) {
+        if (dot(planes[i].xyz, sphere.xyz) + planes[i].w + sphere.w <= 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+int lod(vec4 origin, int first, int levels) {
+    float d = distance(origin.xyz, eye);
+    float size = d <= origin.w ? 3.4e38 : origin.w * lod_scale / d;
+    int out_lod = 0;
+    for (int i = 0; i < levels; ++i) {
+        if (size < lod_sizes[first + i]) {
+            ++out_lod;
+        }
+    }
+    return out_lod;
+}
+
+void main() {
+    v.keep = int(visible(sphere) && lod(origin, lod_first, lod_levels) == target_lod);
+    v.mat = i_mat;
+    v.flags = i_flags;
+    v.fade = i_fade;
+    v.id = uint(i_id);
+}
@@ -0,0 +1,287 @@
+use gl::types::{GLint, GLuint};
+use crate::maths::matrix::Mat4;
+use crate::maths::transform::Transform;
+use crate::maths::vector::{Vec3, Vec4};
+use crate::opengl::buffers::{GPUBuffers, VertexType};
+use crate::opengl::enums::Shaders;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::indirect::DrawStats;
+use crate::opengl::lights::{LightKind, Lights, MAX_LIGHTS};
+use crate::opengl::main_shader::MainShader;
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+use crate::other::resource_manager::ResourceManager;
+
+pub const GBUFFER_LAYERS: usize = 5; //unlit color, emissive, diffuse, normal and specular with the view depth (the depth texture is read on the next unit)
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Renderer {
+    #[default]
+    Forward, //every light is shaded per fragment of the geometry
+    Deferred //the opaque geometry is written in a g-buffer, lit once per pixel and per light volume (transparent parts stay forward)
+}
+
+impl Renderer {
+    pub fn toggle(self) -> Self {
+        match self {
+            Self::Forward => Self::Deferred,
+            Self::Deferred => Self::Forward
+        }
+    }
+}
+
+///triangles (facing outwards) of a subdivided icosahedron circumscribing the unit sphere
+pub fn light_volume() -> Vec<Vec3> {
+    let t = (1. + 5f32.sqrt()) / 2.;
+    let vertices = [
+        [-1., t, 0.], [1., t, 0.], [-1., -t, 0.], [1., -t, 0.],
+        [0., -1., t], [0., 1., t], [0., -1., -t], [0., 1., -t],
+        [t, 0., -1.], [t, 0., 1.], [-t, 0., -1.], [-t, 0., 1.]
+    ].map(|[x, y, z]| Vec3::new(x, y, z).normalize());
+    let faces = [
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1]
+    ];
+    let mut triangles = Vec::new();
+    for [a, b, c] in faces.map(|f| f.map(|i| vertices[i])) {
+        let (ab, bc, ca) = (((a + b) * 0.5).normalize(), ((b + c) * 0.5).normalize(), ((c + a) * 0.5).normalize());
+        triangles.extend([[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]);
+    }
+    //the faces cut into the unit sphere, the volume is scaled until the closest plane touches it
+    let mut inradius = f32::MAX;
+    for triangle in &mut triangles {
+        let [a, b, c] = *triangle;
+        let normal = (b - a).cross_product(&(c - a)).normalize();
+        if normal.dot(&a) < 0. {
+            triangle.swap(1, 2);
+        }
+        inradius = inradius.min(normal.dot(&a).abs());
+    }
+    triangles.into_iter().flatten().map(|v| v / inradius).collect()
+}
+
+//deferred shading: the opaque geometry writes its materials in a g-buffer, a fullscreen pass adds the unlit color and the lights without falloff,
+//then the lights bounded by their falloff are added by drawing a sphere per light (variants of the default shader, same lighting as the forward path)
+#[derive(Debug)]
+pub struct DeferredRenderer {
+    geometry: MainShader, //writes the g-buffer
+    lighting: MainShader, //reads it
+    gbuffer: Framebuffer,
+    volume: GPUBuffers,
+    volume_len: usize,
+    inverse_projection_uniform: Uniform,
+    inverse_camera_uniform: Uniform,
+    origin_uniform: Uniform,
+    light_index_uniform: Uniform,
+    volume_uniform: Uniform,
+    target: GLuint, //framebuffer bound before begin
+    viewport: [GLint; 4]
+}
+
+impl DeferredRenderer {
+    ///variants of the default shader of the resources, None if they could not be built
+    pub fn new(resources: &mut ResourceManager) -> Option<Self> {
+        let geometry = ShaderProgram::from_resources_with(resources, "default", &["DEFERRED_GEOMETRY"])?;
+        let fragment = resources.load_text("default.frag").map(|(_, v)| v.clone())?;
+        let lighting = ShaderProgramBuilder::default()
+            .defines(&["DEFERRED_LIGHTING"])
+            .add_shader(Shaders::Vertex, include_str!("deferred.vert"))
+            .add_shader(Shaders::Fragment, &fragment)
+            .build()?;
+        let lighting = MainShader::new(lighting);
+        for (unit, name) in ["gbuffer_color", "gbuffer_emissive", "gbuffer_diffuse", "gbuffer_normal", "gbuffer_specular", "gbuffer_depth"].iter().enumerate() {
+            lighting.program.uniform(name).int(unit as i32);
+        }
+        let sphere = light_volume();
+        let mut volume = GPUBuffers::new()?;
+        volume.new_vbo(0, VertexType::Vec3);
+        volume.set_vbo(0, &sphere.iter().map(|v| [v[0], v[1], v[2]]).collect::<Vec<_>>());
+        let mut target = RenderTarget::default();
+        for _ in 1..GBUFFER_LAYERS {
+            target = target.color(gl::RGBA16F);
+        }
+        target = target.color(gl::RGBA32F); //specular and view depth
+        Some(Self {
+            geometry: MainShader::new(geometry),
+            inverse_projection_uniform: lighting.program.uniform("inverse_projection"),
+            inverse_camera_uniform: lighting.program.uniform("inverse_camera"),
+            origin_uniform: lighting.program.uniform("origin"),
+            light_index_uniform: lighting.program.uniform("light_index"),
+            volume_uniform: lighting.program.uniform("volume"),
+            lighting,
+            gbuffer: target.depth_texture(gl::DEPTH_COMPONENT32F).build((1, 1)),
+            volume,
+            volume_len: sphere.len(),
+            target: 0,
+            viewport: [0; 4]
+        })
+    }
+
+    ///program of the opaque geometry between begin and resolve
+    pub fn program(&self) -> &ShaderProgram { &self.geometry.program }
+
+    ///bind and clear the g-buffer resized to the viewport, following draws (with the program of the renderer active) write the opaque geometry
+    pub fn begin(&mut self, projection: Mat4, camera: &Transform, debug_cascades: bool, ambient_occlusion: bool) {
+        self.target = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        self.viewport = safe_calls::get_int_array::<4>(gl::VIEWPORT);
+        self.gbuffer.resize((self.viewport[2].max(1) as u32, self.viewport[3].max(1) as u32));
+        self.gbuffer.bind();
+        unsafe {
+            for layer in 0..GBUFFER_LAYERS {
+                gl::ClearBufferfv(gl::COLOR, layer as GLint, [0f32; 4].as_ptr());
+            }
+            gl::ClearBufferfv(gl::DEPTH, 0, &1f32);
+        }
+        let view = camera.as_view_matrix();
+        self.lighting.program.set_active();
+        self.lighting.projection.mat4(projection);
+        self.lighting.camera.mat4(view);
+        self.lighting.eye.vec3(camera.pos);
+        self.inverse_projection_uniform.mat4(projection.inverse().unwrap_or_else(Mat4::identity));
+        self.inverse_camera_uniform.mat4(view.inverse().unwrap_or_else(Mat4::identity));
+        self.origin_uniform.int2(self.viewport[0], self.viewport[1]);
+        self.geometry.program.set_active();
+        self.geometry.projection.mat4(projection);
+        self.geometry.camera.mat4(view);
+        self.geometry.debug_cascades.int(debug_cascades as i32);
+        self.geometry.ambient_occlusion.int(ambient_occlusion as i32);
+    }
+
+    ///light the g-buffer into the framebuffer bound before begin and write its depth (for the forward parts drawn after)
+    pub fn resolve(&self, lights: &Lights, stats: &mut DrawStats) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.target);
+            gl::Viewport(self.viewport[0], self.viewport[1], self.viewport[2], self.viewport[3]);
+        }
+        for layer in 0..GBUFFER_LAYERS {
+            self.gbuffer.bind_color(layer, layer);
+        }
+        self.gbuffer.bind_depth(GBUFFER_LAYERS);
+        self.lighting.program.set_active();
+        self.volume.bind();
+        //base pass: every pixel of the geometry, with its depth
+        self.light_index_uniform.int(-1);
+        self.volume_uniform.vec4(Vec4::default());
+        safe_calls::set_blend(false);
+        safe_calls::set_depth_test(true);
+        safe_calls::set_depth_write(true);
+        unsafe {
+            gl::DepthFunc(gl::ALWAYS);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DepthFunc(gl::LESS);
+        }
+        stats.draw_calls += 1;
+        //light volumes: the back faces cover the pixels in range of the light, even with the camera inside (and beyond the far plane with the depth clamp)
+        let culling = unsafe { gl::IsEnabled(gl::CULL_FACE) } == gl::TRUE;
+        safe_calls::set_depth_test(false);
+        safe_calls::set_blend(true);
+        safe_calls::set_blend_func(gl::ONE, gl::ONE, gl::ONE, gl::ONE);
+        safe_calls::set_cull_face(true);
+        unsafe {
+            gl::CullFace(gl::FRONT);
+            gl::Enable(gl::DEPTH_CLAMP);
+        }
+        for (index, (_, light)) in lights.iter().take(MAX_LIGHTS).enumerate() {
+            let position = match light.kind {
+                LightKind::Spot { position, .. } | LightKind::Point { position } if light.falloff > 0. => position,
+                _ => continue
+            };
+            self.light_index_uniform.int(index as i32);
+            self.volume_uniform.vec4(position.extend(light.falloff));
+            self.volume.draw(gl::TRIANGLES, 0, self.volume_len);
+            stats.draw_calls += 1;
+        }
+        unsafe {
+            gl::CullFace(gl::BACK);
+            gl::Disable(gl::DEPTH_CLAMP);
+        }
+        safe_calls::set_cull_face(culling);
+        safe_calls::set_blend(false);
+        safe_calls::set_depth_test(true);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::deferred::{light_volume, Renderer};
+    use crate::opengl::framebuffer::RenderTarget;
+    use crate::opengl::lights::Light;
+    use crate::opengl::safe_calls;
+    use crate::opengl::scene::{ObjectData, Scene};
+    use crate::opengl::shader::ShaderProgram;
+    use crate::other::resource_manager::ResourceManager;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn volume() {
+        let triangles = light_volume();
+        assert_eq!(triangles.len(), 80 * 3);
+        for triangle in triangles.chunks(3) {
+            //every face is outside of the unit sphere and faces away from its center
+            let normal = (triangle[1] - triangle[0]).cross_product(&(triangle[2] - triangle[0])).normalize();
+            assert!(normal.dot(&triangle[0]) >= 0.9999);
+        }
+    }
+
+    #[test]
+    fn same_image_as_forward() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        let mut resources = ResourceManager::default();
+        resources.register_hints(&["resources", "resources/objs", "resources/materials", "resources/textures", "resources/shaders"]);
+        let program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
+        let mut scene = Scene::new(program);
+        let (cube, _) = resources.load_multipart_model("cube").unwrap();
+        //a half transparent copy of the cube, drawn in the transparent pass by both renderers
+        let (lib, _) = resources.load_material_lib("cube_blender").unwrap();
+        resources.get_material_lib_mut(lib).unwrap().0.get_mut("Material").unwrap().transparency = 0.5;
+        let (glass, _) = resources.load_multipart_model("cube_blender").unwrap();
+        assert!(resources.get_multipart_model(glass).unwrap().has_transparency());
+        let mut floor = Transform::from_look_towards(Vec3::default(), -Vec3::Z);
+        floor.scale = Vec3::new(30., 30., 0.1);
+        scene.spawn_object(cube, ObjectData::from(floor));
+        let mut caster = Transform::from_look_towards(Vec3::Z * 4., -Vec3::Z);
+        caster.scale = Vec3::splat(3.);
+        scene.spawn_object(cube, ObjectData::from(caster));
+        let mut window = Transform::from_look_towards(Vec3::new(7., -5., 6.), -Vec3::Z);
+        window.scale = Vec3::splat(2.);
+        scene.spawn_object(glass, ObjectData::from(window));
+        scene.set_ambient_light(Vec3::splat(0.1));
+        //unbounded: directional and point without falloff, bounded: point and spot
+        scene.spawn_light(Light::directional(Vec3::new(-0.3, -0.2, -1.), Vec3::new(0.5, 0.5, 0.4)).with_shadow(Default::default()));
+        scene.spawn_light(Light::point(Vec3::new(0., -10., 3.), Vec3::new(0.1, 0.1, 0.6), 0.));
+        scene.spawn_light(Light::point(Vec3::new(-6., 5., 6.), Vec3::new(2., 0.5, 0.5), 14.));
+        scene.spawn_light(Light::spot(Vec3::new(8., -8., 10.), Vec3::new(0., 0.3, -1.), 1., Vec3::new(0.2, 2., 0.2), 30.));
+        scene.set_camera(Transform::from_look_at(Vec3::new(0., 0., 30.), Vec3::default()));
+        scene.set_projection(80., 1.);
+        let size = 48;
+        let target = RenderTarget::default().color(gl::RGBA8).depth(gl::DEPTH_COMPONENT24).build((size, size));
+        target.bind();
+        unsafe { gl::Viewport(0, 0, size as i32, size as i32); }
+        safe_calls::set_clear_color(0., 0.1, 0.05);
+        safe_calls::set_depth_test(true);
+        let mut render = |renderer| {
+            scene.set_renderer(&mut resources, renderer);
+            assert_eq!(scene.renderer(), renderer);
+            safe_calls::clear_screen();
+            scene.draw(&resources);
+            assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+            let mut pixels = vec![0u8; (size * size * 4) as usize];
+            unsafe { gl::ReadPixels(0, 0, size as i32, size as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr().cast()); }
+            pixels
+        };
+        let forward = render(Renderer::Forward);
+        let deferred = render(Renderer::Deferred);
+        //the scene is actually lit by the colored lights, not only the clear color
+        assert!(forward.chunks(4).filter(|p| p[0] > 40 || p[1] > 40).count() > (size * size / 4) as usize);
+        let worst = forward.chunks(4).zip(deferred.chunks(4))
+            .flat_map(|(f, d)| f[..3].iter().zip(&d[..3]).map(|(a, b)| a.abs_diff(*b)))
+            .max()
+            .unwrap();
+        assert!(worst <= 4, "deferred differs from forward by {worst}/255");
+    }
+}
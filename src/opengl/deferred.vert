@@ -0,0 +1,16 @@
+#version 330 core
+
+layout (location = 0) in vec3 v_pos; //sphere circumscribing the unit sphere
+
+uniform mat4 projection;
+uniform mat4 camera;
+uniform vec4 volume; //xyz: position of the light, w: falloff distance (0: fullscreen triangle of the base pass)
+
+void main() {
+    if (volume.w == 0.) {
+        vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+        gl_Position = vec4(p * 2. - 1., 0., 1.);
+    } else {
+        gl_Position = projection * camera * vec4(volume.xyz + v_pos * volume.w, 1.);
+    }
+}
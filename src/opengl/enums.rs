@@ -21,7 +21,8 @@ pub enum RenderMode {
 pub enum Shaders {
     Vertex = gl::VERTEX_SHADER as isize,
     Fragment = gl::FRAGMENT_SHADER as isize,
-    Geometry = gl::GEOMETRY_SHADER as isize
+    Geometry = gl::GEOMETRY_SHADER as isize,
+    Compute = gl::COMPUTE_SHADER as isize
 }
 
 impl Into<GLenum> for Side {
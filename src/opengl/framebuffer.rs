@@ -0,0 +1,350 @@
+use gl::types::{GLenum, GLint, GLsizei, GLuint};
+use crate::opengl::safe_calls;
+
+//storage of the depth attachment
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepthAttachment {
+    Renderbuffer(GLenum), //only tested and blitted
+    Texture(GLenum) //can also be sampled by later passes
+}
+
+//description of the attachments of a framebuffer, the storage is allocated by Framebuffer::new and resize
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderTarget {
+    colors: Vec<GLenum>, //internal formats of the color textures, attached to COLOR_ATTACHMENT0 + index (NONE: output location not written)
+    depth: Option<DepthAttachment>,
+    samples: u8 //0: single sampled, otherwise multisampled textures (resolved by a blit before being sampled)
+}
+
+impl RenderTarget {
+    pub fn color(mut self, format: GLenum) -> Self {
+        self.colors.push(format);
+        self
+    }
+
+    ///leave an output location of the shaders without attachment
+    pub fn skip_color(mut self) -> Self {
+        self.colors.push(gl::NONE);
+        self
+    }
+
+    pub fn depth(mut self, format: GLenum) -> Self {
+        self.depth = Some(DepthAttachment::Renderbuffer(format));
+        self
+    }
+
+    pub fn depth_texture(mut self, format: GLenum) -> Self {
+        self.depth = Some(DepthAttachment::Texture(format));
+        self
+    }
+
+    pub fn samples(mut self, samples: u8) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn colors(&self) -> &[GLenum] { &self.colors }
+
+    pub fn sample_count(&self) -> u8 { self.samples }
+
+    ///target of the textures (TEXTURE_2D or TEXTURE_2D_MULTISAMPLE)
+    pub fn texture_target(&self) -> GLenum {
+        if self.samples > 0 { gl::TEXTURE_2D_MULTISAMPLE } else { gl::TEXTURE_2D }
+    }
+
+    pub fn depth_attachment(&self) -> Option<DepthAttachment> { self.depth }
+
+    pub fn build(self, size: (u32, u32)) -> Framebuffer { Framebuffer::new(self, size) }
+}
+
+///format and type of the pixels of an internal format (for the allocation of the textures)
+pub fn pixel_format(internal: GLenum) -> (GLenum, GLenum) {
+    match internal {
+        gl::R8 => (gl::RED, gl::UNSIGNED_BYTE),
+        gl::RG8 => (gl::RG, gl::UNSIGNED_BYTE),
+        gl::RGB8 | gl::SRGB8 => (gl::RGB, gl::UNSIGNED_BYTE),
+        gl::R16F | gl::R32F => (gl::RED, gl::FLOAT),
+        gl::RG16F | gl::RG32F => (gl::RG, gl::FLOAT),
+        gl::RGB16F | gl::RGB32F | gl::R11F_G11F_B10F => (gl::RGB, gl::FLOAT),
+        gl::RGBA16F | gl::RGBA32F => (gl::RGBA, gl::FLOAT),
+        gl::R32UI => (gl::RED_INTEGER, gl::UNSIGNED_INT),
+        gl::R32I => (gl::RED_INTEGER, gl::INT),
+        gl::RGBA32UI => (gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+        gl::DEPTH_COMPONENT16 | gl::DEPTH_COMPONENT24 | gl::DEPTH_COMPONENT32 => (gl::DEPTH_COMPONENT, gl::UNSIGNED_INT),
+        gl::DEPTH_COMPONENT32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+        gl::DEPTH24_STENCIL8 => (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8),
+        gl::DEPTH32F_STENCIL8 => (gl::DEPTH_STENCIL, gl::FLOAT_32_UNSIGNED_INT_24_8_REV),
+        _ => (gl::RGBA, gl::UNSIGNED_BYTE)
+    }
+}
+
+fn has_stencil(format: GLenum) -> bool { matches!(format, gl::DEPTH24_STENCIL8 | gl::DEPTH32F_STENCIL8) }
+
+///output locations of the shaders mapped to the attachments
+fn draw_buffers(colors: &[GLenum]) -> Vec<GLenum> {
+    colors.iter().enumerate().map(|(i, f)| if *f == gl::NONE { gl::NONE } else { gl::COLOR_ATTACHMENT0 + i as GLenum }).collect()
+}
+
+//offscreen framebuffer owning its attachments, deleted with it
+#[derive(Debug)]
+pub struct Framebuffer {
+    id: GLuint,
+    target: RenderTarget,
+    colors: Vec<GLuint>, //textures
+    depth: GLuint, //texture or renderbuffer, 0 without depth
+    size: (u32, u32)
+}
+
+impl Framebuffer {
+    pub fn new(target: RenderTarget, size: (u32, u32)) -> Self {
+        let mut out = Self {
+            id: 0,
+            colors: vec![0; target.colors.len()],
+            depth: 0,
+            target,
+            size: (0, 0)
+        };
+        unsafe {
+            gl::GenFramebuffers(1, &mut out.id);
+            gl::GenTextures(out.colors.len() as GLsizei, out.colors.as_mut_ptr());
+            match out.target.depth {
+                Some(DepthAttachment::Renderbuffer(_)) => gl::GenRenderbuffers(1, &mut out.depth),
+                Some(DepthAttachment::Texture(_)) => gl::GenTextures(1, &mut out.depth),
+                None => {}
+            }
+        }
+        out.resize(size);
+        out
+    }
+
+    pub fn id(&self) -> GLuint { self.id }
+
+    pub fn size(&self) -> (u32, u32) { self.size }
+
+    pub fn target(&self) -> &RenderTarget { &self.target }
+
+    ///texture of a color attachment
+    pub fn color(&self, index: usize) -> GLuint { self.colors[index] }
+
+    ///texture or renderbuffer of the depth attachment (0 without depth)
+    pub fn depth(&self) -> GLuint { self.depth }
+
+    ///(re)allocate the attachments (bound to unit 0 in the process), nothing is done if the size did not change
+    pub fn resize(&mut self, size: (u32, u32)) -> bool {
+        if size == self.size {
+            return false;
+        }
+        self.size = size;
+        let (width, height) = (size.0.max(1) as GLsizei, size.1.max(1) as GLsizei);
+        let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+            let (target, samples) = (self.target.texture_target(), self.target.samples as GLsizei);
+            let allocate = |texture: GLuint, internal: GLenum, filter: GLenum| {
+                safe_calls::edit_texture(target, texture);
+                if samples > 0 {
+                    gl::TexImage2DMultisample(target, samples, internal, width, height, gl::TRUE);
+                    return;
+                }
+                let (format, kind) = pixel_format(internal);
+                gl::TexImage2D(target, 0, internal as GLint, width, height, 0, format, kind, std::ptr::null());
+                gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, filter as GLint);
+                gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, filter as GLint);
+                gl::TexParameteri(target, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+                gl::TexParameteri(target, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            };
+            for (i, (texture, internal)) in self.colors.iter().zip(&self.target.colors).enumerate().filter(|(_, (_, f))| **f != gl::NONE) {
+                //integer textures can not be filtered
+                let filter = if matches!(pixel_format(*internal).0, gl::RED_INTEGER | gl::RGBA_INTEGER) { gl::NEAREST } else { gl::LINEAR };
+                allocate(*texture, *internal, filter);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + i as GLenum, target, *texture, 0);
+            }
+            match self.target.depth {
+                Some(DepthAttachment::Renderbuffer(internal)) => {
+                    gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth);
+                    gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, internal, width, height);
+                    let attachment = if has_stencil(internal) { gl::DEPTH_STENCIL_ATTACHMENT } else { gl::DEPTH_ATTACHMENT };
+                    gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, self.depth);
+                }
+                Some(DepthAttachment::Texture(internal)) => {
+                    allocate(self.depth, internal, gl::NEAREST);
+                    let attachment = if has_stencil(internal) { gl::DEPTH_STENCIL_ATTACHMENT } else { gl::DEPTH_ATTACHMENT };
+                    gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, target, self.depth, 0);
+                }
+                None => {}
+            }
+            let buffers = draw_buffers(&self.target.colors);
+            if buffers.is_empty() {
+                gl::DrawBuffer(gl::NONE);
+                gl::ReadBuffer(gl::NONE);
+            } else {
+                gl::DrawBuffers(buffers.len() as GLsizei, buffers.as_ptr());
+            }
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+        }
+        true
+    }
+
+    ///draw into this framebuffer, the viewport is set to its size
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        }
+        safe_calls::resize(self.size.0, self.size.1);
+    }
+
+    pub fn complete(&self) -> bool {
+        let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+            status == gl::FRAMEBUFFER_COMPLETE
+        }
+    }
+
+    ///bind a color attachment to a texture unit
+    pub fn bind_color(&self, index: usize, unit: usize) {
+        safe_calls::bind_texture_target(unit, self.target.texture_target(), self.colors[index]);
+    }
+
+    ///bind the depth texture to a texture unit (not for renderbuffers)
+    pub fn bind_depth(&self, unit: usize) {
+        safe_calls::bind_texture_target(unit, self.target.texture_target(), self.depth);
+    }
+
+    ///resolve (or copy) every attachment into a framebuffer of the same size and formats, leaves the destination bound
+    pub fn resolve_into(&self, destination: &Framebuffer) {
+        let (width, height) = (self.size.0 as GLint, self.size.1 as GLint);
+        let colors = self.target.colors.len().min(destination.target.colors.len());
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, destination.id);
+            //a blit copies the read buffer to every draw buffer, one attachment at a time
+            for i in (0..colors).filter(|i| self.target.colors[*i] != gl::NONE && destination.target.colors[*i] != gl::NONE) {
+                gl::ReadBuffer(gl::COLOR_ATTACHMENT0 + i as GLenum);
+                gl::DrawBuffer(gl::COLOR_ATTACHMENT0 + i as GLenum);
+                gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+            }
+            if self.target.depth.is_some() && destination.target.depth.is_some() {
+                gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            }
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            let buffers = draw_buffers(&destination.target.colors);
+            gl::DrawBuffers(buffers.len() as GLsizei, buffers.as_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, destination.id);
+        }
+    }
+
+    ///copy the depth of another framebuffer of the same size and depth format, leaves this one bound
+    pub fn blit_depth_from(&self, source: GLuint) {
+        let (width, height) = (self.size.0 as GLint, self.size.1 as GLint);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.id);
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        }
+    }
+
+    ///depth format of the attachment of a framebuffer, a copy of its depth needs the same one (None: no depth)
+    pub fn depth_format(framebuffer: GLuint) -> Option<GLenum> {
+        let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let query = |attachment: GLenum, parameter: GLenum| {
+            let mut value = 0;
+            unsafe {
+                gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, attachment, parameter, &mut value);
+            }
+            value
+        };
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        }
+        let (depth, stencil) = if framebuffer == 0 { (gl::DEPTH, gl::STENCIL) } else { (gl::DEPTH_ATTACHMENT, gl::STENCIL_ATTACHMENT) };
+        let format = (query(depth, gl::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE) != gl::NONE as GLint).then(|| {
+            let stencil = query(stencil, gl::FRAMEBUFFER_ATTACHMENT_OBJECT_TYPE) != gl::NONE as GLint && query(stencil, gl::FRAMEBUFFER_ATTACHMENT_STENCIL_SIZE) > 0;
+            let float = query(depth, gl::FRAMEBUFFER_ATTACHMENT_COMPONENT_TYPE) == gl::FLOAT as GLint;
+            match (query(depth, gl::FRAMEBUFFER_ATTACHMENT_DEPTH_SIZE), stencil, float) {
+                (32, true, _) => gl::DEPTH32F_STENCIL8,
+                (32, false, true) => gl::DEPTH_COMPONENT32F,
+                (32, false, false) => gl::DEPTH_COMPONENT32,
+                (16, _, _) => gl::DEPTH_COMPONENT16,
+                (_, true, _) => gl::DEPTH24_STENCIL8,
+                _ => gl::DEPTH_COMPONENT24
+            }
+        });
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+        }
+        format
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+            gl::DeleteTextures(self.colors.len() as GLsizei, self.colors.as_ptr());
+            match self.target.depth {
+                Some(DepthAttachment::Renderbuffer(_)) => gl::DeleteRenderbuffers(1, &self.depth),
+                Some(DepthAttachment::Texture(_)) => gl::DeleteTextures(1, &self.depth),
+                None => {}
+            }
+        }
+        for texture in &self.colors {
+            safe_calls::forget_texture(*texture);
+        }
+        if matches!(self.target.depth, Some(DepthAttachment::Texture(_))) {
+            safe_calls::forget_texture(self.depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::framebuffer::{pixel_format, DepthAttachment, Framebuffer, RenderTarget};
+    use crate::opengl::safe_calls;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn attachments() {
+        let target = RenderTarget::default().color(gl::RGBA16F).skip_color().color(gl::R32UI).depth_texture(gl::DEPTH_COMPONENT24);
+        assert_eq!(target.colors(), &[gl::RGBA16F, gl::NONE, gl::R32UI]);
+        assert_eq!(target.depth_attachment(), Some(DepthAttachment::Texture(gl::DEPTH_COMPONENT24)));
+        assert_eq!(pixel_format(gl::R32UI), (gl::RED_INTEGER, gl::UNSIGNED_INT));
+        assert_eq!(pixel_format(gl::DEPTH24_STENCIL8), (gl::DEPTH_STENCIL, gl::UNSIGNED_INT_24_8));
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        let mut framebuffer = target.clone().build((8, 4));
+        assert!(framebuffer.complete());
+        let multisampled = target.samples(4).build((8, 4));
+        assert!(multisampled.complete());
+        multisampled.resolve_into(&framebuffer);
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+        assert!(!framebuffer.resize((8, 4)));
+        assert!(framebuffer.resize((3, 5)));
+        assert!(framebuffer.complete());
+        assert_eq!(framebuffer.size(), (3, 5));
+    }
+
+    #[test]
+    fn resize_bound_textures() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        safe_calls::invalidate_state();
+        let target = RenderTarget::default().color(gl::RGBA8);
+        let mut a = target.clone().build((8, 8));
+        let b = target.build((8, 8));
+        //a stays cached on unit 0 while unit 3 is the active one
+        a.bind_color(0, 0);
+        b.bind_color(0, 3);
+        assert!(a.resize((16, 16)));
+        let width = |framebuffer: &Framebuffer| {
+            let mut width = 0;
+            safe_calls::edit_texture(gl::TEXTURE_2D, framebuffer.color(0));
+            unsafe {
+                gl::GetTexLevelParameteriv(gl::TEXTURE_2D, 0, gl::TEXTURE_WIDTH, &mut width);
+            }
+            width
+        };
+        assert_eq!((width(&a), width(&b)), (16, 8));
+    }
+}
@@ -1,58 +1,185 @@
 use crate::maths::matrix::Mat4;
 use crate::maths::transform::Transform;
-use crate::maths::vector::Vec3;
+use crate::maths::vector::{Vec3, Vec4};
+use crate::opengl::volume::{Aabb, Sphere, Volume};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Containment {
+    Outside,
+    Intersecting,
+    Inside
+}
 
 //should be rebuilt when the mvp is changed (camera or light moved)
 #[derive(Debug)]
 pub struct Frustrum {
-    normals: [Vec3; 6] //order: left, right, bottom, top, near, far
-}
-
-//composite volume: sphere + aabb
-//aabb is tested only if the sphere is intersecting partially
-#[derive(Debug, Default)]
-pub struct Volume {
-    radius: f32,
-    aabb_min: Vec3,
-    aabb_max: Vec3
-}
-
-impl Volume {
-    pub fn expand(&mut self, vertex: &Vec3) {
-        let s = vertex.dot(vertex);
-        if self.radius * self.radius < s {
-            self.radius = s.sqrt();
-        }
-        self.aabb_min = self.aabb_min.min(*vertex);
-        self.aabb_max = self.aabb_max.max(*vertex);
-    }
+    planes: [Vec4; 6] //order: left, right, bottom, top, near, far, xyz: normal pointing inside, w: distance (normalized, so dot(xyz, p) + w is the signed distance)
 }
 
 impl Frustrum {
     pub fn from_vp(vp: &Mat4) -> Self {
-        let v = vp.row(3); //position
-        let mut out = Self { normals: [Vec3::default(); 6] };
+        let v = *vp.row(3); //position
+        let mut out = Self { planes: [Vec4::default(); 6] };
         for i in 0..3 {
-            let r = vp.row(i);
-            out.normals[i * 2] = (v + r).resize().normalize();
-            out.normals[i * 2 + 1] = (v - r).resize().normalize();
+            let r = *vp.row(i);
+            out.planes[i * 2] = Self::normalize_plane(v + r);
+            out.planes[i * 2 + 1] = Self::normalize_plane(v - r);
         }
         out
     }
-    
-    pub fn has_volume(&self, transform: &Transform, volume: &Volume) -> bool {
-        let mut sub = true;
-        for norm in &self.normals {
-            if norm.dot(&transform.pos) + volume.radius <= 0. {
-                return false;
+
+    fn normalize_plane(plane: Vec4) -> Vec4 {
+        let n: Vec3 = plane.resize();
+        let l = n.dot(&n).sqrt();
+        if l > 0. { plane / l } else { plane }
+    }
+
+    pub fn planes(&self) -> &[Vec4; 6] { &self.planes }
+
+    ///signed distance of a point to a plane (positive inside)
+    pub fn distance(&self, plane: usize, point: &Vec3) -> f32 {
+        let p = self.planes[plane];
+        p[0] * point[0] + p[1] * point[1] + p[2] * point[2] + p[3]
+    }
+
+    fn normal(&self, plane: usize) -> Vec3 {
+        self.planes[plane].resize()
+    }
+
+    ///world aabb against the 6 planes
+    pub fn test_aabb(&self, aabb: &Aabb) -> Containment {
+        let (center, extents) = (aabb.center(), aabb.half_extents());
+        let mut out = Containment::Inside;
+        for plane in 0..6 {
+            let n = self.normal(plane);
+            let d = self.distance(plane, &center);
+            let r = (0..3).map(|i| n[i].abs() * extents[i]).sum::<f32>();
+            if d + r <= 0. {
+                return Containment::Outside;
+            }
+            if d - r < 0. {
+                out = Containment::Intersecting;
             }
-            if !sub && norm.dot(&transform.pos) - volume.radius >= 0. {
-                sub = false;
+        }
+        out
+    }
+
+    ///world sphere against the 6 planes (reference of the gpu culling)
+    pub fn test_sphere(&self, sphere: &Sphere) -> Containment {
+        let mut out = Containment::Inside;
+        for plane in 0..6 {
+            let d = self.distance(plane, &sphere.center);
+            if d + sphere.radius <= 0. {
+                return Containment::Outside;
+            }
+            if d - sphere.radius < 0. {
+                out = Containment::Intersecting;
             }
         }
-        if sub {
-            //do aabb check
+        out
+    }
+
+    ///test from the cheapest to the tightest volume: world sphere, then world aabb, then obb (only when the previous one intersects a plane)
+    pub fn has_volume(&self, transform: &Transform, volume: &Volume) -> bool {
+        match self.test_sphere(&volume.sphere.transformed(transform)) {
+            Containment::Outside => return false,
+            Containment::Inside => return true,
+            Containment::Intersecting => {}
+        }
+        match self.test_aabb(&volume.aabb.transformed(transform)) {
+            Containment::Outside => return false,
+            Containment::Inside => return true,
+            Containment::Intersecting => {}
+        }
+        let obb = volume.obb.transformed(transform);
+        (0..6).all(|plane| {
+            let n = self.normal(plane);
+            self.distance(plane, &obb.center) + obb.half_axes.iter().map(|a| n.dot(a).abs()).sum::<f32>() > 0.
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::matrix::{Mat4, Matrix};
+    use crate::maths::quaternion::Quaternion;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::frustrum::Frustrum;
+    use crate::opengl::volume::Volume;
+
+    //camera at z = 10 looking at the origin, 90 degrees of fov, square screen, near 1, far 100
+    fn camera() -> Frustrum {
+        let vp = Mat4::projection(90f32.to_radians(), 1., 1., 100.) * Transform::from_look_at(Vec3::Z * 10., Vec3::default()).as_view_matrix();
+        Frustrum::from_vp(&vp)
+    }
+
+    fn cube(size: f32) -> Volume {
+        let mut points = Vec::new();
+        for i in 0..8 {
+            points.push([(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32].map(|c| (c - 0.5) * size));
+        }
+        Volume::from_points(&points)
+    }
+
+    #[test]
+    fn clip_space_planes() {
+        //with an identity vp the frustum is the ndc cube
+        let f = Frustrum::from_vp(&Matrix::identity());
+        let p = Vec3::new(0.5, -0.25, 0.);
+        let expected = [1.5, 0.5, 0.75, 1.25, 1., 1.];
+        for (plane, e) in expected.iter().enumerate() {
+            assert!((f.distance(plane, &p) - e).abs() < 1e-6, "plane {plane}: {} != {e}", f.distance(plane, &p));
+        }
+    }
+
+    #[test]
+    fn perspective_planes() {
+        let f = camera();
+        //near plane at z = 9 and far plane at z = -90 in world space
+        assert!((f.distance(4, &Vec3::default()) - 9.).abs() < 1e-3);
+        assert!((f.distance(5, &Vec3::default()) - 90.).abs() < 1e-2);
+        //90 degrees of fov: the side planes go through the camera at 45 degrees
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        for plane in 0..4 {
+            assert!((f.distance(plane, &Vec3::default()) - 10. * s).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn culling() {
+        let f = camera();
+        let unit = cube(1.);
+        assert!(f.has_volume(&Transform::default(), &unit));
+        assert!(!f.has_volume(&Transform::from_pos(Vec3::Z * 20.), &unit)); //behind the camera
+        assert!(!f.has_volume(&Transform::from_pos(Vec3::Z * -200.), &unit)); //after the far plane
+        assert!(!f.has_volume(&Transform::from_pos(Vec3::X * 30.), &unit)); //right of the screen
+        assert!(f.has_volume(&Transform::from_pos(Vec3::X * 10.), &unit)); //the right plane crosses the cube
+        //scale is applied: a big cube next to the frustum reaches inside
+        assert!(f.has_volume(&(Transform::from_pos(Vec3::X * 30.) * 40.), &unit));
+    }
+
+    #[test]
+    fn boxes_refine_the_sphere() {
+        let f = camera();
+        //thin plank: its bounding sphere crosses the right plane, the plank itself does not
+        let mut points = Vec::new();
+        for i in 0..8 {
+            points.push([(i & 1) as f32 * 0.1, ((i >> 1) & 1) as f32 * 10. - 5., ((i >> 2) & 1) as f32 * 0.1]);
+        }
+        let plank = Volume::from_points(&points);
+        let pos = Vec3::X * 14.;
+        assert!(!f.has_volume(&Transform::from_pos(pos), &plank));
+        //rotated 90 degrees around z, it lies along x and enters the screen
+        let rotated = Transform::from_pos(pos) * Quaternion::from((Vec3::Z, 90f32.to_radians()));
+        assert!(f.has_volume(&rotated, &plank));
+        //rotated 45 degrees around y the aabb crosses the plane, but the obb stays outside
+        let mut points = Vec::new();
+        for i in 0..8 {
+            points.push([(i & 1) as f32 * 6. - 3., ((i >> 1) & 1) as f32 * 0.2 - 0.1, ((i >> 2) & 1) as f32 * 0.2 - 0.1]);
         }
-        true
+        let bar = Volume::from_points(&points);
+        let diagonal = Transform::from_pos(Vec3::new(13., 0., 0.)) * Quaternion::from((Vec3::Y, -45f32.to_radians()));
+        assert!(!f.has_volume(&diagonal, &bar));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::mem::{size_of, size_of_val};
+use std::os::raw::c_void;
+use gl::types::{GLsizei, GLsizeiptr, GLuint};
+use crate::mesh::Mesh;
+use crate::opengl::buffers::{GPUBuffers, VertexType};
+use crate::opengl::culling::GpuCulling;
+use crate::opengl::instances::{InstanceBuffer, InstanceData};
+use crate::opengl::material::{MaterialBuffer, MaterialData, MAX_MATERIALS, MAX_TEXTURES};
+use crate::opengl::object::MultiPartModel;
+use crate::opengl::safe_calls;
+use crate::other::resource_manager::ResourceManager;
+
+//layout expected by glMultiDrawElementsIndirect
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DrawElementsIndirectCommand {
+    pub count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub base_instance: u32
+}
+
+//counters of the last frame drawn by a scene
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DrawStats {
+    pub draw_calls: usize,
+    pub instances: usize,
+    pub triangles: usize
+}
+
+//a lod level of a model in the shared buffers
+#[derive(Debug, Copy, Clone)]
+struct Slice {
+    first_index: usize,
+    count: usize,
+    base_vertex: usize
+}
+
+//models (and lod levels) packed in a single vao, materials and textures renumbered to be shared by the models of the batch
+#[derive(Debug)]
+struct GeometryBatch {
+    buffers: GPUBuffers,
+    materials: MaterialBuffer,
+    textures: Vec<GLuint> //texture unit -> name
+}
+
+impl GeometryBatch {
+    fn bind(&self) {
+        self.materials.bind();
+        for (unit, name) in self.textures.iter().enumerate() {
+            safe_calls::bind_texture(unit, *name);
+        }
+        self.buffers.bind();
+    }
+}
+
+//every model packed in batches, a new batch is started when the materials or textures of a model do not fit in the current one
+//(a model over the limits on its own gets a batch and is clamped, the per model path splits it in material blocks instead)
+#[derive(Debug)]
+struct SharedGeometry {
+    batches: Vec<GeometryBatch>,
+    slices: HashMap<usize, (usize, Vec<Slice>)>, //per model: batch, per level
+    generation: usize
+}
+
+#[derive(Default)]
+struct BatchBuilder {
+    models: usize,
+    merged: Mesh,
+    vertex_materials: Vec<i32>,
+    materials: Vec<MaterialData>,
+    textures: Vec<GLuint>
+}
+
+impl BatchBuilder {
+    fn fits(&self, mpm: &MultiPartModel) -> bool {
+        self.models == 0 || (self.materials.len() + mpm.materials().len() <= MAX_MATERIALS && self.textures.len() + mpm.textures().len() <= MAX_TEXTURES)
+    }
+
+    fn add(&mut self, mpm: &MultiPartModel) -> Vec<Slice> {
+        self.models += 1;
+        let (material_base, texture_base) = (self.materials.len() as i32, self.textures.len() as i32);
+        self.materials.extend(mpm.materials().iter().map(|m| {
+            let mut data = MaterialData::from(m);
+            data.maps = data.maps.map(|t| (t + texture_base).min(MAX_TEXTURES as i32 - 1));
+            if data.alpha_map[0] >= 0 {
+                data.alpha_map[0] = (data.alpha_map[0] + texture_base).min(MAX_TEXTURES as i32 - 1);
+            }
+            data
+        }));
+        self.textures.extend(mpm.textures().iter().map(|t| t.name));
+        let mut levels = Vec::with_capacity(mpm.level_count());
+        for level in 0..mpm.level_count() {
+            let (mesh, vm) = mpm.level_geometry(level);
+            levels.push(Slice {
+                first_index: self.merged.indices.len(),
+                count: mpm.opaque_len(level), //the transparent parts at the end are drawn by the transparent pass
+                base_vertex: self.merged.positions.len()
+            });
+            self.merged.positions.extend_from_slice(&mesh.positions);
+            self.merged.colors.extend_from_slice(&mesh.colors);
+            self.merged.uvs.extend_from_slice(&mesh.uvs);
+            self.merged.normals.extend_from_slice(&mesh.normals);
+            self.merged.indices.extend_from_slice(&mesh.indices); //the base vertex of the draws offsets the indices
+            self.vertex_materials.extend(vm.iter().map(|m| (m + material_base).min(MAX_MATERIALS as i32 - 1)));
+        }
+        levels
+    }
+
+    fn build(mut self) -> GeometryBatch {
+        let mut buffers = GPUBuffers::new().unwrap();
+        buffers.new_vbo(0, VertexType::Vec3);
+        buffers.new_vbo(1, VertexType::Vec3);
+        buffers.new_vbo(2, VertexType::Vec3);
+        buffers.new_vbo(3, VertexType::Vec3);
+        buffers.new_vbo(4, VertexType::Int);
+        buffers.set_vbo(0, &self.merged.positions);
+        buffers.set_vbo(1, &self.merged.colors);
+        buffers.set_vbo(2, &self.merged.uvs);
+        buffers.set_vbo(3, &self.merged.normals);
+        buffers.set_vbo(4, &self.vertex_materials);
+        buffers.set_ebo(&self.merged.indices);
+        self.textures.truncate(MAX_TEXTURES);
+        GeometryBatch {
+            buffers,
+            materials: MaterialBuffer::from_data(self.materials),
+            textures: self.textures
+        }
+    }
+}
+
+impl SharedGeometry {
+    fn pack(resources: &ResourceManager) -> Self {
+        let mut batches = Vec::new();
+        let mut slices = HashMap::new();
+        let mut builder = BatchBuilder::default();
+        let mut models = resources.multipart_models().collect::<Vec<_>>();
+        models.sort_by_key(|(id, _)| *id);
+        for (id, mpm) in models {
+            if mpm.material_blocks() > 1 {
+                println!("model {id}: {} materials and {} textures do not fit in a batch, clamped", mpm.materials().len(), mpm.textures().len());
+            }
+            if !builder.fits(mpm) {
+                batches.push(std::mem::take(&mut builder).build());
+            }
+            slices.insert(id, (batches.len(), builder.add(mpm)));
+        }
+        batches.push(builder.build());
+        Self {
+            batches,
+            slices,
+            generation: resources.models_generation()
+        }
+    }
+}
+
+//draws all the visible instances of all the models with a single glMultiDrawElementsIndirect per batch (gl 4.3), or a loop of draws per command on gl 3.3
+#[derive(Debug)]
+pub struct IndirectRenderer {
+    geometry: Option<SharedGeometry>,
+    instances: InstanceBuffer,
+    commands: Vec<Vec<DrawElementsIndirectCommand>>, //per batch
+    command_buffer: GLuint,
+    layout: Vec<(usize, usize)>, //(model, amount of instances) in the order of the uploaded instances
+    multi_draw: bool
+}
+
+impl IndirectRenderer {
+    pub fn new() -> Self {
+        let version = (safe_calls::get_int(gl::MAJOR_VERSION), safe_calls::get_int(gl::MINOR_VERSION));
+        let mut command_buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut command_buffer);
+        }
+        Self {
+            geometry: None,
+            instances: InstanceBuffer::new(),
+            commands: Vec::new(),
+            command_buffer,
+            layout: Vec::new(),
+            multi_draw: version >= (4, 3) && gl::MultiDrawElementsIndirect::is_loaded()
+        }
+    }
+
+    ///false if the gl 3.3 fallback loop is used
+    pub fn multi_draw(&self) -> bool { self.multi_draw }
+
+    ///force the fallback loop (ex: to compare both paths)
+    pub fn set_multi_draw(&mut self, enabled: bool) {
+        self.multi_draw = enabled && gl::MultiDrawElementsIndirect::is_loaded();
+    }
+
+    ///pack the models again if the resources changed since the last frame
+    pub fn prepare(&mut self, resources: &ResourceManager) {
+        if self.geometry.as_ref().is_none_or(|g| g.generation != resources.models_generation()) {
+            self.geometry = Some(SharedGeometry::pack(resources));
+        }
+    }
+
+    ///generation of the resources the shared geometry was packed from
+    pub fn generation(&self) -> usize {
+        self.geometry.as_ref().map_or(0, |g| g.generation)
+    }
+
+    ///amount of vaos the models were packed in (a single one unless their materials or textures do not fit together)
+    pub fn batch_count(&self) -> usize {
+        self.geometry.as_ref().map_or(0, |g| g.batches.len())
+    }
+
+    ///batch the geometry of a model was packed in
+    pub fn batch(&self, model: usize) -> usize {
+        self.geometry.as_ref().and_then(|g| g.slices.get(&model)).map_or(0, |(batch, _)| *batch)
+    }
+
+    ///commands drawing each lod level of a model, without instances
+    pub fn levels(&self, model: usize) -> Vec<DrawElementsIndirectCommand> {
+        self.geometry.as_ref().and_then(|g| g.slices.get(&model)).map_or(Vec::new(), |(_, levels)| levels.iter().map(|slice| DrawElementsIndirectCommand {
+            count: slice.count as u32,
+            instance_count: 0,
+            first_index: slice.first_index as u32,
+            base_vertex: slice.base_vertex as i32,
+            base_instance: 0
+        }).collect())
+    }
+
+    ///start a new list of commands, the instances are uploaded only if they changed
+    pub fn begin(&mut self) {
+        self.commands.iter_mut().for_each(Vec::clear);
+    }
+
+    ///queue the instances of a model at a lod level, base_instance is the position of the first one in the uploaded instances
+    pub fn push(&mut self, model: usize, lod: usize, base_instance: usize, count: usize) {
+        if let Some((batch, slice)) = self.geometry.as_ref().and_then(|g| g.slices.get(&model)).and_then(|(b, l)| Some((*b, l.get(lod.min(l.len() - 1))?))) {
+            if self.commands.len() <= batch {
+                self.commands.resize_with(batch + 1, Vec::new);
+            }
+            self.commands[batch].push(DrawElementsIndirectCommand {
+                count: slice.count as u32,
+                instance_count: count as u32,
+                first_index: slice.first_index as u32,
+                base_vertex: slice.base_vertex as i32,
+                base_instance: base_instance as u32
+            });
+        }
+    }
+
+    ///returns true if the order or amount of the instances of the models changed since the last frame
+    pub fn set_layout(&mut self, layout: Vec<(usize, usize)>) -> bool {
+        let changed = layout != self.layout;
+        self.layout = layout;
+        changed
+    }
+
+    pub fn upload_instances(&self, instances: &[InstanceData]) {
+        self.instances.upload(instances);
+    }
+
+    ///submit the queued commands
+    pub fn draw(&self, stats: &mut DrawStats) {
+        let mut commands = Vec::new();
+        let mut batches = Vec::new();
+        for (batch, queued) in self.commands.iter().enumerate().filter(|(_, c)| !c.is_empty()) {
+            batches.push((batch, commands.len()..commands.len() + queued.len()));
+            commands.extend_from_slice(queued);
+        }
+        if self.multi_draw && !commands.is_empty() {
+            unsafe {
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.command_buffer);
+                gl::BufferData(gl::DRAW_INDIRECT_BUFFER, size_of_val(commands.as_slice()) as GLsizeiptr, commands.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            }
+        }
+        Self::count(&commands, stats);
+        self.submit(&self.instances, &commands, &batches, self.command_buffer, false, stats);
+    }
+
+    ///submit the commands written by the gpu culling, never waits for it: the compute path draws every command from the gpu buffer
+    ///and counts the instances of the statistics from a previous frame
+    pub fn draw_culled(&self, culling: &GpuCulling, stats: &mut DrawStats) {
+        Self::count(culling.counters(), stats);
+        if culling.compute() {
+            self.submit(culling.output(), culling.templates(), culling.batches(), culling.command_buffer(), true, stats);
+            return;
+        }
+        //the feedback path counted the instances on the cpu
+        let commands = culling.counters();
+        if self.multi_draw && !commands.is_empty() {
+            unsafe {
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, culling.command_buffer());
+                gl::BufferData(gl::DRAW_INDIRECT_BUFFER, size_of_val(commands) as GLsizeiptr, commands.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            }
+        }
+        self.submit(culling.output(), commands, culling.batches(), culling.command_buffer(), false, stats);
+    }
+
+    fn count(commands: &[DrawElementsIndirectCommand], stats: &mut DrawStats) {
+        for command in commands {
+            stats.instances += command.instance_count as usize;
+            stats.triangles += (command.count / 3 * command.instance_count) as usize;
+        }
+    }
+
+    ///draw the ranges of commands of each batch, gpu_counts: the instance counts are only known by the gpu (in the command buffer)
+    fn submit(&self, instances: &InstanceBuffer, commands: &[DrawElementsIndirectCommand], batches: &[(usize, Range<usize>)], command_buffer: GLuint, gpu_counts: bool, stats: &mut DrawStats) {
+        let Some(geometry) = &self.geometry else { return; };
+        let stride = size_of::<DrawElementsIndirectCommand>();
+        for (batch, range) in batches.iter().filter(|(_, r)| r.start < r.end.min(commands.len())) {
+            let Some(batch) = geometry.batches.get(*batch) else { continue; };
+            batch.bind();
+            if self.multi_draw || gpu_counts {
+                //the base instance of the commands offsets the instanced attributes
+                instances.attach(&batch.buffers, 0);
+                unsafe {
+                    gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, command_buffer);
+                    if self.multi_draw {
+                        gl::MultiDrawElementsIndirect(gl::TRIANGLES, gl::UNSIGNED_INT, (range.start * stride) as *const c_void, range.len() as GLsizei, 0);
+                        stats.draw_calls += 1;
+                    } else {
+                        for i in range.clone() {
+                            gl::DrawElementsIndirect(gl::TRIANGLES, gl::UNSIGNED_INT, (i * stride) as *const c_void);
+                        }
+                        stats.draw_calls += range.len();
+                    }
+                    gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+                }
+            } else {
+                for command in commands[range.clone()].iter().filter(|c| c.instance_count > 0) {
+                    instances.attach(&batch.buffers, command.base_instance as usize);
+                    unsafe {
+                        gl::DrawElementsInstancedBaseVertex(gl::TRIANGLES, command.count as GLsizei, gl::UNSIGNED_INT, (command.first_index as usize * size_of::<u32>()) as *const c_void, command.instance_count as GLsizei, command.base_vertex);
+                    }
+                    stats.draw_calls += 1;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for IndirectRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.command_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::framebuffer::RenderTarget;
+    use crate::opengl::indirect::DrawStats;
+    use crate::opengl::material::MAX_MATERIALS;
+    use crate::opengl::safe_calls;
+    use crate::opengl::scene::{ObjectData, Scene};
+    use crate::opengl::shader::ShaderProgram;
+    use crate::other::resource_manager::ResourceManager;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn same_image_as_per_model() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        //two grids of quads with an emissive material each, together over the materials of a single batch
+        let directory = std::env::temp_dir().join(format!("scop_indirect_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let quads = MAX_MATERIALS / 2 + 8;
+        for grid in 0..2 {
+            let (mut obj, mut mtl) = (format!("mtllib grid{grid}.mtl\n"), String::new());
+            for i in 0..quads {
+                let (x, y) = ((i % 8) as f32 - 4., (i / 8) as f32 - 5. + grid as f32 * 5.);
+                writeln!(mtl, "newmtl color{i}\nKa 0 0 0\nKe {} {} {}", (i % 4) as f32 / 3., (i / 4 % 4) as f32 / 3., grid as f32).unwrap();
+                writeln!(obj, "v {x} {y} 0\nv {} {y} 0\nv {} {} 0\nv {x} {} 0\nvn 0 0 1", x + 0.9, x + 0.9, y + 0.9, y + 0.9).unwrap();
+                let (v, n) = (i * 4 + 1, i + 1);
+                writeln!(obj, "usemtl color{i}\nf {v}//{n} {}//{n} {}//{n}\nf {v}//{n} {}//{n} {}//{n}", v + 1, v + 2, v + 2, v + 3).unwrap();
+            }
+            std::fs::write(directory.join(format!("grid{grid}.obj")), obj).unwrap();
+            std::fs::write(directory.join(format!("grid{grid}.mtl")), mtl).unwrap();
+        }
+        let mut resources = ResourceManager::default();
+        resources.register_hints(&["resources", "resources/shaders", directory.to_str().unwrap()]);
+        let program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
+        let mut scene = Scene::new(program);
+        for grid in 0..2 {
+            let (model, _) = resources.load_multipart_model(format!("grid{grid}")).unwrap();
+            for x in [-5., 5.] {
+                scene.spawn_object(model, ObjectData::from(Transform::from_look_towards(Vec3::new(x, 0., 0.), -Vec3::Z)));
+            }
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+        scene.set_post_processing(false);
+        scene.set_camera(Transform::from_look_at(Vec3::new(0., 0., 12.), Vec3::default()));
+        scene.set_projection(80., 1.);
+        let size = 64;
+        let target = RenderTarget::default().color(gl::RGBA8).depth(gl::DEPTH_COMPONENT24).build((size, size));
+        target.bind();
+        safe_calls::set_clear_color(0., 0., 0.);
+        safe_calls::set_depth_test(true);
+        let render = |scene: &mut Scene| {
+            safe_calls::clear_screen();
+            scene.draw(&resources);
+            assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+            let mut pixels = vec![0u8; (size * size * 4) as usize];
+            unsafe { gl::ReadPixels(0, 0, size as i32, size as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr().cast()); }
+            (pixels, scene.stats())
+        };
+        let counts = |stats: DrawStats| (stats.instances, stats.triangles);
+        let (expected, stats) = render(&mut scene);
+        //both grids are drawn, with their own colors
+        assert!(expected.chunks(4).filter(|p| p[2] > 200).count() > (size * size / 16) as usize);
+        assert!(expected.chunks(4).filter(|p| p[2] < 50 && (p[0] > 50 || p[1] > 50)).count() > (size * size / 16) as usize);
+        assert_eq!(counts(stats), (4, 4 * quads * 2));
+        scene.set_indirect(true);
+        for multi_draw in [true, false] {
+            scene.indirect_mut().unwrap().set_multi_draw(multi_draw);
+            let (pixels, stats) = render(&mut scene);
+            let indirect = scene.indirect().unwrap();
+            assert!(pixels == expected, "the indirect path (multi draw: {}) differs from the per model path", indirect.multi_draw());
+            assert_eq!(indirect.batch_count(), 2);
+            assert_eq!(counts(stats), (4, 4 * quads * 2));
+            //a multi draw per batch, or a draw per command (a model at a lod level)
+            assert_eq!(stats.draw_calls, 2);
+        }
+        //the counters of the gpu culling come from a previous frame
+        scene.set_gpu_culling(true);
+        render(&mut scene);
+        unsafe {
+            gl::Finish();
+        }
+        let (pixels, stats) = render(&mut scene);
+        assert!(pixels == expected, "the gpu culling differs from the per model path");
+        assert_eq!(counts(stats), (4, 4 * quads * 2));
+    }
+}
@@ -0,0 +1,80 @@
+use std::mem::{offset_of, size_of, size_of_val};
+use std::os::raw::c_void;
+use gl::types::{GLsizeiptr, GLuint};
+use crate::opengl::buffers::{GPUBuffers, VertexType};
+
+pub const FLAGS_LOCATION: usize = 5;
+pub const MATRIX_LOCATION: usize = 6; //a mat4 takes 4 locations (one per column): 6 to 9
+pub const FADE_LOCATION: usize = 10;
+
+//per instance vertex attributes, read by the shaders with a divisor of 1
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct InstanceData {
+    pub mat: [f32; 16], //column major
+    pub flags: i32,
+    pub fade: f32,
+    pub id: u32 //not read by the draws, lets the gpu culling report which instances it kept
+}
+
+//stream of instances shared by the vaos of all the parts (and lod levels) of a model
+#[derive(Debug)]
+pub struct InstanceBuffer {
+    vbo: GLuint
+}
+
+impl Default for InstanceBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstanceBuffer {
+    pub fn new() -> Self {
+        let mut vbo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vbo);
+        }
+        Self { vbo }
+    }
+
+    ///point the instance attributes of a vao to this buffer, starting at an instance (gl 3.3 has no base instance for the draws)
+    pub fn attach(&self, buffers: &GPUBuffers, first: usize) {
+        let stride = size_of::<InstanceData>();
+        let base = first * stride;
+        buffers.new_external_instanced_vbo(self.vbo, FLAGS_LOCATION, VertexType::Int, stride, base + offset_of!(InstanceData, flags));
+        for column in 0..4 {
+            buffers.new_external_instanced_vbo(self.vbo, MATRIX_LOCATION + column, VertexType::Vec4, stride, base + offset_of!(InstanceData, mat) + column * size_of::<[f32; 4]>());
+        }
+        buffers.new_external_instanced_vbo(self.vbo, FADE_LOCATION, VertexType::Float, stride, base + offset_of!(InstanceData, fade));
+    }
+
+    pub fn vbo(&self) -> GLuint { self.vbo }
+
+    ///allocate room for an amount of instances without uploading anything (filled by the gpu)
+    pub fn reserve(&self, len: usize) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (len * size_of::<InstanceData>()) as GLsizeiptr, std::ptr::null(), gl::DYNAMIC_COPY);
+        }
+    }
+
+    ///orphan the previous storage (the driver does not have to wait for the draws still using it) and upload the new instances
+    pub fn upload(&self, instances: &[InstanceData]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, size_of_val(instances) as GLsizeiptr, std::ptr::null(), gl::STREAM_DRAW);
+            if !instances.is_empty() {
+                gl::BufferSubData(gl::ARRAY_BUFFER, 0, size_of_val(instances) as GLsizeiptr, instances.as_ptr() as *const c_void);
+            }
+        }
+    }
+}
+
+impl Drop for InstanceBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+#version 330 core
+
+in vec3 world;
+
+uniform vec3 light;
+uniform float far;
+uniform float bias; //in texels of the map at the distance of the fragment
+uniform float slope_bias;
+uniform float texel; //size of a texel at a distance of 1
+
+//linear distance to the light (0 to 1 at far), pushed away from the light like a polygon offset
+void main() {
+    float d = distance(world, light);
+    float slope = max(abs(dFdx(d)), abs(dFdy(d)));
+    gl_FragDepth = (d + bias * texel * d + slope_bias * slope) / far;
+}
\ No newline at end of file
@@ -0,0 +1,8 @@
+#version 330 core
+layout (location = 0) in vec3 v_pos;
+
+layout (location = 6) in mat4 i_mat;
+
+void main() {
+    gl_Position = i_mat * vec4(v_pos, 1.0); //world position, projected by the geometry shader
+}
\ No newline at end of file
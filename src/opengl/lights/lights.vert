@@ -1,10 +1,10 @@
 #version 330 core
-layout (location = 0) in vec3 pos;
+layout (location = 0) in vec3 v_pos;
 
-uniform mat4 object;
-uniform mat4 proj;
-uniform mat4 camera;
+layout (location = 6) in mat4 i_mat;
+
+uniform mat4 light_space;
 
 void main() {
-    gl_Position = proj * camera * object * vec4(pos, 1.0);
+    gl_Position = light_space * i_mat * vec4(v_pos, 1.0);
 }
\ No newline at end of file
@@ -1,18 +1,22 @@
-use std::collections::HashMap;
 use std::mem::size_of;
-use gl::types::{GLsizei, GLuint};
+use std::os::raw::c_void;
+use gl::types::{GLsizeiptr, GLuint};
+use crate::maths::matrix::Mat4;
 use crate::maths::vector::Vec3;
-use crate::opengl::enums::Shaders;
-use crate::opengl::part::ObjectPart;
-use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::lights::shadows::{CubeShadowProgram, DirectionalShadow, PointShadow, ShadowProgram, ShadowSettings, CUBE_SHADOW_MAPS_UNIT, MAX_CASCADES, MAX_CUBE_SHADOW_MAPS, MAX_SHADOW_MAPS, SHADOW_MAPS_UNIT};
+use crate::other::itermap::IterMap;
 
-mod directional;
+pub mod shadows;
+
+pub const MAX_LIGHTS: usize = 32; //size of the lights array of the Lights uniform block of the shaders
+pub const LIGHTS_BINDING: GLuint = 1; //uniform block binding point of the Lights block
 
 #[derive(Debug, Copy, Clone)]
 pub struct Light {
     pub kind: LightKind,
-    pub falloff: f32,
-    pub color: Vec3
+    pub falloff: f32, //distance at which the light fades out, 0 for no attenuation (ignored by directional lights)
+    pub color: Vec3,
+    pub shadow: Option<ShadowSettings> //directional and point lights cast shadows, spots do not for now
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -30,66 +34,290 @@ pub enum LightKind {
     }
 }
 
-//calculate the shadow maps and light intensities of directional lights, spot lights and point lights in a scene
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3) -> Self {
+        Self { kind: LightKind::Directional { direction }, falloff: 0., color, shadow: None }
+    }
+
+    pub fn spot(position: Vec3, direction: Vec3, aperture: f32, color: Vec3, falloff: f32) -> Self {
+        Self { kind: LightKind::Spot { position, direction, aperture }, falloff, color, shadow: None }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, falloff: f32) -> Self {
+        Self { kind: LightKind::Point { position }, falloff, color, shadow: None }
+    }
+
+    pub fn with_shadow(mut self, settings: ShadowSettings) -> Self {
+        self.shadow = Some(settings);
+        self
+    }
+}
+
+impl Default for LightKind {
+    fn default() -> Self {
+        Self::Directional { direction: -Vec3::Z }
+    }
+}
+
+//layout of a light in the Lights block (std140)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct LightData {
+    pub position: [f32; 4], //w: 0 directional, 1 spot, 2 point
+    pub direction: [f32; 4], //normalized, w: cosine of the half aperture (edge of the cone)
+    pub color: [f32; 4], //w: falloff distance
+    pub parameters: [f32; 4], //x: cosine of the inner cone (full intensity)
+    pub shadow: [f32; 4], //x: shadow map (-1: none, cube map for point lights), y: radius of the filter in texels, z: range of a cube map, w: resolution of a cube map
+}
+
+impl From<&Light> for LightData {
+    fn from(light: &Light) -> Self {
+        let (kind, position, direction, aperture) = match light.kind {
+            LightKind::Directional { direction } => (0., Vec3::default(), direction, std::f32::consts::TAU),
+            LightKind::Spot { position, direction, aperture } => (1., position, direction, aperture),
+            LightKind::Point { position } => (2., position, -Vec3::Z, std::f32::consts::TAU),
+        };
+        let half = (aperture / 2.).min(std::f32::consts::PI);
+        let direction = direction.normalize();
+        Self {
+            position: [position[0], position[1], position[2], kind],
+            direction: [direction[0], direction[1], direction[2], half.cos()],
+            color: [light.color[0], light.color[1], light.color[2], light.falloff],
+            parameters: [(half * 0.9).cos(), 0., 0., 0.], //the last tenth of the half aperture blends the edge of the cone
+            shadow: [-1., 0., 0., 0.]
+        }
+    }
+}
+
+//layout of the cascades of a directional shadow map in the Lights block (std140)
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ShadowData {
+    pub light_spaces: [[f32; 16]; MAX_CASCADES], //column major, per cascade
+    pub splits: [f32; 4], //view depth of the end of each cascade
+    pub parameters: [f32; 4], //x: cascades, y: fraction of a cascade blended with the next
+}
+
+//layout of the Lights block (std140)
+#[repr(C)]
+struct LightsBlock {
+    ambient: [f32; 4],
+    count: [i32; 4],
+    lights: [LightData; MAX_LIGHTS],
+    shadows: [ShadowData; MAX_SHADOW_MAPS]
+}
+
+//lights of a scene, uploaded to a uniform buffer shared by every draw when one of them changed
+#[derive(Debug)]
 pub struct Lights {
-    pub program: ShaderProgram,
-    pub lights: HashMap<usize, Light>, //kind, falloff, color
-    pub next_id: usize,
-    pub kinds: Vec<i32>, //0 directional, 1 spot, 2 point
-    pub apertures: Vec<f32>,
-    pub falloffs: Vec<f32>,
-    pub positions: Vec<Vec3>,
-    pub directions: Vec<Vec3>,
-    pub colors: Vec<Vec3>,
-    pub vao: GLuint,
-    pub vbos: [GLuint; 6],
-    pub maps: [GLuint; 16],
-    pub cube_maps: [GLuint; 8]
+    lights: IterMap<usize, Light>,
+    next_id: usize,
+    ambient: Vec3,
+    ubo: GLuint,
+    dirty: bool,
+    shadows: Vec<DirectionalShadow>, //index of the shadow map in the shaders
+    depth: ShadowProgram,
+    point_shadows: Vec<PointShadow>, //index of the cube map in the shaders
+    max_point_shadows: usize,
+    cube_depth: CubeShadowProgram
 }
 
 impl Lights {
     pub fn new() -> Self {
-        let program = ShaderProgramBuilder::default()
-            .add_shader(Shaders::Vertex, include_str!("lights.vert"))
-            // .add_shader(Shaders::Geometry, include_str!("lights.geom"))
-            .add_shader(Shaders::Fragment, include_str!("lights.frag"))
-            .build().unwrap();
-        let mut vao = 0;
-        let mut vbos = [0; 6];
-        let mut maps = [0; 16];
-        let mut cube_maps = [0; 8];
+        let mut ubo = 0;
         unsafe {
-            gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
-            gl::GenBuffers(6, &mut vbos[0]);
-            for i in 0..6 {
-                gl::BindBuffer(gl::ARRAY_BUFFER, vbos[i]);
-                if i < 3 {
-                    gl::VertexAttribPointer(i as GLuint, 1, gl::FLOAT, gl::FALSE, size_of::<f32>() as GLsizei, 0 as *const _);
-                } else {
-                    gl::VertexAttribPointer(i as GLuint, 3, gl::FLOAT, gl::FALSE, ObjectPart::VEC3_SIZE as GLsizei, 0 as *const _);
+            gl::GenBuffers(1, &mut ubo);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, ubo);
+            gl::BufferData(gl::UNIFORM_BUFFER, size_of::<LightsBlock>() as GLsizeiptr, std::ptr::null(), gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+        Self {
+            lights: IterMap::new(),
+            next_id: 0,
+            ambient: Vec3::new(0.2, 0.2, 0.2),
+            ubo,
+            dirty: true,
+            shadows: Vec::new(),
+            depth: ShadowProgram::new(),
+            point_shadows: Vec::new(),
+            max_point_shadows: MAX_CUBE_SHADOW_MAPS,
+            cube_depth: CubeShadowProgram::new()
+        }
+    }
+
+    ///lights past MAX_LIGHTS are kept but not uploaded
+    pub fn spawn(&mut self, light: Light) -> usize {
+        self.lights.insert(self.next_id, light);
+        self.next_id += 1;
+        self.dirty = true;
+        self.next_id - 1
+    }
+
+    pub fn despawn(&mut self, id: usize) -> Option<Light> {
+        let out = self.lights.remove(&id);
+        self.dirty |= out.is_some();
+        out
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Light> {
+        self.lights.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Light> {
+        let out = self.lights.get_mut(&id);
+        self.dirty |= out.is_some();
+        out
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, Light)> {
+        self.lights.iter()
+    }
+
+    pub fn ambient(&self) -> Vec3 { self.ambient }
+
+    ///light reaching every surface, scaled by the ambient color of the materials
+    pub fn set_ambient(&mut self, ambient: Vec3) {
+        self.ambient = ambient;
+        self.dirty = true;
+    }
+
+    pub fn max_point_shadows(&self) -> usize { self.max_point_shadows }
+
+    ///point lights casting shadows past this amount are lit without shadows (clamped to MAX_CUBE_SHADOW_MAPS)
+    pub fn set_max_point_shadows(&mut self, max: usize) {
+        self.max_point_shadows = max.min(MAX_CUBE_SHADOW_MAPS);
+    }
+
+    ///keep a shadow map for each of the first MAX_SHADOW_MAPS directional lights casting shadows and a cube map for each of the first
+    ///max_point_shadows point lights casting shadows, in order of spawn
+    pub fn sync_shadows(&mut self) {
+        let casters = self.lights.iter().filter(|(_, light)| matches!(light.kind, LightKind::Directional { .. }))
+            .filter_map(|(id, light)| light.shadow.map(|settings| (*id, settings)))
+            .take(MAX_SHADOW_MAPS).collect::<Vec<_>>();
+        if casters.len() != self.shadows.len() || casters.iter().zip(&self.shadows).any(|((id, settings), shadow)| *id != shadow.light || *settings != shadow.settings) {
+            let mut previous = std::mem::take(&mut self.shadows);
+            for (id, settings) in casters {
+                //the textures are reused when the resolution did not change, the maps are drawn again
+                match previous.iter().position(|shadow| shadow.light == id && shadow.settings.resolution == settings.resolution && shadow.settings.cascades == settings.cascades) {
+                    Some(i) => {
+                        let mut shadow = previous.swap_remove(i);
+                        shadow.settings = settings;
+                        shadow.cascades.iter_mut().for_each(|cascade| cascade.light_space = Mat4::default());
+                        self.shadows.push(shadow);
+                    }
+                    None => self.shadows.push(DirectionalShadow::new(id, settings))
                 }
             }
-            gl::GenTextures(16, &mut maps[0]);
-            gl::GenTextures(8, &mut cube_maps[0]);
-            for i in 0..8 {
-                
+            self.dirty = true;
+        }
+        let casters = self.lights.iter().filter(|(_, light)| matches!(light.kind, LightKind::Point { .. }))
+            .filter_map(|(id, light)| light.shadow.map(|settings| (*id, settings)))
+            .take(self.max_point_shadows).collect::<Vec<_>>();
+        if casters.len() != self.point_shadows.len() || casters.iter().zip(&self.point_shadows).any(|((id, settings), shadow)| *id != shadow.light || *settings != shadow.settings) {
+            let mut previous = std::mem::take(&mut self.point_shadows);
+            for (id, settings) in casters {
+                match previous.iter().position(|shadow| shadow.light == id && shadow.settings.resolution == settings.resolution) {
+                    Some(i) => {
+                        let mut shadow = previous.swap_remove(i);
+                        shadow.settings = settings;
+                        shadow.position = Vec3::splat(f32::NAN);
+                        self.point_shadows.push(shadow);
+                    }
+                    None => self.point_shadows.push(PointShadow::new(id, settings))
+                }
             }
+            self.dirty = true;
         }
-        Self {
-            program,
-            lights: HashMap::new(),
-            next_id: 0,
-            kinds: Vec::new(),
-            positions: Vec::new(),
-            directions: Vec::new(),
-            apertures: Vec::new(),
-            falloffs: Vec::new(),
-            colors: Vec::new(),
-            vao,
-            vbos,
-            maps,
-            cube_maps
+    }
+
+    ///depth program and shadow maps with the direction of their light, the lights are uploaded again at the next bind
+    pub fn shadows_mut(&mut self) -> (&ShadowProgram, impl Iterator<Item = (Vec3, &mut DirectionalShadow)>) {
+        self.dirty |= !self.shadows.is_empty();
+        let lights = &self.lights;
+        (&self.depth, self.shadows.iter_mut().filter_map(|shadow| match lights.get(&shadow.light).map(|light| light.kind) {
+            Some(LightKind::Directional { direction }) => Some((direction, shadow)),
+            _ => None
+        }))
+    }
+
+    ///cube depth program and cube maps with the position and range of their light (falloff, or the distance of the settings without one)
+    pub fn point_shadows_mut(&mut self) -> (&CubeShadowProgram, impl Iterator<Item = (Vec3, f32, &mut PointShadow)>) {
+        self.dirty |= !self.point_shadows.is_empty();
+        let lights = &self.lights;
+        (&self.cube_depth, self.point_shadows.iter_mut().filter_map(|shadow| match lights.get(&shadow.light).map(|light| (light.kind, light.falloff)) {
+            Some((LightKind::Point { position }, falloff)) => Some((position, if falloff > 0. { falloff } else { shadow.settings.distance }, shadow)),
+            _ => None
+        }))
+    }
+
+    ///upload the lights if they changed and bind the buffer to the Lights block (and the shadow maps to their units)
+    pub fn bind(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            let mut block = LightsBlock {
+                ambient: [self.ambient[0], self.ambient[1], self.ambient[2], 0.],
+                count: [self.lights.len().min(MAX_LIGHTS) as i32, 0, 0, 0],
+                lights: [LightData::default(); MAX_LIGHTS],
+                shadows: [ShadowData::default(); MAX_SHADOW_MAPS]
+            };
+            for (slot, (id, light)) in block.lights.iter_mut().zip(self.lights.iter()) {
+                *slot = LightData::from(light);
+                if let Some((index, shadow)) = self.shadows.iter().enumerate().find(|(_, shadow)| shadow.light == *id) {
+                    slot.shadow = [index as f32, shadow.settings.filter as f32, 0., 0.];
+                } else if let Some((index, shadow)) = self.point_shadows.iter().enumerate().find(|(_, shadow)| shadow.light == *id) {
+                    slot.shadow = [index as f32, shadow.settings.filter as f32, shadow.far, shadow.settings.resolution as f32];
+                }
+            }
+            for (slot, shadow) in block.shadows.iter_mut().zip(&self.shadows) {
+                for (i, cascade) in shadow.cascades.iter().enumerate() {
+                    slot.light_spaces[i] = cascade.light_space.raw_array();
+                    slot.splits[i] = cascade.split;
+                }
+                slot.parameters = [shadow.cascades.len() as f32, shadow.settings.cascade_blend.clamp(0., 1.), 0., 0.];
+            }
+            unsafe {
+                gl::BindBuffer(gl::UNIFORM_BUFFER, self.ubo);
+                gl::BufferSubData(gl::UNIFORM_BUFFER, 0, size_of::<LightsBlock>() as GLsizeiptr, &block as *const LightsBlock as *const c_void);
+                gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            }
+        }
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, LIGHTS_BINDING, self.ubo);
+        }
+        for (index, shadow) in self.shadows.iter().enumerate() {
+            shadow.map.bind_unit(SHADOW_MAPS_UNIT + index);
+        }
+        for (index, shadow) in self.point_shadows.iter().enumerate() {
+            shadow.map.bind_unit(CUBE_SHADOW_MAPS_UNIT + index);
+        }
+    }
+}
+
+impl Drop for Lights {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.ubo);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::vector::Vec3;
+    use crate::opengl::lights::{Light, LightData};
+
+    #[test]
+    fn spot_packing() {
+        let light = Light::spot(Vec3::new(1., 2., 3.), Vec3::new(0., 0., -4.), std::f32::consts::FRAC_PI_2, Vec3::new(1., 0.5, 0.25), 100.);
+        let data = LightData::from(&light);
+        assert_eq!(data.position, [1., 2., 3., 1.]);
+        assert_eq!(&data.direction[..3], &[0., 0., -1.]);
+        assert!((data.direction[3] - std::f32::consts::FRAC_PI_4.cos()).abs() < 1e-6);
+        assert!(data.parameters[0] > data.direction[3]);
+        assert_eq!(data.color, [1., 0.5, 0.25, 100.]);
+        //a point light lits in every direction
+        let data = LightData::from(&Light::point(Vec3::default(), Vec3::new(1., 1., 1.), 0.));
+        assert_eq!(data.position[3], 2.);
+        assert!(data.direction[3] <= -1. + 1e-6);
+    }
+}
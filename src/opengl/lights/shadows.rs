@@ -0,0 +1,462 @@
+use gl::types::{GLint, GLsizei, GLuint};
+use crate::maths::matrix::Mat4;
+use crate::maths::vector::{Vec3, Vec4};
+use crate::opengl::enums::Shaders;
+use crate::opengl::instances::{InstanceBuffer, InstanceData};
+use crate::opengl::material::MAX_TEXTURES;
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+use crate::opengl::volume::Aabb;
+use crate::other::resource_manager::ResourceManager;
+
+pub const MAX_SHADOW_MAPS: usize = 4; //directional lights casting shadows at the same time
+pub const MAX_CASCADES: usize = 4; //layers of the shadow map of a directional light
+pub const SHADOW_MAPS_UNIT: usize = MAX_TEXTURES; //first texture unit of the shadow maps, after the ones of the materials
+pub const MAX_CUBE_SHADOW_MAPS: usize = 4; //upper bound of the point lights casting shadows at the same time
+pub const CUBE_SHADOW_MAPS_UNIT: usize = SHADOW_MAPS_UNIT + MAX_SHADOW_MAPS;
+
+pub type ShadowDraw = (usize, usize, usize, usize); //model, lod, first instance, count
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShadowSettings {
+    pub resolution: u32, //width and height of the depth texture
+    pub filter: u32, //radius in texels of the percentage closer filter (0: single hardware filtered sample)
+    pub bias: f32, //constant depth offset of the casters (units of the depth buffer, texels at the distance of the caster for point lights)
+    pub slope_bias: f32, //depth offset of the casters scaled by their slope relative to the light
+    pub distance: f32, //shadows are only computed up to this distance from the camera (from the light for point lights without falloff)
+    pub cascades: u32, //slices of the view frustum of a directional light with their own map (1 to MAX_CASCADES)
+    pub split_blend: f32, //distribution of the slices, 0: linear (even slices), 1: logarithmic (even texel density)
+    pub cascade_blend: f32, //fraction of the end of a slice faded into the next one (the last one fades out)
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            filter: 1,
+            bias: 4.,
+            slope_bias: 2.,
+            distance: 200.,
+            cascades: 4,
+            split_blend: 0.75,
+            cascade_blend: 0.1
+        }
+    }
+}
+
+//draws the depth of the casters from a light
+#[derive(Debug)]
+pub struct ShadowProgram {
+    program: ShaderProgram,
+    light_space: Uniform
+}
+
+impl ShadowProgram {
+    pub fn new() -> Self {
+        let program = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("lights.vert"))
+            .add_shader(Shaders::Fragment, include_str!("lights.frag"))
+            .build().unwrap();
+        Self {
+            light_space: program.uniform("light_space"),
+            program
+        }
+    }
+
+    pub fn begin(&self, light_space: Mat4) {
+        self.program.set_active();
+        self.light_space.mat4(light_space);
+    }
+}
+
+//draws the distance of the casters to a point light in the 6 faces of a cube map in a single pass (layered rendering)
+#[derive(Debug)]
+pub struct CubeShadowProgram {
+    program: ShaderProgram,
+    faces: Uniform,
+    light: Uniform,
+    far: Uniform,
+    bias: Uniform,
+    slope_bias: Uniform,
+    texel: Uniform
+}
+
+impl CubeShadowProgram {
+    pub fn new() -> Self {
+        let program = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("cube.vert"))
+            .add_shader(Shaders::Geometry, include_str!("cube.geom"))
+            .add_shader(Shaders::Fragment, include_str!("cube.frag"))
+            .build().unwrap();
+        Self {
+            faces: program.uniform("faces"),
+            light: program.uniform("light"),
+            far: program.uniform("far"),
+            bias: program.uniform("bias"),
+            slope_bias: program.uniform("slope_bias"),
+            texel: program.uniform("texel"),
+            program
+        }
+    }
+
+    pub fn begin(&self, shadow: &PointShadow) {
+        self.program.set_active();
+        self.faces.array_mat4(&cube_faces(&shadow.position, shadow.far).to_vec());
+        self.light.vec3(shadow.position);
+        self.far.float(shadow.far);
+        self.bias.float(shadow.settings.bias);
+        self.slope_bias.float(shadow.settings.slope_bias);
+        self.texel.float(2. / shadow.settings.resolution as f32);
+    }
+}
+
+//array of depth textures (one per cascade) compared in the shaders (sampler2DArrayShadow), fragments outside of them are lit
+#[derive(Debug)]
+pub struct ShadowMap {
+    framebuffer: GLuint,
+    texture: GLuint,
+    resolution: u32,
+    layers: u32
+}
+
+impl ShadowMap {
+    pub fn new(resolution: u32, layers: u32) -> Self {
+        let mut out = Self { framebuffer: 0, texture: 0, resolution, layers };
+        unsafe {
+            gl::GenFramebuffers(1, &mut out.framebuffer);
+            gl::GenTextures(1, &mut out.texture);
+            safe_calls::edit_texture(gl::TEXTURE_2D_ARRAY, out.texture);
+            gl::TexImage3D(gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT24 as GLint, resolution as GLsizei, resolution as GLsizei, layers as GLsizei, 0, gl::DEPTH_COMPONENT, gl::FLOAT, std::ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+            gl::TexParameterfv(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_BORDER_COLOR, [1f32; 4].as_ptr());
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+            let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+            gl::BindFramebuffer(gl::FRAMEBUFFER, out.framebuffer);
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, out.texture, 0, 0);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+        }
+        out
+    }
+
+    pub fn resolution(&self) -> u32 { self.resolution }
+
+    pub fn layers(&self) -> u32 { self.layers }
+
+    ///bind and clear a layer of the depth texture, the caller restores the framebuffer and viewport
+    pub fn begin(&self, layer: u32) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.texture, 0, layer.min(self.layers - 1) as GLint);
+            gl::Viewport(0, 0, self.resolution as GLsizei, self.resolution as GLsizei);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    pub fn bind_unit(&self, unit: usize) {
+        safe_calls::bind_texture_target(unit, gl::TEXTURE_2D_ARRAY, self.texture);
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+            safe_calls::forget_texture(self.texture);
+        }
+    }
+}
+
+//cube map of the distances to a point light divided by its far plane (samplerCubeShadow), every face is drawn by a single pass
+#[derive(Debug)]
+pub struct CubeShadowMap {
+    framebuffer: GLuint,
+    texture: GLuint,
+    resolution: u32
+}
+
+impl CubeShadowMap {
+    pub fn new(resolution: u32) -> Self {
+        let mut out = Self { framebuffer: 0, texture: 0, resolution };
+        unsafe {
+            gl::GenFramebuffers(1, &mut out.framebuffer);
+            gl::GenTextures(1, &mut out.texture);
+            safe_calls::edit_texture(gl::TEXTURE_CUBE_MAP, out.texture);
+            for face in 0..6 {
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, gl::DEPTH_COMPONENT24 as GLint, resolution as GLsizei, resolution as GLsizei, 0, gl::DEPTH_COMPONENT, gl::FLOAT, std::ptr::null());
+            }
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(gl::TEXTURE_CUBE_MAP, wrap, gl::CLAMP_TO_EDGE as GLint);
+            }
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+            let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+            gl::BindFramebuffer(gl::FRAMEBUFFER, out.framebuffer);
+            gl::FramebufferTexture(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, out.texture, 0);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+        }
+        out
+    }
+
+    pub fn resolution(&self) -> u32 { self.resolution }
+
+    ///bind and clear the 6 faces, the caller restores the framebuffer and viewport
+    pub fn begin(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, self.resolution as GLsizei, self.resolution as GLsizei);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    pub fn bind_unit(&self, unit: usize) {
+        safe_calls::bind_texture_target(unit, gl::TEXTURE_CUBE_MAP, self.texture);
+    }
+}
+
+impl Drop for CubeShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteTextures(1, &self.texture);
+            safe_calls::forget_texture(self.texture);
+        }
+    }
+}
+
+//instances drawn in a shadow map, sorted per model and lod in a single buffer
+#[derive(Debug, Default)]
+pub struct ShadowCasters {
+    instances: InstanceBuffer,
+    draws: Vec<ShadowDraw>,
+}
+
+impl ShadowCasters {
+    pub fn upload(&mut self, draws: Vec<ShadowDraw>, instances: &[InstanceData]) {
+        self.draws = draws;
+        self.instances.upload(instances);
+    }
+
+    pub fn draw(&self, resources: &ResourceManager) {
+        for &(model, lod, first, count) in &self.draws {
+            if let Some(mpm) = resources.get_multipart_model(model) {
+                mpm.draw_instances_from(&self.instances, first, count, lod);
+            }
+        }
+    }
+}
+
+//slice of the view frustum covered by a layer of the shadow map of a directional light, only drawn again when its light matrix or an instance changed
+#[derive(Debug, Default)]
+pub struct Cascade {
+    pub light_space: Mat4,
+    pub split: f32, //view depth of the end of the slice
+    pub casters: ShadowCasters
+}
+
+//cascaded shadow map of a directional light
+#[derive(Debug)]
+pub struct DirectionalShadow {
+    pub light: usize,
+    pub settings: ShadowSettings,
+    pub map: ShadowMap,
+    pub cascades: Vec<Cascade>
+}
+
+impl DirectionalShadow {
+    pub fn new(light: usize, settings: ShadowSettings) -> Self {
+        let layers = settings.cascades.clamp(1, MAX_CASCADES as u32);
+        Self {
+            light,
+            settings,
+            map: ShadowMap::new(settings.resolution, layers),
+            cascades: (0..layers).map(|_| Cascade::default()).collect()
+        }
+    }
+}
+
+//cube shadow map of a point light, only rebuilt when the light moved or an instance changed
+#[derive(Debug)]
+pub struct PointShadow {
+    pub light: usize,
+    pub settings: ShadowSettings,
+    pub map: CubeShadowMap,
+    pub position: Vec3,
+    pub far: f32, //range of the map (falloff of the light)
+    pub casters: ShadowCasters
+}
+
+impl PointShadow {
+    pub fn new(light: usize, settings: ShadowSettings) -> Self {
+        Self {
+            light,
+            settings,
+            map: CubeShadowMap::new(settings.resolution),
+            position: Vec3::splat(f32::NAN), //never equal, drawn at the first update
+            far: 0.,
+            casters: ShadowCasters::default()
+        }
+    }
+}
+
+///world corners of the part of a camera frustum between two view distances (near and far are the ones of its projection)
+///order: the 4 corners at `from` then the 4 at `to`
+pub fn frustum_slice(vp: &Mat4, near: f32, far: f32, from: f32, to: f32) -> [Vec3; 8] {
+    let inverse = vp.inverse().unwrap_or_else(Mat4::identity);
+    let mut edges = [(Vec3::default(), Vec3::default()); 4];
+    for (i, edge) in edges.iter_mut().enumerate() {
+        let (x, y) = (if i & 1 == 0 { -1. } else { 1. }, if i & 2 == 0 { -1. } else { 1. });
+        let corner = |z: f32| {
+            let p = inverse * Vec4::new(x, y, z, 1.);
+            p.resize::<3>() / p[3]
+        };
+        *edge = (corner(-1.), corner(1.));
+    }
+    //the view depth is linear along an edge of the frustum
+    let mut out = [Vec3::default(); 8];
+    for (i, (n, f)) in edges.into_iter().enumerate() {
+        out[i] = n + (f - n) * ((from - near) / (far - near));
+        out[i + 4] = n + (f - n) * ((to - near) / (far - near));
+    }
+    out
+}
+
+///rotation of a light looking towards a direction down its -z (any up vector not aligned with it)
+pub fn light_view(direction: &Vec3) -> Mat4 {
+    let up = if direction.normalize()[1].abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+    look_towards(direction, &up)
+}
+
+///rotation looking down -z towards a direction, with y as close as possible to an up vector
+pub fn look_towards(direction: &Vec3, up: &Vec3) -> Mat4 {
+    let back = -direction.normalize();
+    let right = up.cross_product(&back).normalize();
+    let up = back.cross_product(&right);
+    Mat4::from([
+        [right[0], right[1], right[2], 0.],
+        [up[0], up[1], up[2], 0.],
+        [back[0], back[1], back[2], 0.],
+        [0., 0., 0., 1.],
+    ])
+}
+
+///view projections of the faces of a cube map centered on a point, in the order and orientation of the gl cube map faces
+pub fn cube_faces(position: &Vec3, far: f32) -> [Mat4; 6] {
+    let projection = Mat4::projection(90f32.to_radians(), 1., (far * 0.001).min(0.1), far);
+    let translation = Mat4::from_pos(&-*position);
+    let faces = [(Vec3::X, -Vec3::Y), (-Vec3::X, -Vec3::Y), (Vec3::Y, Vec3::Z), (-Vec3::Y, -Vec3::Z), (Vec3::Z, -Vec3::Y), (-Vec3::Z, -Vec3::Y)];
+    faces.map(|(direction, up)| projection * look_towards(&direction, &up) * translation)
+}
+
+///view depth of the end of each cascade, blend between linear (0) and logarithmic (1) splits
+pub fn cascade_splits(near: f32, far: f32, cascades: u32, blend: f32) -> Vec<f32> {
+    let blend = blend.clamp(0., 1.);
+    (1..=cascades).map(|i| {
+        let t = i as f32 / cascades as f32;
+        blend * near * (far / near).powf(t) + (1. - blend) * (near + (far - near) * t)
+    }).collect()
+}
+
+///orthographic light matrix enclosing some world points, stretched towards the light to keep the casters of the bounds
+///the box is the bounding sphere of the points (same size whatever the rotation of the camera) moved by whole texels of the map
+///(same rasterization of the casters whatever its translation), so the edges of the shadows do not shimmer
+pub fn fit_directional(direction: &Vec3, points: &[Vec3], casters: Option<&Aabb>, resolution: u32) -> Mat4 {
+    let view = light_view(direction);
+    let to_light = |p: &Vec3| (view * p.extend(1.)).resize::<3>();
+    let center = points.iter().fold(Vec3::default(), |acc, p| acc + *p) / points.len().max(1) as f32;
+    let radius = points.iter().map(|p| (*p - center).dot(&(*p - center)).sqrt()).fold(0f32, f32::max);
+    let texel = 2. * radius / resolution.max(1) as f32;
+    let mut center = to_light(&center);
+    if texel > 0. {
+        center[0] = (center[0] / texel).floor() * texel;
+        center[1] = (center[1] / texel).floor() * texel;
+    }
+    let min = center - Vec3::splat(radius);
+    let mut max = center + Vec3::splat(radius);
+    if let Some(aabb) = casters {
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { aabb.min[0] } else { aabb.max[0] },
+                if i & 2 == 0 { aabb.min[1] } else { aabb.max[1] },
+                if i & 4 == 0 { aabb.min[2] } else { aabb.max[2] }
+            );
+            max[2] = max[2].max(to_light(&corner)[2]);
+        }
+    }
+    //the light looks down -z: the nearest points have the highest z
+    Mat4::orthographic_box(min[0], max[0], min[1], max[1], -max[2] - 1., -min[2] + 1.) * view
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::matrix::Mat4;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::{Vec3, Vec4};
+    use crate::opengl::lights::shadows::{cascade_splits, cube_faces, fit_directional, frustum_slice, light_view};
+    use crate::opengl::volume::Aabb;
+
+    #[test]
+    fn fitted_light() {
+        let vp = Mat4::projection(80f32.to_radians(), 16. / 9., 0.1, 1000.) * Transform::from_look_at(Vec3::new(0., 20., 20.), Vec3::default()).as_view_matrix();
+        let corners = frustum_slice(&vp, 0.1, 1000., 0.1, 50.);
+        let casters = Aabb { min: Vec3::splat(-500.), max: Vec3::splat(500.) };
+        let light = fit_directional(&Vec3::new(-1., -2., -3.), &corners, Some(&casters), 2048);
+        let clip = |p: &Vec3| {
+            let c = light * Vec4::new(p[0], p[1], p[2], 1.);
+            [c[0] / c[3], c[1] / c[3], c[2] / c[3]]
+        };
+        for corner in &corners {
+            assert!(clip(corner).iter().all(|v| v.abs() <= 1. + 1e-4), "{:?}", clip(corner));
+        }
+        //a caster between the light and the slice is kept in front of the near plane
+        let above = corners[0] + Vec3::new(1., 2., 3.) * 100.;
+        assert!(clip(&above)[2] >= -1. - 1e-4);
+        //the light looks down its -z
+        let forward = light_view(&Vec3::new(-1., -2., -3.)) * Vec4::new(-1., -2., -3., 0.);
+        assert!(forward[0].abs() < 1e-5 && forward[1].abs() < 1e-5 && forward[2] < 0.);
+        //the slice ends 50 units from the camera
+        let depth = (Transform::from_look_at(Vec3::new(0., 20., 20.), Vec3::default()).as_view_matrix() * Vec4::new(corners[4][0], corners[4][1], corners[4][2], 1.))[2];
+        assert!((depth + 50.).abs() < 0.1, "{depth}");
+        //the world origin lands on a texel corner of the map, wherever the camera is
+        let origin = clip(&Vec3::default());
+        for v in &origin[..2] {
+            assert!((v * 1024. - (v * 1024.).round()).abs() < 1e-2, "{origin:?}");
+        }
+    }
+
+    #[test]
+    fn splits() {
+        let linear = cascade_splits(1., 100., 4, 0.);
+        assert!(linear.iter().zip([25.75, 50.5, 75.25, 100.]).all(|(a, b)| (a - b).abs() < 1e-4), "{linear:?}");
+        let log = cascade_splits(1., 100., 2, 1.);
+        assert!((log[0] - 10.).abs() < 1e-4 && (log[1] - 100.).abs() < 1e-3, "{log:?}");
+    }
+
+    #[test]
+    fn cube_face_orientation() {
+        let position = Vec3::new(10., -5., 2.);
+        let faces = cube_faces(&position, 100.);
+        let ndc = |face: usize, d: Vec3| {
+            let c = faces[face] * Vec4::new(position[0] + d[0], position[1] + d[1], position[2] + d[2], 1.);
+            [c[0] / c[3], c[1] / c[3]]
+        };
+        //gl cube map conventions: +x face (s, t) = (-z, -y) / x, +y face (s, t) = (x, z) / y
+        let [x, y] = ndc(0, Vec3::new(4., 2., 1.));
+        assert!((x + 0.25).abs() < 1e-4 && (y + 0.5).abs() < 1e-4, "{x} {y}");
+        let [x, y] = ndc(2, Vec3::new(1., 4., 2.));
+        assert!((x - 0.25).abs() < 1e-4 && (y - 0.5).abs() < 1e-4, "{x} {y}");
+        let [x, y] = ndc(5, Vec3::new(1., 2., -4.));
+        assert!((x + 0.25).abs() < 1e-4 && (y + 0.5).abs() < 1e-4, "{x} {y}");
+    }
+}
@@ -1,60 +1,48 @@
+use crate::opengl::lights::LIGHTS_BINDING;
+use crate::opengl::lights::shadows::{CUBE_SHADOW_MAPS_UNIT, MAX_CUBE_SHADOW_MAPS, MAX_SHADOW_MAPS, SHADOW_MAPS_UNIT};
+use crate::opengl::material::{MATERIALS_BINDING, MAX_TEXTURES};
+use crate::opengl::ssao::AMBIENT_OCCLUSION_UNIT;
 use crate::opengl::shader::ShaderProgram;
 use crate::opengl::uniform::Uniform;
 
 #[derive(Debug)]
 pub struct MainShader {
     pub program: ShaderProgram,
-    
+
     pub projection: Uniform,
     pub camera: Uniform,
-    pub flags: Uniform,
-    pub object: Uniform,
-    
-    pub fade: Uniform,
-    
-    pub ambient: Uniform,
-    pub diffuse: Uniform,
-    pub transparency: Uniform,
-    pub specular_exponent: Uniform,
-    pub specular: Uniform,
-    pub emissive: Uniform,
-    pub bump: Uniform,
-    pub displacement: Uniform,
-    pub stencil: Uniform,
+    pub eye: Uniform, //world position of the camera, for the specular highlights
+
+    pub textures: Uniform,
+    pub shadow_maps: Uniform,
+    pub cube_shadow_maps: Uniform,
+    pub debug_cascades: Uniform,
+    pub weighted_transparency: Uniform, //write the accumulation targets of the weighted blended transparency
+    pub ambient_occlusion: Uniform, //screen space occlusion modulating the ambient term (opaque geometry only)
 }
 
 impl MainShader {
     pub fn new(program: ShaderProgram) -> Self {
-        Self {
+        let out = Self {
             projection: program.uniform("projection"),
             camera: program.uniform("camera"),
-            flags: program.uniform("flags"),
-            object: program.uniform("object"),
-            fade: program.uniform("fade"),
-            ambient: program.uniform("ambient"),
-            diffuse: program.uniform("diffuse"),
-            transparency: program.uniform("transparency"),
-            specular_exponent: program.uniform("specular_exponent"),
-            specular: program.uniform("specular"),
-            emissive: program.uniform("emissive"),
-            bump: program.uniform("bump"),
-            displacement: program.uniform("displacement"),
-            stencil: program.uniform("stencil"),
+            eye: program.uniform("eye"),
+            textures: program.uniform("textures"),
+            shadow_maps: program.uniform("shadow_maps"),
+            cube_shadow_maps: program.uniform("cube_shadow_maps"),
+            debug_cascades: program.uniform("debug_cascades"),
+            weighted_transparency: program.uniform("weighted_transparency"),
+            ambient_occlusion: program.uniform("use_ambient_occlusion"),
             program
-        }
+        };
+        //the samplers, the materials block and the lights block never change binding, models bind their textures and materials to the same units (the lights their shadow maps)
+        out.program.set_active();
+        out.textures.array_int(&(0..MAX_TEXTURES as i32).collect::<Vec<_>>());
+        out.shadow_maps.array_int(&(SHADOW_MAPS_UNIT as i32..(SHADOW_MAPS_UNIT + MAX_SHADOW_MAPS) as i32).collect::<Vec<_>>());
+        out.cube_shadow_maps.array_int(&(CUBE_SHADOW_MAPS_UNIT as i32..(CUBE_SHADOW_MAPS_UNIT + MAX_CUBE_SHADOW_MAPS) as i32).collect::<Vec<_>>());
+        out.program.uniform("ambient_occlusion").int(AMBIENT_OCCLUSION_UNIT as i32);
+        out.program.bind_uniform_block("Materials", MATERIALS_BINDING);
+        out.program.bind_uniform_block("Lights", LIGHTS_BINDING);
+        out
     }
-    
-    pub fn material_uniforms(&self) -> [Uniform; 9] {
-        [
-            self.ambient,
-            self.diffuse,
-            self.transparency,
-            self.specular_exponent,
-            self.specular,
-            self.emissive,
-            self.bump,
-            self.displacement,
-            self.stencil
-        ]
-    }
-}
\ No newline at end of file
+}
@@ -1,12 +1,18 @@
-use crate::opengl::main_shader::MainShader;
+use std::mem::size_of_val;
+use std::os::raw::c_void;
+use gl::types::{GLsizeiptr, GLuint};
 use crate::opengl::shader::ShaderProgram;
 use crate::opengl::texture::Texture;
 
-#[derive(Default, Debug, Clone)]
+pub const MAX_MATERIALS: usize = 64; //size of the Materials uniform block of the shaders
+pub const MAX_TEXTURES: usize = 16; //texture units bound for a single draw
+pub const MATERIALS_BINDING: GLuint = 0; //uniform block binding point of the Materials block
+
+#[derive(Debug, Clone)]
 pub struct Material {
     pub specular_exponent: f32,
     pub density: f32,
-    pub transparency: f32,
+    pub transparency: f32, //opacity (d), 1: opaque
     pub filter: [f32; 3],
     pub ambient: [f32; 3],
     pub diffuse: [f32; 3],
@@ -24,7 +30,37 @@ pub struct Material {
     pub stencil_map: usize,
 }
 
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            specular_exponent: 0.,
+            density: 1.,
+            transparency: 1.,
+            filter: [1.; 3],
+            ambient: [0.; 3],
+            diffuse: [0.; 3],
+            specular: [0.; 3],
+            emissive: [0.; 3],
+            illum: 0,
+            ambient_map: 0,
+            diffuse_map: 0,
+            transparency_map: 0,
+            specular_exponent_map: 0,
+            specular_map: 0,
+            emissive_map: 0,
+            bump_map: 0,
+            displacement_map: 0,
+            stencil_map: 0
+        }
+    }
+}
+
 impl Material {
+    ///drawn after the opaque geometry, blended and sorted
+    pub fn is_transparent(&self) -> bool {
+        self.transparency < 1. || self.transparency_map != 0
+    }
+
     pub fn maps(&self) -> [usize; 9] {
         [
             self.ambient_map,
@@ -44,10 +80,73 @@ impl Material {
             textures[*t].bake();
         }
     }
+}
+
+//std140 layout of a material in the Materials uniform block
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MaterialData {
+    pub ambient: [f32; 4],
+    pub diffuse: [f32; 4], //w: transparency
+    pub specular: [f32; 4], //w: specular exponent
+    pub emissive: [f32; 4],
+    pub maps: [i32; 4], //texture units of the ambient, diffuse, specular and emissive maps
+    pub alpha_map: [i32; 4] //x: texture unit of the transparency map (-1: none)
+}
+
+impl MaterialData {
+    ///with the texture unit each map of the model is bound to
+    pub fn new(value: &Material, unit: impl Fn(usize) -> i32) -> Self {
+        Self {
+            ambient: [value.ambient[0], value.ambient[1], value.ambient[2], 1.],
+            diffuse: [value.diffuse[0], value.diffuse[1], value.diffuse[2], value.transparency],
+            specular: [value.specular[0], value.specular[1], value.specular[2], value.specular_exponent],
+            emissive: [value.emissive[0], value.emissive[1], value.emissive[2], 0.],
+            maps: [unit(value.ambient_map), unit(value.diffuse_map), unit(value.specular_map), unit(value.emissive_map)],
+            alpha_map: [if value.transparency_map == 0 { -1 } else { unit(value.transparency_map) }, 0, 0, 0]
+        }
+    }
+}
+
+impl From<&Material> for MaterialData {
+    ///maps past MAX_TEXTURES use the last unit
+    fn from(value: &Material) -> Self {
+        Self::new(value, |map| map.min(MAX_TEXTURES - 1) as i32)
+    }
+}
+
+//uniform buffer holding the materials of a model, indexed by the per vertex material of its mesh
+#[derive(Debug, Default)]
+pub struct MaterialBuffer {
+    ubo: GLuint
+}
+
+impl MaterialBuffer {
+    ///materials past MAX_MATERIALS are dropped, the buffer is padded with default materials to the size of the block
+    pub fn from_data(mut data: Vec<MaterialData>) -> Self {
+        data.truncate(MAX_MATERIALS);
+        data.resize(MAX_MATERIALS, MaterialData::from(&Material::default()));
+        let mut ubo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut ubo);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, ubo);
+            gl::BufferData(gl::UNIFORM_BUFFER, size_of_val(data.as_slice()) as GLsizeiptr, data.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        }
+        Self { ubo }
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, MATERIALS_BINDING, self.ubo);
+        }
+    }
+}
 
-    pub fn bind(&self, textures: &Vec<Texture>, shader: &MainShader) {
-        for (i, (t, u)) in self.maps().iter().zip(shader.material_uniforms()).enumerate() {
-            textures[*t].bind(i, u);
+impl Drop for MaterialBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.ubo);
         }
     }
 }
\ No newline at end of file
@@ -11,5 +11,18 @@ pub mod scene;
 pub mod lights;
 pub mod buffers;
 pub mod frustrum;
+pub mod volume;
+pub mod visibility;
+pub mod bvh;
+pub mod ray;
+pub mod picking;
+pub mod instances;
+pub mod indirect;
+pub mod culling;
+pub mod transparency;
+pub mod framebuffer;
+pub mod post;
+pub mod ssao;
 mod main_shader;
 mod single_vao_object;
+pub mod deferred;
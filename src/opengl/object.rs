@@ -1,28 +1,75 @@
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use crate::maths::matrix::Mat4;
 use crate::maths::transform::Transform;
 use crate::maths::vector::Vec3;
 use crate::opengl::buffers::{GPUBuffers, VertexType};
-use crate::opengl::frustrum::{Frustrum, Volume};
+use crate::opengl::frustrum::Frustrum;
+use crate::opengl::instances::{InstanceBuffer, InstanceData};
 use crate::opengl::main_shader::MainShader;
-use crate::opengl::material::Material;
+use crate::opengl::material::{Material, MaterialBuffer, MaterialData, MAX_MATERIALS, MAX_TEXTURES};
+use crate::opengl::ray::{Hit, Ray};
 use crate::opengl::texture::Texture;
+use crate::opengl::volume::{Aabb, Sphere, Volume};
+use crate::mesh::Mesh;
 use crate::other::resource_manager::ResourceManager;
 use crate::parser::ParsedObject;
 
+//the whole model at a lod level: the parts merged in a single vao, each vertex knows the material of its part
+//the opaque parts come first, the transparent ones are drawn after every opaque model
 #[derive(Debug)]
-struct Part {
-    material: usize,
+struct Level {
     len: usize,
+    opaque: usize, //indices of the opaque parts
+    parts: Vec<Range<usize>>, //indices of each part
+    runs: Vec<(usize, Range<usize>)>, //indices using the same material block, a draw each
     buffers: GPUBuffers,
+    mesh: Mesh, //merged geometry kept cpu side to be packed with other models (see indirect)
+    materials: Vec<i32> //per vertex, index in the materials of the model (the vao gets the slot in the block)
+}
+
+//materials and textures bound together for a draw, a model over MAX_MATERIALS or MAX_TEXTURES is split in several blocks
+#[derive(Debug)]
+struct MaterialBlock {
+    textures: Vec<usize>, //texture of the model bound to each unit, the palette (no map) always on unit 0
+    buffer: MaterialBuffer
+}
+
+#[derive(Debug)]
+struct Part {
+    material: usize,
+    mesh: Mesh,
     volume: Volume
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct LodSettings {
+    pub levels: usize, //amount of simplified levels generated after the full detail one
+    pub ratio: f32, //fraction of the triangles kept from one level to the next
+    pub screen_size: f32, //projected height (fraction of the screen) under which the first simplified level is used, halved for each next level
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            levels: 3,
+            ratio: 0.5,
+            screen_size: 0.25
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct MultiPartModel {
     textures: Vec<Texture>,
     materials: Vec<Material>,
-    parts: Vec<Part>
+    parts: Vec<Part>,
+    levels: Vec<Level>, //level 0 is the full detail model, the next ones are simplified versions of the previous
+    lod_sizes: Vec<f32>,
+    instances: InstanceBuffer,
+    blocks: Vec<MaterialBlock>,
+    slots: Vec<(usize, usize)> //per material: block, index in the buffer of the block
 }
 
 impl Eq for MultiPartModel {}
@@ -45,26 +92,24 @@ impl Hash for MultiPartModel {
 
 impl MultiPartModel {
     pub fn from_raw(vertices: Vec<[f32; 3]>, indices: Vec<u32>) -> Self {
-        let mut parts = Vec::new();
-        let mut buffers = GPUBuffers::new().unwrap();
-        buffers.new_vbo(0, VertexType::Vec3);
-        let len = indices.len();
-        buffers.set_ebo(indices);
-        let mut volume = Volume::default();
-        for v in &vertices {
-            volume.expand(&Vec3::from(*v));
-        }
-        buffers.set_vbo(0, vertices);
-        parts.push(Part {
-            material: 0,
-            len,
-            buffers,
-            volume,
-        });
+        let mesh = Mesh {
+            colors: vec![[0.; 3]; vertices.len()],
+            uvs: vec![[0.; 3]; vertices.len()],
+            normals: vec![[0.; 3]; vertices.len()],
+            positions: vertices,
+            indices,
+        };
+        let part = Part::new(0, mesh);
+        let (blocks, slots) = MaterialBlock::split(&[]);
         Self {
             textures: Vec::new(),
             materials: Vec::new(),
-            parts
+            levels: vec![Level::upload([(&part.mesh, part.material, false)].into_iter(), &[])],
+            parts: vec![part],
+            lod_sizes: Vec::new(),
+            instances: InstanceBuffer::new(),
+            blocks,
+            slots
         }
     }
 
@@ -74,75 +119,7 @@ impl MultiPartModel {
         texture_map.insert("".to_string(), 0);
         out.textures.push(Texture::palette());
         for &[material, start, end] in &parsed.groups {
-            let mut buffers = GPUBuffers::new().unwrap();
-            let mut indexes = Vec::new();
-            let mut vertices: Vec<f32> = Vec::new();
-            let mut colors: Vec<f32> = Vec::new();
-            let mut uvs: Vec<f32> = Vec::new();
-            let mut normals: Vec<f32> = Vec::new();
-            let mut volume = Volume::default();
-            // buffers.new_mingled_vbo(0, 0, VertexType::Vec3, 48, 0);
-            // buffers.new_mingled_vbo(0, 1, VertexType::Vec3, 48, 12);
-            // buffers.new_mingled_vbo(0, 2, VertexType::Vec3, 48, 24);
-            // buffers.new_mingled_vbo(0, 3, VertexType::Vec3, 48, 36);
-            buffers.new_vbo(0, VertexType::Vec3);
-            buffers.new_vbo(1, VertexType::Vec3);
-            buffers.new_vbo(2, VertexType::Vec3);
-            buffers.new_vbo(3, VertexType::Vec3);
-            for f in start..=end {
-                let face = &parsed.faces[f];
-                let pf = vertices.len() /* / 12 */ / 3;
-                for vf in face {
-                    let (v, c) = if vf[0] > 0 && vf[0] <= parsed.vertexes.len() {
-                        (parsed.vertexes[vf[0] - 1].pos, parsed.vertexes[vf[0] - 1].color)
-                    } else {
-                        ([0., 0., 0.], [0., 0., 0.])
-                    };
-                    for i in 0..3 {
-                        vertices.push(v[i]);
-                    }
-                    volume.expand(&Vec3::from(v));
-                    for i in 0..3 {
-                        // vertices.push(c[i]);
-                        colors.push(c[i]);
-                    }
-                    let u = if vf[1] > 0 && vf[1] <= parsed.uvs.len() {
-                        parsed.uvs[vf[1] - 1]
-                    } else {
-                        [0., 0., 0.]
-                    };
-                    for i in 0..3 {
-                        // vertices.push(u[i]);
-                        uvs.push(u[i]);
-                    }
-                    let n = if vf[2] > 0 && vf[2] <= parsed.normals.len() {
-                        parsed.normals[vf[2] - 1]
-                    } else {
-                        [0., 0., 0.]
-                    };
-                    for i in 0..3 {
-                        // vertices.push(n[i]);
-                        normals.push(n[i]);
-                    }
-                }
-                for step in 0..(face.len() - 2) {
-                    for i in 0..3 {
-                        indexes.push((pf + if i == 0 { 0 } else { i + step }) as u32);
-                    }
-                }
-            }
-            buffers.set_vbo(0, vertices);
-            buffers.set_vbo(1, colors);
-            buffers.set_vbo(2, uvs);
-            buffers.set_vbo(3, normals);
-            let len = indexes.len();
-            buffers.set_ebo(indexes);
-            out.parts.push(Part {
-                material,
-                len,
-                buffers,
-                volume,
-            });
+            out.parts.push(Part::new(material, Mesh::from_faces(parsed, start..=end)));
         }
         for material in parsed.materials.iter() {
             let p = &parsed.libs.0[material];
@@ -188,26 +165,409 @@ impl MultiPartModel {
             }
             out.materials.push(mat);
         }
+        out.textures.iter_mut().for_each(|t| t.bake());
+        (out.blocks, out.slots) = MaterialBlock::split(&out.materials);
+        out.levels = vec![Level::upload(out.parts.iter().map(|p| (&p.mesh, p.material, out.is_transparent(p.material))), &out.slots)];
         out
     }
     
+    ///replace the simplified levels by a new chain, per part levels that would not remove at least a tenth of the triangles are skipped (the part keeps its previous level)
+    pub fn generate_lods(&mut self, settings: LodSettings) {
+        self.levels.truncate(1);
+        let mut chains = Vec::with_capacity(self.parts.len());
+        for part in &self.parts {
+            let mut chain: Vec<Mesh> = Vec::new();
+            for _ in 0..settings.levels {
+                let mesh = chain.last().unwrap_or(&part.mesh);
+                let target = (mesh.triangle_count() as f32 * settings.ratio) as usize;
+                let next = mesh.simplify(target);
+                if next.triangle_count() * 10 > mesh.triangle_count() * 9 {
+                    break;
+                }
+                chain.push(next);
+            }
+            chains.push(chain);
+        }
+        let depth = chains.iter().map(Vec::len).max().unwrap_or(0);
+        for level in 0..depth {
+            let meshes = self.parts.iter().zip(&chains).map(|(p, c)| (c.get(level).or(c.last()).unwrap_or(&p.mesh), p.material, self.is_transparent(p.material)));
+            self.levels.push(Level::upload(meshes, &self.slots));
+        }
+        self.lod_sizes = (0..settings.levels).map(|l| settings.screen_size / (1 << l) as f32).collect();
+    }
+
+    ///level to use for an instance covering this fraction of the screen height
+    pub fn select_lod(&self, screen_size: f32) -> usize {
+        self.lod_sizes.iter().filter(|s| screen_size < **s).count()
+    }
+
+    pub fn lod_count(&self) -> usize {
+        self.lod_sizes.len() + 1
+    }
+
+    ///screen sizes under which the next lod level is used
+    pub fn lod_sizes(&self) -> &[f32] { &self.lod_sizes }
+
+    pub fn radius(&self) -> f32 {
+        self.parts.iter().fold(0., |acc, p| acc.max(p.volume.radius()))
+    }
+
+    ///model space box containing all the parts
+    pub fn bounds(&self) -> Aabb {
+        let mut parts = self.parts.iter().map(|p| p.volume.aabb);
+        let first = parts.next().unwrap_or_default();
+        parts.fold(first, |acc, aabb| acc.union(&aabb))
+    }
+
+    ///model space sphere containing the spheres of all the parts (centered on the bounds)
+    pub fn bounding_sphere(&self) -> Sphere {
+        let center = self.bounds().center();
+        let radius = self.parts.iter().fold(0f32, |acc, p| {
+            let d = p.volume.sphere.center - center;
+            acc.max(d.dot(&d).sqrt() + p.volume.sphere.radius)
+        });
+        Sphere { center, radius }
+    }
+
     pub fn visible(&self, transform: &Transform, frustrum: &Frustrum) -> bool {
         for Part { volume, .. } in &self.parts {
             if frustrum.has_volume(transform, volume) {
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    pub fn draw_instances(&self, count: usize, shader: Option<&MainShader>) {
-        for Part { material, len, buffers, .. } in &self.parts {
-            if let Some(shader) = shader {
-                if *material < self.materials.len() {
-                    self.materials[*material].bind(&self.textures, shader);
+    ///closest triangle (full detail meshes) crossed by a world ray, the instance of the hit is left to the caller
+    pub fn raycast(&self, ray: &Ray, transform: &Transform, max_distance: f32) -> Option<Hit> {
+        let inverse = Mat4::from(transform).inverse()?;
+        let local = ray.transformed(&inverse);
+        let mut best: Option<(usize, usize, f32, f32, f32)> = None;
+        for (index, Part { mesh, volume, .. }) in self.parts.iter().enumerate() {
+            let limit = best.map_or(max_distance, |b| b.2);
+            if local.sphere(&volume.sphere).is_none_or(|d| d > limit) || local.aabb(&volume.aabb).is_none_or(|d| d > limit) {
+                continue;
+            }
+            for triangle in 0..mesh.triangle_count() {
+                let [a, b, c] = mesh.triangle(triangle).map(|i| Vec3::from(mesh.positions[i]));
+                if let Some((t, u, v)) = local.triangle(&a, &b, &c) {
+                    if t <= best.map_or(max_distance, |b| b.2) {
+                        best = Some((index, triangle, t, u, v));
+                    }
                 }
             }
-            buffers.draw_instances(gl::TRIANGLES, 0, *len, count);
         }
+        let (part, triangle, distance, u, v) = best?;
+        let mesh = &self.parts[part].mesh;
+        let corners = mesh.triangle(triangle);
+        let barycentrics = [1. - u - v, u, v];
+        let mut normal = Vec3::default();
+        for (i, w) in corners.iter().zip(barycentrics) {
+            normal += Vec3::from(mesh.normals[*i]) * w;
+        }
+        if normal.dot(&normal) < 1e-12 {
+            let [a, b, c] = corners.map(|i| Vec3::from(mesh.positions[i]));
+            normal = (b - a).cross_product(&(c - a));
+        }
+        //normals are transformed by the transpose of the inverse
+        let mut world = Vec3::default();
+        for r in 0..3 {
+            world[r] = (0..3).map(|c| inverse[(r, c)] * normal[c]).sum();
+        }
+        Some(Hit {
+            instance: 0,
+            part,
+            triangle,
+            barycentrics,
+            distance,
+            point: ray.at(distance),
+            normal: world.normalize()
+        })
+    }
+
+    ///replace the instances streamed to the vaos of the parts
+    pub fn upload_instances(&self, instances: &[InstanceData]) {
+        self.instances.upload(instances);
+    }
+
+    ///amount of distinct geometry levels (lod_count can be higher if some levels could not be simplified)
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    ///merged geometry of a lod level and the material of each vertex
+    pub fn level_geometry(&self, lod: usize) -> (&Mesh, &[i32]) {
+        let level = &self.levels[lod.min(self.levels.len() - 1)];
+        (&level.mesh, &level.materials)
+    }
+
+    ///indices of the opaque parts of a lod level, at the start of its geometry
+    pub fn opaque_len(&self, lod: usize) -> usize {
+        self.levels[lod.min(self.levels.len() - 1)].opaque
+    }
+
+    pub fn triangle_count(&self, lod: usize) -> usize {
+        self.levels[lod.min(self.levels.len() - 1)].len / 3
+    }
+
+    pub fn transparent_triangle_count(&self, lod: usize) -> usize {
+        let level = &self.levels[lod.min(self.levels.len() - 1)];
+        (level.len - level.opaque) / 3
+    }
+
+    fn is_transparent(&self, material: usize) -> bool {
+        self.materials.get(material).is_some_and(Material::is_transparent)
+    }
+
+    ///some parts are drawn in the transparent pass
+    pub fn has_transparency(&self) -> bool {
+        self.levels.first().is_some_and(|level| level.opaque < level.len)
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn textures(&self) -> &[Texture] {
+        &self.textures
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    ///amount of material blocks the model is drawn with (more than one past MAX_MATERIALS or MAX_TEXTURES)
+    pub fn material_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn bind_block(&self, block: usize) {
+        let MaterialBlock { textures, buffer } = &self.blocks[block];
+        buffer.bind();
+        for (unit, texture) in textures.iter().filter_map(|t| self.textures.get(*t)).enumerate() {
+            texture.bind_unit(unit);
+        }
+    }
+
+    ///draw the indices of a level in this range with their materials, a draw per block
+    fn draw_runs(&self, level: &Level, indices: Range<usize>, count: usize) {
+        for (block, run) in &level.runs {
+            let (start, end) = (run.start.max(indices.start), run.end.min(indices.end));
+            if start < end {
+                self.bind_block(*block);
+                level.buffers.draw_instances(gl::TRIANGLES, start, end - start, count);
+            }
+        }
+    }
+
+    ///draw a range of the uploaded instances with a lod level, all the parts in a single instanced draw (one per material block with a shader)
+    ///with a shader only the opaque parts are drawn (see draw_transparent_from), without one every part is (ex: ids, depth)
+    pub fn draw_instances(&self, first: usize, count: usize, shader: Option<&MainShader>, lod: usize) {
+        let level = &self.levels[lod.min(self.levels.len() - 1)];
+        self.instances.attach(&level.buffers, first);
+        if shader.is_some() {
+            self.draw_runs(level, 0..level.opaque, count);
+        } else {
+            level.buffers.draw_instances(gl::TRIANGLES, 0, level.len, count);
+        }
+    }
+
+    ///draw the opaque parts of the instances without the materials (ex: depth prepass)
+    pub fn draw_opaque_instances(&self, first: usize, count: usize, lod: usize) {
+        let Level { opaque, buffers, .. } = &self.levels[lod.min(self.levels.len() - 1)];
+        self.instances.attach(buffers, first);
+        buffers.draw_instances(gl::TRIANGLES, 0, *opaque, count);
+    }
+
+    ///draw the transparent parts of instances of another buffer (ex: sorted back to front), with the materials
+    pub fn draw_transparent_from(&self, instances: &InstanceBuffer, first: usize, count: usize, lod: usize) {
+        let level = &self.levels[lod.min(self.levels.len() - 1)];
+        instances.attach(&level.buffers, first);
+        self.draw_runs(level, level.opaque..level.len, count);
+    }
+
+    ///draw instances of another buffer (ex: casters of a shadow map), without the materials
+    pub fn draw_instances_from(&self, instances: &InstanceBuffer, first: usize, count: usize, lod: usize) {
+        let Level { len, buffers, .. } = &self.levels[lod.min(self.levels.len() - 1)];
+        instances.attach(buffers, first);
+        buffers.draw_instances(gl::TRIANGLES, 0, *len, count);
     }
-}
\ No newline at end of file
+
+    ///same as draw_instances restricted to some parts (ex: per part visibility), one draw per part
+    pub fn draw_part_instances(&self, parts: &[usize], first: usize, count: usize, shader: Option<&MainShader>, lod: usize) {
+        let level = &self.levels[lod.min(self.levels.len() - 1)];
+        self.instances.attach(&level.buffers, first);
+        for (part, range) in parts.iter().filter_map(|p| Some((self.parts.get(*p)?, level.parts.get(*p)?))) {
+            if shader.is_some() {
+                self.bind_block(self.slots.get(part.material).map_or(0, |s| s.0));
+            }
+            level.buffers.draw_instances(gl::TRIANGLES, range.start, range.len(), count);
+        }
+    }
+}
+
+impl MaterialBlock {
+    ///pack the materials in order, a new block is started when the next one (or its maps) does not fit in the current one
+    fn split(materials: &[Material]) -> (Vec<Self>, Vec<(usize, usize)>) {
+        let mut blocks: Vec<(Vec<usize>, Vec<&Material>)> = vec![(vec![0], Vec::new())];
+        let mut slots = Vec::with_capacity(materials.len());
+        for material in materials {
+            //the maps read by the shaders
+            let maps = [material.ambient_map, material.diffuse_map, material.specular_map, material.emissive_map, material.transparency_map];
+            let missing = |textures: &[usize]| {
+                let mut missing = maps.to_vec();
+                missing.sort();
+                missing.dedup();
+                missing.retain(|m| !textures.contains(m));
+                missing
+            };
+            let (textures, block) = blocks.last().unwrap();
+            if block.len() == MAX_MATERIALS || textures.len() + missing(textures).len() > MAX_TEXTURES {
+                blocks.push((vec![0], Vec::new()));
+            }
+            let index = blocks.len() - 1;
+            let (textures, block) = &mut blocks[index];
+            textures.extend(missing(textures));
+            slots.push((index, block.len()));
+            block.push(material);
+        }
+        let blocks = blocks.into_iter().map(|(textures, block)| {
+            let unit = |map: usize| textures.iter().position(|t| *t == map).unwrap_or(0) as i32;
+            let buffer = MaterialBuffer::from_data(block.into_iter().map(|m| MaterialData::new(m, unit)).collect());
+            Self { textures, buffer }
+        }).collect();
+        (blocks, slots)
+    }
+}
+
+impl Level {
+    ///merge the meshes of the parts with their material, the transparent parts after the opaque ones, grouped by material block
+    fn upload<'a>(parts: impl Iterator<Item = (&'a Mesh, usize, bool)>, slots: &[(usize, usize)]) -> Self {
+        let mut merged = Mesh::default();
+        let mut materials = Vec::new();
+        let mut vertex_slots = Vec::new();
+        let slot = |material: usize| slots.get(material).copied().unwrap_or_default();
+        let mut parts = parts.enumerate().collect::<Vec<_>>();
+        parts.sort_by_key(|(_, (_, material, transparent))| (*transparent, slot(*material).0));
+        let mut ranges = vec![0..0; parts.len()];
+        let mut runs: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut opaque = 0;
+        for (index, (mesh, material, transparent)) in parts {
+            let base = merged.positions.len() as u32;
+            let start = merged.indices.len();
+            merged.positions.extend_from_slice(&mesh.positions);
+            merged.colors.extend_from_slice(&mesh.colors);
+            merged.uvs.extend_from_slice(&mesh.uvs);
+            merged.normals.extend_from_slice(&mesh.normals);
+            merged.indices.extend(mesh.indices.iter().map(|i| i + base));
+            materials.resize(merged.positions.len(), material as i32);
+            let (block, index_in_block) = slot(material);
+            vertex_slots.resize(merged.positions.len(), index_in_block as i32);
+            ranges[index] = start..merged.indices.len();
+            match runs.last_mut() {
+                Some((b, run)) if *b == block => run.end = merged.indices.len(),
+                _ => runs.push((block, start..merged.indices.len()))
+            }
+            if !transparent {
+                opaque = merged.indices.len();
+            }
+        }
+        let mut buffers = GPUBuffers::new().unwrap();
+        buffers.new_vbo(0, VertexType::Vec3);
+        buffers.new_vbo(1, VertexType::Vec3);
+        buffers.new_vbo(2, VertexType::Vec3);
+        buffers.new_vbo(3, VertexType::Vec3);
+        buffers.new_vbo(4, VertexType::Int);
+        buffers.set_vbo(0, &merged.positions);
+        buffers.set_vbo(1, &merged.colors);
+        buffers.set_vbo(2, &merged.uvs);
+        buffers.set_vbo(3, &merged.normals);
+        buffers.set_vbo(4, &vertex_slots);
+        buffers.set_ebo(&merged.indices);
+        Self {
+            len: merged.indices.len(),
+            opaque,
+            parts: ranges,
+            runs,
+            buffers,
+            mesh: merged,
+            materials
+        }
+    }
+}
+
+impl Part {
+    fn new(material: usize, mesh: Mesh) -> Self {
+        Self {
+            volume: Volume::from_points(&mesh.positions),
+            material,
+            mesh
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt::Write;
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::framebuffer::RenderTarget;
+    use crate::opengl::material::{Material, MAX_MATERIALS, MAX_TEXTURES};
+    use crate::opengl::object::MaterialBlock;
+    use crate::opengl::safe_calls;
+    use crate::opengl::scene::{ObjectData, Scene};
+    use crate::opengl::shader::ShaderProgram;
+    use crate::other::resource_manager::ResourceManager;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn split_in_material_blocks() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        //a map each: the palette and 15 maps fill the units of the first block
+        let materials = (1..=20).map(|map| Material { diffuse_map: map, ..Default::default() }).collect::<Vec<_>>();
+        let (blocks, slots) = MaterialBlock::split(&materials);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].textures, (0..MAX_TEXTURES).collect::<Vec<_>>());
+        assert_eq!(blocks[1].textures, [0, 16, 17, 18, 19, 20]);
+        assert_eq!((slots[14], slots[15]), ((0, 14), (1, 0)));
+        //a grid of quads with an emissive material each, red for the first block and green past it
+        let quads = MAX_MATERIALS + 8;
+        let directory = std::env::temp_dir().join(format!("scop_blocks_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (mut obj, mut mtl) = ("mtllib blocks.mtl\n".to_string(), String::new());
+        for i in 0..quads {
+            let (x, y) = ((i % 8) as f32 - 4., (i / 8) as f32 - 4.5);
+            writeln!(mtl, "newmtl color{i}\nKa 0 0 0\nKe {}", if i < MAX_MATERIALS { "1 0 0" } else { "0 1 0" }).unwrap();
+            writeln!(obj, "v {x} {y} 0\nv {} {y} 0\nv {} {} 0\nv {x} {} 0\nvn 0 0 1", x + 0.9, x + 0.9, y + 0.9, y + 0.9).unwrap();
+            let (v, n) = (i * 4 + 1, i + 1);
+            writeln!(obj, "usemtl color{i}\nf {v}//{n} {}//{n} {}//{n}\nf {v}//{n} {}//{n} {}//{n}", v + 1, v + 2, v + 2, v + 3).unwrap();
+        }
+        std::fs::write(directory.join("blocks.obj"), obj).unwrap();
+        std::fs::write(directory.join("blocks.mtl"), mtl).unwrap();
+        let mut resources = ResourceManager::default();
+        resources.register_hints(&["resources", "resources/shaders", directory.to_str().unwrap()]);
+        let program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
+        let mut scene = Scene::new(program);
+        let (model, _) = resources.load_multipart_model("blocks".to_string()).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(resources.get_multipart_model(model).unwrap().material_blocks(), 2);
+        scene.spawn_object(model, ObjectData::from(Transform::from_look_towards(Vec3::default(), -Vec3::Z)));
+        scene.set_post_processing(false);
+        scene.set_camera(Transform::from_look_at(Vec3::new(0., 0., 8.), Vec3::default()));
+        scene.set_projection(80., 1.);
+        let size = 64;
+        let target = RenderTarget::default().color(gl::RGBA8).depth(gl::DEPTH_COMPONENT24).build((size, size));
+        target.bind();
+        safe_calls::set_clear_color(0., 0., 0.);
+        safe_calls::set_depth_test(true);
+        safe_calls::clear_screen();
+        scene.draw(&resources);
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+        let mut pixels = vec![0u8; (size * size * 4) as usize];
+        unsafe { gl::ReadPixels(0, 0, size as i32, size as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr().cast()); }
+        //the last row is drawn with the materials of the second block instead of the last material of the first
+        let red = pixels.chunks(4).filter(|p| p[0] > 200 && p[1] < 50).count();
+        let green = pixels.chunks(4).filter(|p| p[1] > 200 && p[0] < 50).count();
+        assert!(red > green * 4 && green > 0, "red: {red}, green: {green}");
+    }
+}
@@ -0,0 +1,18 @@
+#version 330 core
+
+uniform sampler2D accumulation; //rgb: sum of the weighted colors, a: product of the transparencies
+uniform sampler2D weights; //r: sum of the weighted alphas
+
+layout (location = 0) out vec4 output_color;
+layout (location = 1) out vec4 emissive_output; //the emission behind is covered like the colors
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 accumulated = texelFetch(accumulation, texel, 0);
+    if (accumulated.a >= 1.) { //nothing transparent on this pixel
+        discard;
+    }
+    float weight = texelFetch(weights, texel, 0).r;
+    output_color = vec4(accumulated.rgb / max(weight, 0.00001), 1. - accumulated.a);
+    emissive_output = vec4(0, 0, 0, output_color.a);
+}
\ No newline at end of file
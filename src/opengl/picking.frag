@@ -1,9 +1,9 @@
 #version 330 core
 
-in vec4 v_id;
+flat in uint v_id;
 
-out vec4 output_color;
+out uint output_id;
 
 void main() {
-    output_color = v_id;
+    output_id = v_id;
 }
\ No newline at end of file
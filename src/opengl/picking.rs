@@ -0,0 +1,136 @@
+use std::os::raw::c_void;
+use gl::types::{GLint, GLsizei};
+use crate::maths::matrix::Mat4;
+use crate::opengl::enums::Shaders;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+
+//offscreen id buffer: each pixel stores the index (+1, 0 is nothing) of the instance drawn on it
+#[derive(Debug)]
+pub struct PickingHandler {
+    pub shader: ShaderProgram,
+    pub camera_uniform: Uniform,
+    pub projection_uniform: Uniform,
+    pub id_uniform: Uniform,
+    framebuffer: Framebuffer //R32UI ids, depth renderbuffer
+}
+
+impl PickingHandler {
+    pub fn new() -> Self {
+        let shader = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("picking.vert"))
+            .add_shader(Shaders::Fragment, include_str!("picking.frag"))
+            .build().unwrap();
+        Self {
+            camera_uniform: shader.uniform("camera"),
+            projection_uniform: shader.uniform("projection"),
+            id_uniform: shader.uniform("id"),
+            shader,
+            framebuffer: RenderTarget::default().color(gl::R32UI).depth(gl::DEPTH_COMPONENT24).build((1, 1))
+        }
+    }
+
+    ///bind and clear the id buffer, following draws write ids instead of colors
+    ///the matrices are only sent when picking, the camera can move every frame without switching programs
+    pub fn begin(&mut self, projection: Mat4, camera: Mat4) {
+        self.framebuffer.resize(safe_calls::get_size());
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer.id());
+            gl::ClearBufferuiv(gl::COLOR, 0, [0u32; 4].as_ptr());
+            gl::ClearBufferfv(gl::DEPTH, 0, &1f32);
+        }
+        self.shader.set_active();
+        self.projection_uniform.mat4(projection);
+        self.camera_uniform.mat4(camera);
+    }
+
+    ///back to the default framebuffer
+    pub fn end(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    ///ids of a region (origin at the bottom left, clamped to the buffer), row by row from the bottom
+    pub fn read(&self, x: usize, y: usize, width: usize, height: usize) -> (Vec<u32>, usize, usize) {
+        let size = self.framebuffer.size();
+        let (x, y) = (x.min(size.0 as usize), y.min(size.1 as usize));
+        let width = width.min(size.0 as usize - x);
+        let height = height.min(size.1 as usize - y);
+        let mut out = vec![0u32; width * height];
+        if !out.is_empty() {
+            unsafe {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer.id());
+                gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+                gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+                gl::ReadPixels(x as GLint, y as GLint, width as GLsizei, height as GLsizei, gl::RED_INTEGER, gl::UNSIGNED_INT, out.as_mut_ptr() as *mut c_void);
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            }
+        }
+        (out, width, height)
+    }
+}
+
+///even-odd rule
+pub fn inside_polygon(point: [f32; 2], polygon: &[[f32; 2]]) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a[1] > point[1]) != (b[1] > point[1]) && point[0] < a[0] + (point[1] - a[1]) * (b[0] - a[0]) / (b[1] - a[1]) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::picking::inside_polygon;
+    use crate::opengl::scene::{ObjectData, Scene};
+    use crate::opengl::shader::ShaderProgram;
+    use crate::other::resource_manager::ResourceManager;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn lasso() {
+        //concave "L" shape
+        let l = [[0., 0.], [4., 0.], [4., 1.], [1., 1.], [1., 4.], [0., 4.]];
+        assert!(inside_polygon([0.5, 0.5], &l));
+        assert!(inside_polygon([3.5, 0.5], &l));
+        assert!(inside_polygon([0.5, 3.5], &l));
+        assert!(!inside_polygon([2., 2.], &l));
+        assert!(!inside_polygon([5., 0.5], &l));
+        assert!(!inside_polygon([0.5, -1.], &l));
+    }
+
+    #[test]
+    fn rectangle_and_lasso() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        let mut resources = ResourceManager::default();
+        resources.register_hints(&["resources", "resources/objs", "resources/materials", "resources/shaders"]);
+        let program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
+        let mut scene = Scene::new(program);
+        let (cube, _) = resources.load_multipart_model("cube").unwrap();
+        //a row of cubes around the pixels 20, 32 and 43 of the middle line, and one near the top
+        let [left, middle, right, top] = [Vec3::X * -6., Vec3::default(), Vec3::X * 6., Vec3::Y * 10.]
+            .map(|pos| scene.spawn_object(cube, ObjectData::from(Transform::from_look_towards(pos, -Vec3::Z))));
+        scene.set_camera(Transform::from_look_at(Vec3::Z * 20., Vec3::default()));
+        scene.set_projection(80., 1.);
+        //the id buffer follows the size of the viewport
+        unsafe {
+            gl::Viewport(0, 0, 64, 64);
+        }
+        assert_eq!(scene.pick(&resources, 32, 32), Some(middle));
+        assert_eq!(scene.pick_rect(&resources, (34, 28), (10, 36)), vec![left, middle]);
+        assert_eq!(scene.pick_rect(&resources, (0, 0), (63, 63)), vec![left, middle, right, top]);
+        //"U" shape around the left and right cubes, the middle one is in its notch
+        let u = [[14., 20.], [50., 20.], [50., 44.], [40., 44.], [40., 24.], [24., 24.], [24., 44.], [14., 44.]];
+        assert_eq!(scene.pick_lasso(&resources, &u), vec![left, right]);
+        assert_eq!(scene.pick_lasso(&resources, &u[..2]), Vec::<usize>::new());
+        assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+    }
+}
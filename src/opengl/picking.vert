@@ -2,15 +2,15 @@
 
 layout (location = 0) in vec3 pos;
 
-out vec4 v_id;
+layout (location = 6) in mat4 i_mat;
+
+flat out uint v_id;
 
 uniform int id;
 uniform mat4 projection;
 uniform mat4 camera;
-uniform mat4 object[128];
 
 void main() {
-    gl_Position = projection * camera * object[gl_InstanceID] * vec4(pos, 1.0);
-    int t = id + gl_InstanceID + 1;
-    v_id = vec4(float((t & 0xFF000000) >> 24) / 255, float((t & 0xFF0000) >> 16) / 255, float((t & 0xFF00) >> 8) / 255, float(t & 0xFF) / 255);
+    gl_Position = projection * camera * i_mat * vec4(pos, 1.0);
+    v_id = uint(id + gl_InstanceID + 1);
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+use std::str::FromStr;
+use crate::maths::matrix::Mat4;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::post::{Effect, EffectContext, FullscreenPass, HDR_FORMAT};
+use crate::opengl::uniform::Uniform;
+
+pub const TAA_SAMPLES: usize = 8; //length of the jitter sequence
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    Msaa(u8), //samples of the multisampled scene target (or of the window without post processing)
+    Fxaa, //edge detection on the final image
+    Taa //sub pixel jitter of the projection accumulated over the frames
+}
+
+impl AntiAliasing {
+    ///next mode of the runtime toggle: none, msaa x4, fxaa, taa
+    pub fn cycle(self) -> Self {
+        match self {
+            Self::None => Self::Msaa(4),
+            Self::Msaa(_) => Self::Fxaa,
+            Self::Fxaa => Self::Taa,
+            Self::Taa => Self::None
+        }
+    }
+}
+
+impl FromStr for AntiAliasing {
+    type Err = ();
+
+    ///"none" (or "off"), "msaa" (4 samples), "msaa2" to "msaa16", "fxaa" or "taa"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Self::None),
+            "msaa" => Ok(Self::Msaa(4)),
+            "fxaa" => Ok(Self::Fxaa),
+            "taa" => Ok(Self::Taa),
+            s => match s.strip_prefix("msaa").map(|n| n.strip_prefix('x').unwrap_or(n).parse::<u8>()) {
+                Some(Ok(samples @ (2 | 4 | 8 | 16))) => Ok(Self::Msaa(samples)),
+                _ => Err(())
+            }
+        }
+    }
+}
+
+///element of the halton low discrepancy sequence in a base, in [0, 1[
+pub fn halton(mut index: usize, base: usize) -> f32 {
+    let mut out = 0.;
+    let mut fraction = 1.;
+    while index > 0 {
+        fraction /= base as f32;
+        out += fraction * (index % base) as f32;
+        index /= base;
+    }
+    out
+}
+
+///offset of the projection for a frame in normalized device coordinates, within half a pixel of the target
+pub fn jitter(frame: usize, (width, height): (u32, u32)) -> (f32, f32) {
+    let index = frame % TAA_SAMPLES + 1;
+    ((halton(index, 2) - 0.5) * 2. / width.max(1) as f32, (halton(index, 3) - 0.5) * 2. / height.max(1) as f32)
+}
+
+//fast approximate anti-aliasing, should be the last effect (on the displayed colors)
+#[derive(Debug)]
+pub struct Fxaa {
+    pub enabled: bool,
+    pass: FullscreenPass
+}
+
+impl Fxaa {
+    pub fn new() -> Self {
+        Self { enabled: true, pass: FullscreenPass::new(include_str!("fxaa.frag")) }
+    }
+}
+
+impl Effect for Fxaa {
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn apply(&mut self, _context: &EffectContext) {
+        self.pass.draw();
+    }
+}
+
+//temporal anti-aliasing: the jittered frames are blended with the history reprojected by the camera motion, should be the first effect (on the hdr scene)
+#[derive(Debug)]
+pub struct Taa {
+    pub enabled: bool,
+    pub feedback: f32, //weight of the current frame in the history
+    history: [Option<Framebuffer>; 2],
+    current: usize, //history written this frame
+    previous: Option<Mat4>, //unjittered view projection of the last frame, None restarts the accumulation
+    pass: FullscreenPass,
+    reprojection_uniform: Uniform,
+    feedback_uniform: Uniform
+}
+
+impl Taa {
+    pub fn new() -> Self {
+        let pass = FullscreenPass::new(include_str!("taa.frag"));
+        pass.uniform("history").int(1);
+        pass.uniform("depth").int(2);
+        Self {
+            enabled: true,
+            feedback: 0.1,
+            history: [None, None],
+            current: 0,
+            previous: None,
+            reprojection_uniform: pass.uniform("reprojection"),
+            feedback_uniform: pass.uniform("feedback"),
+            pass
+        }
+    }
+
+    ///forget the accumulated frames (ex: after a camera cut)
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+impl Effect for Taa {
+    fn enabled(&self) -> bool { self.enabled }
+
+    ///the depth of the scene is read on unit 2
+    fn apply(&mut self, context: &EffectContext) {
+        let size = context.size();
+        for history in &mut self.history {
+            match history {
+                Some(history) => if history.resize(size) { self.previous = None; },
+                None => {
+                    *history = Some(RenderTarget::default().color(HDR_FORMAT).build(size));
+                    self.previous = None;
+                }
+            }
+        }
+        let current = context.view_projection;
+        let reprojection = self.previous.zip(current.inverse()).map(|(previous, inverse)| previous * inverse);
+        self.previous = Some(current);
+        self.current = 1 - self.current;
+        let (write, read) = (self.history[self.current].as_ref().unwrap(), self.history[1 - self.current].as_ref().unwrap());
+        write.bind();
+        context.input.bind_color(0, 0);
+        read.bind_color(0, 1);
+        context.scene.bind_depth(2);
+        self.pass.program.set_active();
+        self.reprojection_uniform.mat4(reprojection.unwrap_or_else(Mat4::identity));
+        self.feedback_uniform.float(if reprojection.is_some() { self.feedback.clamp(0., 1.) } else { 1. });
+        self.pass.draw();
+        context.blit(write);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::post::antialiasing::{halton, jitter, AntiAliasing, TAA_SAMPLES};
+
+    #[test]
+    fn halton_sequence() {
+        assert_eq!((1..5).map(|i| halton(i, 2)).collect::<Vec<_>>(), vec![0.5, 0.25, 0.75, 0.125]);
+        assert!((halton(2, 3) - 2. / 3.).abs() < 1e-6);
+        //the offsets stay within half a pixel and repeat
+        for frame in 0..TAA_SAMPLES {
+            let (x, y) = jitter(frame, (100, 50));
+            assert!(x.abs() < 1. / 100. && y.abs() < 1. / 50.);
+            assert_eq!(jitter(frame, (100, 50)), jitter(frame + TAA_SAMPLES, (100, 50)));
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("msaa".parse(), Ok(AntiAliasing::Msaa(4)));
+        assert_eq!("MSAA8".parse(), Ok(AntiAliasing::Msaa(8)));
+        assert_eq!("msaax2".parse(), Ok(AntiAliasing::Msaa(2)));
+        assert_eq!("taa".parse(), Ok(AntiAliasing::Taa));
+        assert_eq!("off".parse(), Ok(AntiAliasing::None));
+        assert_eq!("msaa3".parse::<AntiAliasing>(), Err(()));
+        assert_eq!(AntiAliasing::Taa.cycle(), AntiAliasing::None);
+    }
+}
@@ -0,0 +1,13 @@
+#version 330 core
+
+uniform sampler2D source; //hdr color
+uniform sampler2D bloom; //blurred bright parts, half resolution
+uniform float intensity;
+
+in vec2 uv;
+
+out vec4 output_color;
+
+void main() {
+    output_color = vec4(texture(source, uv).rgb + texture(bloom, uv).rgb * intensity, 1.);
+}
@@ -0,0 +1,126 @@
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::post::{Effect, EffectContext, FullscreenPass, EMISSIVE_FORMAT};
+use crate::opengl::safe_calls;
+use crate::opengl::uniform::Uniform;
+
+///sizes of the blur levels, each one half of the previous starting at half the target (at least one level)
+pub fn mip_sizes((width, height): (u32, u32), levels: usize) -> Vec<(u32, u32)> {
+    let mut out = Vec::new();
+    let mut size = ((width / 2).max(1), (height / 2).max(1));
+    while out.len() < levels.max(1) {
+        out.push(size);
+        if size == (1, 1) {
+            break;
+        }
+        size = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+    }
+    out
+}
+
+//glow around the bright parts of the hdr scene and its emissive surfaces: extracted, blurred by a downsample / upsample chain then added back
+#[derive(Debug)]
+pub struct Bloom {
+    pub enabled: bool,
+    pub threshold: f32, //brightness where the lit surfaces start to glow, the emissive light always does
+    pub knee: f32, //softness of the threshold
+    pub intensity: f32,
+    pub radius: f32, //spread of the upsampling filter in texels
+    pub levels: usize, //halvings of the resolution, more levels give a wider glow
+    mips: Vec<Framebuffer>,
+    down: FullscreenPass,
+    up: FullscreenPass,
+    composite: FullscreenPass,
+    prefilter_uniform: Uniform,
+    threshold_uniform: Uniform,
+    knee_uniform: Uniform,
+    radius_uniform: Uniform,
+    intensity_uniform: Uniform
+}
+
+impl Bloom {
+    pub fn new() -> Self {
+        let down = FullscreenPass::new(include_str!("bloom_down.frag"));
+        down.uniform("emissive").int(1);
+        let up = FullscreenPass::new(include_str!("bloom_up.frag"));
+        let composite = FullscreenPass::new(include_str!("bloom.frag"));
+        composite.uniform("bloom").int(1);
+        Self {
+            enabled: true,
+            threshold: 1.,
+            knee: 0.5,
+            intensity: 0.1,
+            radius: 1.,
+            levels: 6,
+            mips: Vec::new(),
+            prefilter_uniform: down.uniform("prefilter"),
+            threshold_uniform: down.uniform("threshold"),
+            knee_uniform: down.uniform("knee"),
+            radius_uniform: up.uniform("radius"),
+            intensity_uniform: composite.uniform("intensity"),
+            down,
+            up,
+            composite
+        }
+    }
+}
+
+impl Effect for Bloom {
+    fn enabled(&self) -> bool { self.enabled }
+
+    ///the emissive light is read from the second color of the scene
+    fn apply(&mut self, context: &EffectContext) {
+        let sizes = mip_sizes(context.size(), self.levels);
+        self.mips.truncate(sizes.len());
+        for (i, size) in sizes.into_iter().enumerate() {
+            match self.mips.get_mut(i) {
+                Some(mip) => { mip.resize(size); }
+                None => self.mips.push(RenderTarget::default().color(EMISSIVE_FORMAT).build(size))
+            }
+        }
+        //the allocation of the levels went through unit 0
+        context.input.bind_color(0, 0);
+        self.down.program.set_active();
+        self.prefilter_uniform.int(1);
+        self.threshold_uniform.float(self.threshold.max(0.));
+        self.knee_uniform.float(self.knee.max(0.));
+        context.scene.bind_color(1, 1);
+        self.mips[0].bind();
+        self.down.draw();
+        self.prefilter_uniform.int(0);
+        for i in 1..self.mips.len() {
+            self.mips[i].bind();
+            self.mips[i - 1].bind_color(0, 0);
+            self.down.draw();
+        }
+        //each level receives the blurred sum of the smaller ones
+        self.up.program.set_active();
+        self.radius_uniform.float(self.radius.max(0.));
+        safe_calls::set_blend(true);
+        safe_calls::set_blend_func(gl::ONE, gl::ONE, gl::ONE, gl::ONE);
+        for i in (1..self.mips.len()).rev() {
+            self.mips[i - 1].bind();
+            self.mips[i].bind_color(0, 0);
+            self.up.draw();
+        }
+        safe_calls::set_blend(false);
+        context.bind_output();
+        context.input.bind_color(0, 0);
+        self.mips[0].bind_color(0, 1);
+        self.composite.program.set_active();
+        self.intensity_uniform.float(self.intensity.max(0.));
+        self.composite.draw();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::post::bloom::mip_sizes;
+
+    #[test]
+    fn levels() {
+        assert_eq!(mip_sizes((1920, 1080), 4), vec![(960, 540), (480, 270), (240, 135), (120, 67)]);
+        //stops at a single texel, never empty
+        assert_eq!(mip_sizes((8, 2), 10), vec![(4, 1), (2, 1), (1, 1)]);
+        assert_eq!(mip_sizes((1, 1), 0), vec![(1, 1)]);
+    }
+}
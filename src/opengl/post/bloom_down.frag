@@ -0,0 +1,46 @@
+#version 330 core
+
+uniform sampler2D source; //previous level (the scene for the first one)
+uniform sampler2D emissive; //emissive light of the scene
+uniform bool prefilter; //first level: bright parts of the scene and emissive light
+uniform float threshold; //brightness where the scene starts to glow
+uniform float knee; //width of the soft transition below the threshold
+
+in vec2 uv;
+
+out vec4 output_color;
+
+//13 taps (jimenez): weighted average of 5 overlapping boxes, stable when the camera moves
+vec3 downsample(sampler2D map) {
+    vec2 t = 1. / vec2(textureSize(map, 0));
+    vec3 a = texture(map, uv + t * vec2(-2, 2)).rgb;
+    vec3 b = texture(map, uv + t * vec2(0, 2)).rgb;
+    vec3 c = texture(map, uv + t * vec2(2, 2)).rgb;
+    vec3 d = texture(map, uv + t * vec2(-2, 0)).rgb;
+    vec3 e = texture(map, uv).rgb;
+    vec3 f = texture(map, uv + t * vec2(2, 0)).rgb;
+    vec3 g = texture(map, uv + t * vec2(-2, -2)).rgb;
+    vec3 h = texture(map, uv + t * vec2(0, -2)).rgb;
+    vec3 i = texture(map, uv + t * vec2(2, -2)).rgb;
+    vec3 j = texture(map, uv + t * vec2(-1, 1)).rgb;
+    vec3 k = texture(map, uv + t * vec2(1, 1)).rgb;
+    vec3 l = texture(map, uv + t * vec2(-1, -1)).rgb;
+    vec3 m = texture(map, uv + t * vec2(1, -1)).rgb;
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+//part of a color above the threshold, with a quadratic curve in the knee
+vec3 bright(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0., 2. * knee);
+    soft = soft * soft / (4. * knee + 0.00001);
+    return color * max(soft, brightness - threshold) / max(brightness, 0.00001);
+}
+
+void main() {
+    vec3 color = downsample(source);
+    if (prefilter) {
+        color = bright(max(color, 0.)) + downsample(emissive);
+    }
+    output_color = vec4(color, 1.);
+}
@@ -0,0 +1,17 @@
+#version 330 core
+
+uniform sampler2D source; //smaller level, added to the bound one
+uniform float radius; //spread of the filter in texels of the source
+
+in vec2 uv;
+
+out vec4 output_color;
+
+//3x3 tent filter
+void main() {
+    vec2 t = radius / vec2(textureSize(source, 0));
+    vec3 color = texture(source, uv).rgb * 4.;
+    color += (texture(source, uv + vec2(t.x, 0)).rgb + texture(source, uv - vec2(t.x, 0)).rgb + texture(source, uv + vec2(0, t.y)).rgb + texture(source, uv - vec2(0, t.y)).rgb) * 2.;
+    color += texture(source, uv + t).rgb + texture(source, uv - t).rgb + texture(source, uv + vec2(t.x, -t.y)).rgb + texture(source, uv + vec2(-t.x, t.y)).rgb;
+    output_color = vec4(color / 16., 1.);
+}
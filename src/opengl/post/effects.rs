@@ -0,0 +1,258 @@
+use std::ffi::c_void;
+use gl::types::{GLint, GLsizei, GLuint};
+use crate::opengl::post::{Effect, EffectContext, FullscreenPass};
+use crate::opengl::safe_calls;
+use crate::opengl::uniform::Uniform;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Tonemapper {
+    Reinhard,
+    #[default]
+    Aces
+}
+
+//hdr to displayable colors
+#[derive(Debug)]
+pub struct Tonemap {
+    pub enabled: bool,
+    pub operator: Tonemapper,
+    pub exposure: f32,
+    pass: FullscreenPass,
+    operator_uniform: Uniform,
+    exposure_uniform: Uniform
+}
+
+impl Tonemap {
+    pub fn new() -> Self {
+        let pass = FullscreenPass::new(include_str!("tonemap.frag"));
+        Self {
+            enabled: true,
+            operator: Tonemapper::default(),
+            exposure: 1.,
+            operator_uniform: pass.uniform("operator"),
+            exposure_uniform: pass.uniform("exposure"),
+            pass
+        }
+    }
+}
+
+impl Effect for Tonemap {
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn apply(&mut self, _context: &EffectContext) {
+        self.pass.program.set_active();
+        self.operator_uniform.int(self.operator as i32);
+        self.exposure_uniform.float(self.exposure);
+        self.pass.draw();
+    }
+}
+
+//linear to display colors
+#[derive(Debug)]
+pub struct Gamma {
+    pub enabled: bool,
+    pub gamma: f32,
+    pass: FullscreenPass,
+    gamma_uniform: Uniform
+}
+
+impl Gamma {
+    pub fn new() -> Self {
+        let pass = FullscreenPass::new(include_str!("gamma.frag"));
+        Self {
+            enabled: true,
+            gamma: 2.2,
+            gamma_uniform: pass.uniform("gamma"),
+            pass
+        }
+    }
+}
+
+impl Effect for Gamma {
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn apply(&mut self, _context: &EffectContext) {
+        self.pass.program.set_active();
+        self.gamma_uniform.float(self.gamma.max(0.01));
+        self.pass.draw();
+    }
+}
+
+//darkened corners
+#[derive(Debug)]
+pub struct Vignette {
+    pub enabled: bool,
+    pub strength: f32, //0: nothing, 1: black corners
+    pub radius: f32, //distance to the center where the darkening starts, 1 is a corner
+    pub softness: f32,
+    pass: FullscreenPass,
+    strength_uniform: Uniform,
+    radius_uniform: Uniform,
+    softness_uniform: Uniform
+}
+
+impl Vignette {
+    pub fn new() -> Self {
+        let pass = FullscreenPass::new(include_str!("vignette.frag"));
+        Self {
+            enabled: true,
+            strength: 0.3,
+            radius: 0.5,
+            softness: 0.5,
+            strength_uniform: pass.uniform("strength"),
+            radius_uniform: pass.uniform("radius"),
+            softness_uniform: pass.uniform("softness"),
+            pass
+        }
+    }
+}
+
+impl Effect for Vignette {
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn apply(&mut self, _context: &EffectContext) {
+        self.pass.program.set_active();
+        self.strength_uniform.float(self.strength.clamp(0., 1.));
+        self.radius_uniform.float(self.radius);
+        self.softness_uniform.float(self.softness.max(0.0001));
+        self.pass.draw();
+    }
+}
+
+///rgb lut of a given size, red varies first then green then blue, each color maps to itself
+pub fn identity_lut(size: usize) -> Vec<u8> {
+    let level = |i: usize| (i * 255 / (size - 1).max(1)) as u8;
+    let mut out = Vec::with_capacity(size * size * size * 3);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                out.extend_from_slice(&[level(r), level(g), level(b)]);
+            }
+        }
+    }
+    out
+}
+
+///lut from the usual horizontal strip layout (rgb image of size * size by size pixels, one blue slice per square, green along the rows)
+pub fn lut_from_strip(data: &[u8], width: usize, height: usize) -> Option<(Vec<u8>, usize)> {
+    let size = height;
+    if size < 2 || width != size * size || data.len() < width * height * 3 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(size * size * size * 3);
+    for b in 0..size {
+        for g in 0..size {
+            let row = (g * width + b * size) * 3;
+            out.extend_from_slice(&data[row..row + size * 3]);
+        }
+    }
+    Some((out, size))
+}
+
+//remaps the colors through a 3d lut
+#[derive(Debug)]
+pub struct ColorGrading {
+    pub enabled: bool,
+    pub intensity: f32, //blend between the input (0) and the graded colors (1)
+    lut: GLuint, //3d texture
+    size: usize,
+    pass: FullscreenPass,
+    size_uniform: Uniform,
+    intensity_uniform: Uniform
+}
+
+impl ColorGrading {
+    pub const LUT_UNIT: usize = 1;
+
+    ///identity lut of 16 texels per side
+    pub fn new() -> Self {
+        let pass = FullscreenPass::new(include_str!("grading.frag"));
+        pass.uniform("lut").int(Self::LUT_UNIT as i32);
+        let mut out = Self {
+            enabled: true,
+            intensity: 1.,
+            lut: 0,
+            size: 0,
+            size_uniform: pass.uniform("size"),
+            intensity_uniform: pass.uniform("intensity"),
+            pass
+        };
+        unsafe {
+            gl::GenTextures(1, &mut out.lut);
+        }
+        out.set_lut(&identity_lut(16), 16);
+        out
+    }
+
+    pub fn size(&self) -> usize { self.size }
+
+    ///upload a lut of size^3 rgb colors (red varies first)
+    pub fn set_lut(&mut self, data: &[u8], size: usize) -> bool {
+        if size < 2 || data.len() < size * size * size * 3 {
+            return false;
+        }
+        self.size = size;
+        safe_calls::edit_texture(gl::TEXTURE_3D, self.lut);
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage3D(gl::TEXTURE_3D, 0, gl::RGB8 as GLint, size as GLsizei, size as GLsizei, size as GLsizei, 0, gl::RGB, gl::UNSIGNED_BYTE, data.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            for wrap in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(gl::TEXTURE_3D, wrap, gl::CLAMP_TO_EDGE as GLint);
+            }
+        }
+        true
+    }
+
+    ///upload a lut stored as a horizontal strip (see lut_from_strip)
+    pub fn set_strip(&mut self, data: &[u8], width: usize, height: usize) -> bool {
+        lut_from_strip(data, width, height).is_some_and(|(lut, size)| self.set_lut(&lut, size))
+    }
+}
+
+impl Effect for ColorGrading {
+    fn enabled(&self) -> bool { self.enabled }
+
+    fn apply(&mut self, _context: &EffectContext) {
+        self.pass.program.set_active();
+        safe_calls::bind_texture_target(Self::LUT_UNIT, gl::TEXTURE_3D, self.lut);
+        self.size_uniform.float(self.size as f32);
+        self.intensity_uniform.float(self.intensity.clamp(0., 1.));
+        self.pass.draw();
+    }
+}
+
+impl Drop for ColorGrading {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.lut);
+        }
+        safe_calls::forget_texture(self.lut);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::post::effects::{identity_lut, lut_from_strip};
+
+    #[test]
+    fn strip_layout() {
+        let size = 4;
+        let lut = identity_lut(size);
+        assert_eq!(&lut[..3], &[0, 0, 0]);
+        assert_eq!(&lut[lut.len() - 3..], &[255, 255, 255]);
+        //(r, g, b) = (1, 2, 3): red first, then green, then blue
+        assert_eq!(&lut[(1 + 2 * size + 3 * size * size) * 3..][..3], &[85, 170, 255]);
+        //the same lut laid out as a strip: blue picks the square, green the row, red the column
+        let mut strip = vec![0; size * size * size * 3];
+        for (i, texel) in lut.chunks(3).enumerate() {
+            let (r, g, b) = (i % size, i / size % size, i / (size * size));
+            let at = (g * size * size + b * size + r) * 3;
+            strip[at..at + 3].copy_from_slice(texel);
+        }
+        assert_eq!(lut_from_strip(&strip, size * size, size), Some((lut, size)));
+        assert_eq!(lut_from_strip(&strip, size * size - 1, size), None);
+    }
+}
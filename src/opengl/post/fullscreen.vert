@@ -0,0 +1,10 @@
+#version 330 core
+
+out vec2 uv;
+
+//fullscreen triangle from the vertex ids, no vertex buffer
+void main() {
+    vec2 p = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+    uv = p;
+    gl_Position = vec4(p * 2. - 1., 0., 1.);
+}
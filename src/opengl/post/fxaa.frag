@@ -0,0 +1,109 @@
+#version 330 core
+
+uniform sampler2D source; //display color
+
+in vec2 uv;
+
+out vec4 output_color;
+
+#define EDGE_THRESHOLD_MIN 0.0312
+#define EDGE_THRESHOLD_MAX 0.125
+#define ITERATIONS 12
+#define SUBPIXEL_QUALITY 0.75
+
+float luma(vec3 color) {
+    return sqrt(dot(color, vec3(0.299, 0.587, 0.114)));
+}
+
+float luma_at(vec2 p) {
+    return luma(texture(source, p).rgb);
+}
+
+//the search along the edge speeds up after a few steps
+float step_scale(int i) {
+    return i < 5 ? 1. : i == 5 ? 1.5 : i < 10 ? 2. : i == 10 ? 4. : 8.;
+}
+
+//fxaa 3.11 quality (lottes): edges are found by their local contrast, followed until their ends and the pixel is blended across them
+void main() {
+    vec2 texel = 1. / vec2(textureSize(source, 0));
+    vec3 center = texture(source, uv).rgb;
+    float l = luma(center);
+    float down = luma(textureOffset(source, uv, ivec2(0, -1)).rgb);
+    float up = luma(textureOffset(source, uv, ivec2(0, 1)).rgb);
+    float left = luma(textureOffset(source, uv, ivec2(-1, 0)).rgb);
+    float right = luma(textureOffset(source, uv, ivec2(1, 0)).rgb);
+    float lowest = min(l, min(min(down, up), min(left, right)));
+    float highest = max(l, max(max(down, up), max(left, right)));
+    float range = highest - lowest;
+    if (range < max(EDGE_THRESHOLD_MIN, highest * EDGE_THRESHOLD_MAX)) {
+        output_color = vec4(center, 1.);
+        return;
+    }
+    float down_left = luma(textureOffset(source, uv, ivec2(-1, -1)).rgb);
+    float up_right = luma(textureOffset(source, uv, ivec2(1, 1)).rgb);
+    float up_left = luma(textureOffset(source, uv, ivec2(-1, 1)).rgb);
+    float down_right = luma(textureOffset(source, uv, ivec2(1, -1)).rgb);
+    float down_up = down + up;
+    float left_right = left + right;
+    float left_corners = down_left + up_left;
+    float down_corners = down_left + down_right;
+    float right_corners = down_right + up_right;
+    float up_corners = up_right + up_left;
+    float horizontal_edge = abs(-2. * left + left_corners) + abs(-2. * l + down_up) * 2. + abs(-2. * right + right_corners);
+    float vertical_edge = abs(-2. * up + up_corners) + abs(-2. * l + left_right) * 2. + abs(-2. * down + down_corners);
+    bool horizontal = horizontal_edge >= vertical_edge;
+
+    //side of the edge with the steepest gradient
+    float luma1 = horizontal ? down : left;
+    float luma2 = horizontal ? up : right;
+    float gradient1 = luma1 - l;
+    float gradient2 = luma2 - l;
+    bool steepest1 = abs(gradient1) >= abs(gradient2);
+    float gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+    float step_length = horizontal ? texel.y : texel.x;
+    float local_average;
+    if (steepest1) {
+        step_length = -step_length;
+        local_average = 0.5 * (luma1 + l);
+    } else {
+        local_average = 0.5 * (luma2 + l);
+    }
+
+    //walk along the edge in both directions until the contrast changes
+    vec2 current = uv + (horizontal ? vec2(0., step_length) : vec2(step_length, 0.)) * 0.5;
+    vec2 offset = horizontal ? vec2(texel.x, 0.) : vec2(0., texel.y);
+    vec2 uv1 = current - offset;
+    vec2 uv2 = current + offset;
+    float end1 = luma_at(uv1) - local_average;
+    float end2 = luma_at(uv2) - local_average;
+    bool reached1 = abs(end1) >= gradient_scaled;
+    bool reached2 = abs(end2) >= gradient_scaled;
+    for (int i = 1; i < ITERATIONS && !(reached1 && reached2); ++i) {
+        if (!reached1) {
+            uv1 -= offset * step_scale(i);
+            end1 = luma_at(uv1) - local_average;
+            reached1 = abs(end1) >= gradient_scaled;
+        }
+        if (!reached2) {
+            uv2 += offset * step_scale(i);
+            end2 = luma_at(uv2) - local_average;
+            reached2 = abs(end2) >= gradient_scaled;
+        }
+    }
+    float distance1 = horizontal ? uv.x - uv1.x : uv.y - uv1.y;
+    float distance2 = horizontal ? uv2.x - uv.x : uv2.y - uv.y;
+    bool closest1 = distance1 < distance2;
+    float pixel_offset = 0.5 - min(distance1, distance2) / (distance1 + distance2);
+    //only blend when the closest end of the edge varies the other way than the center
+    bool correct_variation = ((closest1 ? end1 : end2) < 0.) != (l < local_average);
+    float edge_offset = correct_variation ? pixel_offset : 0.;
+
+    //thin features smaller than a pixel
+    float average = (2. * (down_up + left_right) + left_corners + right_corners) / 12.;
+    float subpixel = clamp(abs(average - l) / range, 0., 1.);
+    subpixel = (-2. * subpixel + 3.) * subpixel * subpixel;
+    float final_offset = max(edge_offset, subpixel * subpixel * SUBPIXEL_QUALITY);
+
+    output_color = vec4(texture(source, uv + (horizontal ? vec2(0., final_offset * step_length) : vec2(final_offset * step_length, 0.))).rgb, 1.);
+}
@@ -0,0 +1,12 @@
+#version 330 core
+
+uniform sampler2D source; //linear color
+uniform float gamma;
+
+in vec2 uv;
+
+out vec4 output_color;
+
+void main() {
+    output_color = vec4(pow(max(texture(source, uv).rgb, 0.), vec3(1. / gamma)), 1.);
+}
@@ -0,0 +1,17 @@
+#version 330 core
+
+uniform sampler2D source; //display color
+uniform sampler3D lut; //graded color of each input color, red along x, green along y, blue along z
+uniform float size; //texels per side of the lut
+uniform float intensity;
+
+in vec2 uv;
+
+out vec4 output_color;
+
+void main() {
+    vec3 color = clamp(texture(source, uv).rgb, 0., 1.);
+    //the centers of the first and last texels map to 0 and 1
+    vec3 graded = texture(lut, color * (size - 1.) / size + 0.5 / size).rgb;
+    output_color = vec4(mix(color, graded, intensity), 1.);
+}
@@ -0,0 +1,262 @@
+use std::any::Any;
+use std::fmt::Debug;
+use gl::types::{GLint, GLuint};
+use crate::maths::matrix::Mat4;
+use crate::opengl::enums::Shaders;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::post::antialiasing::{jitter, AntiAliasing, Fxaa, Taa};
+use crate::opengl::post::bloom::Bloom;
+use crate::opengl::post::effects::{ColorGrading, Gamma, Tonemap, Vignette};
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+
+pub mod effects;
+pub mod bloom;
+pub mod antialiasing;
+
+pub const HDR_FORMAT: GLuint = gl::RGBA16F; //color of the scene and of the intermediate targets of the chain
+pub const EMISSIVE_FORMAT: GLuint = gl::R11F_G11F_B10F; //second output of the scene, light emitted by the surfaces
+
+//program drawn over the whole target by a single triangle generated from the vertex ids
+#[derive(Debug)]
+pub struct FullscreenPass {
+    pub program: ShaderProgram,
+    vao: GLuint //empty
+}
+
+impl FullscreenPass {
+    ///fragment shader reading the uv of the target (0 to 1) from "in vec2 uv"
+    pub fn new(fragment: &str) -> Self {
+        let program = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("fullscreen.vert"))
+            .add_shader(Shaders::Fragment, fragment)
+            .build().unwrap();
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+        Self { program, vao }
+    }
+
+    pub fn uniform(&self, name: &str) -> Uniform { self.program.uniform(name) }
+
+    ///draw the triangle with the program of the pass (the depth test should be disabled)
+    pub fn draw(&self) {
+        self.program.set_active();
+        safe_calls::bind_vao(self.vao);
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+    }
+}
+
+impl Drop for FullscreenPass {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+        safe_calls::forget_vao(self.vao);
+    }
+}
+
+//targets seen by an effect, its output is bound and the color of its input is bound to unit 0 before it is applied
+pub struct EffectContext<'a> {
+    pub input: &'a Framebuffer, //output of the previous effect (the scene for the first one)
+    pub scene: &'a Framebuffer, //hdr color, emissive and depth texture of the scene
+    pub view_projection: Mat4, //camera of the frame, without the jitter of the temporal anti-aliasing
+    output: GLuint,
+    viewport: [GLint; 4]
+}
+
+impl EffectContext<'_> {
+    ///bind the output again (after drawing into intermediate targets of the effect)
+    pub fn bind_output(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.output);
+            gl::Viewport(self.viewport[0], self.viewport[1], self.viewport[2], self.viewport[3]);
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) { self.input.size() }
+
+    ///copy the first color of a framebuffer of the size of the input to the output, leaves the output bound
+    pub fn blit(&self, source: &Framebuffer) {
+        let (width, height) = (source.size().0 as GLint, source.size().1 as GLint);
+        let [x, y, w, h] = self.viewport;
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source.id());
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.output);
+            gl::BlitFramebuffer(0, 0, width, height, x, y, x + w, y + h, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        }
+        self.bind_output();
+    }
+}
+
+//step of the post processing chain, drawn into the output of the context
+pub trait Effect: Any + Debug {
+    ///disabled effects are skipped by the chain
+    fn enabled(&self) -> bool { true }
+
+    fn apply(&mut self, context: &EffectContext);
+}
+
+//the scene is drawn into an hdr target, resolved to the previously bound framebuffer by the enabled effects in order
+#[derive(Debug)]
+pub struct PostChain {
+    scene: Framebuffer,
+    msaa: Option<Framebuffer>, //multisampled copy of the scene target drawn instead of it, resolved before the effects
+    antialiasing: AntiAliasing,
+    view_projection: Mat4,
+    frame: usize,
+    swap: [Option<Framebuffer>; 2], //intermediate targets, allocated when more than one effect is enabled
+    effects: Vec<Box<dyn Effect>>,
+    target: GLuint, //framebuffer bound before begin
+    viewport: [GLint; 4]
+}
+
+impl Default for PostChain {
+    ///bloom, tonemapping, vignette, gamma then an identity color grading
+    fn default() -> Self {
+        let mut out = Self::new();
+        out.push(Box::new(Bloom::new()));
+        out.push(Box::new(Tonemap::new()));
+        out.push(Box::new(Vignette::new()));
+        out.push(Box::new(Gamma::new()));
+        out.push(Box::new(ColorGrading::new()));
+        out
+    }
+}
+
+impl PostChain {
+    ///chain without effects (the scene is copied to the target)
+    pub fn new() -> Self {
+        Self {
+            scene: RenderTarget::default().color(HDR_FORMAT).color(EMISSIVE_FORMAT).depth_texture(gl::DEPTH_COMPONENT24).build((1, 1)),
+            msaa: None,
+            antialiasing: AntiAliasing::None,
+            view_projection: Mat4::identity(),
+            frame: 0,
+            swap: [None, None],
+            effects: Vec::new(),
+            target: 0,
+            viewport: [0; 4]
+        }
+    }
+
+    pub fn scene(&self) -> &Framebuffer { &self.scene }
+
+    pub fn effects(&self) -> &[Box<dyn Effect>] { &self.effects }
+
+    pub fn push(&mut self, effect: Box<dyn Effect>) {
+        self.effects.push(effect);
+    }
+
+    pub fn insert(&mut self, index: usize, effect: Box<dyn Effect>) {
+        self.effects.insert(index.min(self.effects.len()), effect);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Box<dyn Effect>> {
+        (index < self.effects.len()).then(|| self.effects.remove(index))
+    }
+
+    ///first effect of a type
+    pub fn effect<T: Effect>(&self) -> Option<&T> {
+        self.effects.iter().find_map(|e| (e.as_ref() as &dyn Any).downcast_ref::<T>())
+    }
+
+    ///first effect of a type (ex: to change its settings)
+    pub fn effect_mut<T: Effect>(&mut self) -> Option<&mut T> {
+        self.effects.iter_mut().find_map(|e| (e.as_mut() as &mut dyn Any).downcast_mut::<T>())
+    }
+
+    pub fn antialiasing(&self) -> AntiAliasing { self.antialiasing }
+
+    ///msaa draws into a multisampled scene target, fxaa is appended to the effects and taa inserted before them
+    pub fn set_antialiasing(&mut self, mode: AntiAliasing) {
+        if mode == self.antialiasing {
+            return;
+        }
+        self.effects.retain(|e| !(e.as_ref() as &dyn Any).is::<Fxaa>() && !(e.as_ref() as &dyn Any).is::<Taa>());
+        self.msaa = match mode {
+            AntiAliasing::Msaa(samples) => Some(self.scene.target().clone().samples(samples).build(self.scene.size())),
+            _ => None
+        };
+        match mode {
+            AntiAliasing::Fxaa => self.push(Box::new(Fxaa::new())),
+            AntiAliasing::Taa => self.insert(0, Box::new(Taa::new())),
+            _ => {}
+        }
+        self.antialiasing = mode;
+    }
+
+    ///offset of the projection of the next frame in normalized device coordinates, None without an enabled temporal anti-aliasing
+    pub fn jitter(&self) -> Option<(f32, f32)> {
+        self.effect::<Taa>().filter(|taa| taa.enabled()).map(|_| {
+            let viewport = safe_calls::get_int_array::<4>(gl::VIEWPORT);
+            jitter(self.frame, (viewport[2].max(1) as u32, viewport[3].max(1) as u32))
+        })
+    }
+
+    ///bind and clear the hdr target, resized to the viewport (the emissive output is cleared to black, not to the clear color)
+    ///the view projection (without jitter) is given to the effects reprojecting the previous frames
+    pub fn begin(&mut self, view_projection: Mat4) {
+        self.target = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        self.viewport = safe_calls::get_int_array::<4>(gl::VIEWPORT);
+        self.view_projection = view_projection;
+        self.frame = self.frame.wrapping_add(1);
+        let size = (self.viewport[2].max(1) as u32, self.viewport[3].max(1) as u32);
+        self.scene.resize(size);
+        if let Some(msaa) = &mut self.msaa {
+            msaa.resize(size);
+            msaa.bind();
+        } else {
+            self.scene.bind();
+        }
+        safe_calls::clear_screen();
+        unsafe {
+            gl::ClearBufferfv(gl::COLOR, 1, [0.; 4].as_ptr());
+        }
+    }
+
+    ///apply the enabled effects, the last one draws into the framebuffer bound before begin
+    pub fn resolve(&mut self) {
+        if let Some(msaa) = &self.msaa {
+            msaa.resolve_into(&self.scene);
+        }
+        let Self { scene, swap, effects, target, viewport, view_projection, .. } = self;
+        let size = scene.size();
+        let enabled = effects.iter().enumerate().filter(|(_, e)| e.enabled()).map(|(i, _)| i).collect::<Vec<_>>();
+        for (i, slot) in swap.iter_mut().enumerate() {
+            if enabled.len() > i + 1 {
+                slot.get_or_insert_with(|| RenderTarget::default().color(HDR_FORMAT).build(size)).resize(size);
+            } else {
+                *slot = None;
+            }
+        }
+        if enabled.is_empty() {
+            unsafe {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, scene.id());
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, *target);
+                gl::BlitFramebuffer(0, 0, size.0 as GLint, size.1 as GLint, viewport[0], viewport[1], viewport[0] + viewport[2], viewport[1] + viewport[3], gl::COLOR_BUFFER_BIT, gl::NEAREST);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, *target);
+                gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            }
+            return;
+        }
+        safe_calls::set_depth_test(false);
+        for (n, &index) in enabled.iter().enumerate() {
+            let input = if n == 0 { &*scene } else { swap[(n - 1) % 2].as_ref().unwrap() };
+            let (output, view) = if n + 1 == enabled.len() {
+                (*target, *viewport)
+            } else {
+                (swap[n % 2].as_ref().unwrap().id(), [0, 0, size.0 as GLint, size.1 as GLint])
+            };
+            let context = EffectContext { input, scene, view_projection: *view_projection, output, viewport: view };
+            context.bind_output();
+            input.bind_color(0, 0);
+            effects[index].apply(&context);
+        }
+        safe_calls::set_depth_test(true);
+    }
+}
@@ -0,0 +1,43 @@
+#version 330 core
+
+uniform sampler2D source; //current hdr frame, drawn with a sub pixel jitter
+uniform sampler2D history; //accumulation of the previous frames
+uniform sampler2D depth; //depth of the scene
+uniform mat4 reprojection; //clip space of the current frame to the previous one (camera motion only)
+uniform float feedback; //weight of the current frame, 1 restarts the accumulation
+
+in vec2 uv;
+
+out vec4 output_color;
+
+//blending in a compressed range keeps the highlights from flickering
+vec3 compress(vec3 color) {
+    return color / (1. + max(color.r, max(color.g, color.b)));
+}
+
+vec3 expand(vec3 color) {
+    return color / max(1. - max(color.r, max(color.g, color.b)), 0.00001);
+}
+
+void main() {
+    vec3 current = compress(max(texture(source, uv).rgb, 0.));
+    //the history is clamped to the colors around the pixel, rejecting what was disoccluded or moved
+    vec3 lowest = current;
+    vec3 highest = current;
+    ivec2 last = textureSize(source, 0) - 1;
+    for (int x = -1; x <= 1; ++x) {
+        for (int y = -1; y <= 1; ++y) {
+            vec3 neighbour = compress(max(texelFetch(source, clamp(ivec2(gl_FragCoord.xy) + ivec2(x, y), ivec2(0), last), 0).rgb, 0.));
+            lowest = min(lowest, neighbour);
+            highest = max(highest, neighbour);
+        }
+    }
+    vec4 previous = reprojection * vec4(uv * 2. - 1., texture(depth, uv).r * 2. - 1., 1.);
+    vec2 previous_uv = previous.xy / previous.w * 0.5 + 0.5;
+    float weight = feedback;
+    if (any(lessThan(previous_uv, vec2(0.))) || any(greaterThan(previous_uv, vec2(1.)))) {
+        weight = 1.;
+    }
+    vec3 past = clamp(compress(max(texture(history, previous_uv).rgb, 0.)), lowest, highest);
+    output_color = vec4(expand(mix(past, current, weight)), 1.);
+}
@@ -0,0 +1,19 @@
+#version 330 core
+
+uniform sampler2D source; //hdr color
+uniform float exposure;
+uniform int operator; //0: reinhard, 1: aces
+
+in vec2 uv;
+
+out vec4 output_color;
+
+//fit of the aces filmic curve (narkowicz)
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0., 1.);
+}
+
+void main() {
+    vec3 color = max(texture(source, uv).rgb, 0.) * exposure;
+    output_color = vec4(operator == 1 ? aces(color) : color / (color + 1.), 1.);
+}
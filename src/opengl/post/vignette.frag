@@ -0,0 +1,16 @@
+#version 330 core
+
+uniform sampler2D source;
+uniform float strength; //darkening of the corners
+uniform float radius; //distance to the center where the darkening starts (1: corners)
+uniform float softness; //width of the transition
+
+in vec2 uv;
+
+out vec4 output_color;
+
+void main() {
+    float d = length(uv - 0.5) * sqrt(2.);
+    float falloff = smoothstep(radius, radius + softness, d);
+    output_color = vec4(texture(source, uv).rgb * (1. - strength * falloff), 1.);
+}
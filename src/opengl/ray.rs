@@ -0,0 +1,156 @@
+use crate::maths::matrix::Mat4;
+use crate::maths::vector::{Vec3, Vec4};
+use crate::opengl::volume::{Aabb, Sphere};
+
+#[derive(Debug, Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3 //normalized for world rays (distances in world units), scaled in model space so the distances stay the same
+}
+
+//closest intersection of a ray with the triangles of an instance
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    pub instance: usize,
+    pub part: usize,
+    pub triangle: usize,
+    pub barycentrics: [f32; 3], //weights of the 3 corners of the triangle
+    pub distance: f32,
+    pub point: Vec3, //world
+    pub normal: Vec3 //world, interpolated from the vertex normals if the mesh has some
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize()
+        }
+    }
+
+    ///ray going through a point of the screen in ndc ([-1, 1], y up), starting on the near plane
+    pub fn from_ndc(inverse_vp: &Mat4, x: f32, y: f32) -> Self {
+        let unproject = |z: f32| {
+            let p = *inverse_vp * Vec4::new(x, y, z, 1.);
+            (p / p[3]).resize::<3>()
+        };
+        let near = unproject(-1.);
+        Self::new(near, unproject(1.) - near)
+    }
+
+    ///ray going through a pixel (origin at the bottom left of the viewport like gl), None if the view projection can't be inverted
+    pub fn from_screen(vp: &Mat4, pixel_x: f32, pixel_y: f32, width: f32, height: f32) -> Option<Self> {
+        vp.inverse().map(|inverse| Self::from_ndc(&inverse, 2. * pixel_x / width - 1., 2. * pixel_y / height - 1.))
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    ///same ray in another space (ex: the inverse of a model matrix), the direction is not normalized again
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        Self {
+            origin: (*matrix * self.origin.extend(1.)).resize(),
+            direction: (*matrix * self.direction.extend(0.)).resize()
+        }
+    }
+
+    ///distance to the first intersection, 0 if the origin is inside
+    pub fn sphere(&self, sphere: &Sphere) -> Option<f32> {
+        let o = self.origin - sphere.center;
+        let a = self.direction.dot(&self.direction);
+        let b = o.dot(&self.direction);
+        let c = o.dot(&o) - sphere.radius * sphere.radius;
+        if c <= 0. {
+            return Some(0.);
+        }
+        let delta = b * b - a * c;
+        if delta < 0. || b > 0. {
+            return None;
+        }
+        Some((-b - delta.sqrt()) / a)
+    }
+
+    ///distance to the entry point, 0 if the origin is inside
+    pub fn aabb(&self, aabb: &Aabb) -> Option<f32> {
+        aabb.ray_distance(&self.origin, &self.direction)
+    }
+
+    ///möller-trumbore (both faces), returns the distance and the barycentric weights of b and c
+    pub fn triangle(&self, a: &Vec3, b: &Vec3, c: &Vec3) -> Option<(f32, f32, f32)> {
+        let (e1, e2) = (*b - *a, *c - *a);
+        let p = self.direction.cross_product(&e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv = 1. / det;
+        let s = self.origin - *a;
+        let u = s.dot(&p) * inv;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = s.cross_product(&e1);
+        let v = self.direction.dot(&q) * inv;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = e2.dot(&q) * inv;
+        if t >= 0. { Some((t, u, v)) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::matrix::{Mat4, Matrix};
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::{Vec3, Vec4};
+    use crate::opengl::ray::Ray;
+    use crate::opengl::volume::{Aabb, Sphere};
+
+    #[test]
+    fn unprojection() {
+        let view = Transform::from_look_at(Vec3::new(3., 2., 10.), Vec3::default()).as_view_matrix();
+        let vp = Mat4::projection(70f32.to_radians(), 16. / 9., 0.1, 100.) * view;
+        let product = vp * vp.inverse().unwrap();
+        let identity: Mat4 = Matrix::identity();
+        for c in 0..4 {
+            for r in 0..4 {
+                assert!((product[(c, r)] - identity[(c, r)]).abs() < 1e-4);
+            }
+        }
+        //any point of the ray projects back on the same pixel
+        let ray = Ray::from_screen(&vp, 600., 150., 800., 600.).unwrap();
+        let p = vp * ray.at(5.).extend(1.);
+        let p: Vec4 = p / p[3];
+        assert!((p[0] - 0.5).abs() < 1e-4 && (p[1] + 0.5).abs() < 1e-4);
+        //the center of the screen looks along the axis of the camera
+        let vp = Mat4::projection(70f32.to_radians(), 16. / 9., 0.1, 100.) * Transform::from_look_at(Vec3::Z * 10., Vec3::default()).as_view_matrix();
+        let ray = Ray::from_screen(&vp, 400., 300., 800., 600.).unwrap();
+        assert!((ray.direction + Vec3::Z).dot(&(ray.direction + Vec3::Z)) < 1e-6);
+        assert!((ray.origin[2] - 9.9).abs() < 1e-3);
+        assert!(Mat4::from_scale(&Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+
+    #[test]
+    fn intersections() {
+        let ray = Ray::new(Vec3::new(0., 0., -10.), Vec3::Z);
+        assert_eq!(ray.sphere(&Sphere { center: Vec3::default(), radius: 2. }), Some(8.));
+        assert_eq!(ray.sphere(&Sphere { center: Vec3::X * 3., radius: 2. }), None);
+        assert_eq!(ray.sphere(&Sphere { center: Vec3::Z * -20., radius: 2. }), None); //behind
+        assert_eq!(ray.aabb(&Aabb { min: Vec3::splat(-1.), max: Vec3::splat(1.) }), Some(9.));
+        assert_eq!(ray.aabb(&Aabb { min: Vec3::splat(1.), max: Vec3::splat(2.) }), None);
+
+        let (a, b, c) = (Vec3::new(-1., -1., 0.), Vec3::new(3., -1., 0.), Vec3::new(-1., 3., 0.));
+        let (t, u, v) = ray.triangle(&a, &b, &c).unwrap();
+        assert!((t - 10.).abs() < 1e-5 && (u - 0.25).abs() < 1e-5 && (v - 0.25).abs() < 1e-5);
+        assert!(ray.triangle(&a, &c, &b).is_some()); //back face
+        assert!(Ray::new(Vec3::new(2., 2., -10.), Vec3::Z).triangle(&a, &b, &c).is_none());
+        assert!(Ray::new(Vec3::new(0., 0., 1.), Vec3::Z).triangle(&a, &b, &c).is_none());
+        //a transformed ray keeps the distances
+        let m = Mat4::from(Transform::from_pos(Vec3::X) * 2.).inverse().unwrap();
+        let local = ray.transformed(&m);
+        let (t, ..) = local.triangle(&a, &b, &c).unwrap();
+        assert!((t - 10.).abs() < 1e-5);
+    }
+}
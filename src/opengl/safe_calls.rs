@@ -1,6 +1,134 @@
+use std::cell::RefCell;
 use gl::types::{GLenum, GLint, GLsizei, GLuint};
 use crate::opengl::enums::{RenderMode, Side};
 
+pub const MAX_TEXTURE_UNITS: usize = 32;
+
+//calls sent to the driver and calls skipped because the state was already set
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct StateCounters {
+    pub issued: usize,
+    pub skipped: usize
+}
+
+//shadow of the gl state changed through this module (None: unknown, the next call is always issued)
+#[derive(Debug, Default)]
+struct RenderState {
+    program: Option<GLuint>,
+    vao: Option<GLuint>,
+    active_unit: Option<usize>,
+    textures: [Option<(GLenum, GLuint)>; MAX_TEXTURE_UNITS], //target and texture last bound to each unit
+    depth_test: Option<bool>,
+    depth_write: Option<bool>,
+    cull_face: Option<bool>,
+    blend: Option<bool>,
+    blend_func: Option<[GLenum; 4]>, //source and destination factors of the colors, then of the alpha
+    polygon_mode: Option<RenderMode>, //front and back
+    counters: StateCounters
+}
+
+thread_local! {
+    //a gl context is current on a single thread
+    static STATE: RefCell<RenderState> = RefCell::new(RenderState::default());
+}
+
+///returns true (and records the new value) if the call has to be issued
+fn changed<T: PartialEq + Copy>(slot: fn(&mut RenderState) -> &mut Option<T>, value: T) -> bool {
+    STATE.with_borrow_mut(|state| {
+        let out = *slot(state) != Some(value);
+        *slot(state) = Some(value);
+        if out {
+            state.counters.issued += 1;
+        } else {
+            state.counters.skipped += 1;
+        }
+        out
+    })
+}
+
+///counters since the last call (ex: once per frame)
+pub fn take_state_counters() -> StateCounters {
+    STATE.with_borrow_mut(|state| std::mem::take(&mut state.counters))
+}
+
+///forget the shadowed state (ex: after raw gl calls changing it or a new context)
+pub fn invalidate_state() {
+    STATE.with_borrow_mut(|state| *state = RenderState { counters: state.counters, ..Default::default() });
+}
+
+pub fn use_program(program: GLuint) {
+    if changed(|s| &mut s.program, program) {
+        unsafe {
+            gl::UseProgram(program);
+        }
+    }
+}
+
+pub fn bind_vao(vao: GLuint) {
+    if changed(|s| &mut s.vao, vao) {
+        unsafe {
+            gl::BindVertexArray(vao);
+        }
+    }
+}
+
+///a deleted vao is unbound by gl, its name can be reused
+pub fn forget_vao(vao: GLuint) {
+    STATE.with_borrow_mut(|state| if state.vao == Some(vao) { state.vao = None; });
+}
+
+///bind a 2d texture to a texture unit (units past MAX_TEXTURE_UNITS are not shadowed)
+pub fn bind_texture(unit: usize, texture: GLuint) {
+    bind_texture_target(unit, gl::TEXTURE_2D, texture);
+}
+
+///bind a texture of any target (cube map, array...) to a texture unit for sampling
+///only guarantees the texture is bound on this unit: a cached binding skips ActiveTexture, so raw Tex* calls must go through edit_texture
+pub fn bind_texture_target(unit: usize, target: GLenum, texture: GLuint) {
+    if unit >= MAX_TEXTURE_UNITS {
+        STATE.with_borrow_mut(|state| state.active_unit = None);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit as GLenum);
+            gl::BindTexture(target, texture);
+        }
+        return;
+    }
+    if STATE.with_borrow(|state| state.textures[unit] == Some((target, texture))) {
+        STATE.with_borrow_mut(|state| state.counters.skipped += 1);
+        return;
+    }
+    if changed(|s| &mut s.active_unit, unit) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit as GLenum);
+        }
+    }
+    STATE.with_borrow_mut(|state| {
+        state.textures[unit] = Some((target, texture));
+        state.counters.issued += 1;
+    });
+    unsafe {
+        gl::BindTexture(target, texture);
+    }
+}
+
+///bind a texture to unit 0 and make unit 0 active, always issued: the following raw Tex* calls (allocation, parameters) edit this texture
+pub fn edit_texture(target: GLenum, texture: GLuint) {
+    STATE.with_borrow_mut(|state| {
+        state.active_unit = Some(0);
+        state.textures[0] = Some((target, texture));
+        state.counters.issued += 2;
+    });
+    unsafe {
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(target, texture);
+    }
+}
+
+///a deleted texture is unbound from every unit by gl, its name can be reused
+pub fn forget_texture(texture: GLuint) {
+    STATE.with_borrow_mut(|state| state.textures.iter_mut().filter(|t| t.is_some_and(|(_, name)| name == texture)).for_each(|t| *t = None));
+}
+
 pub fn clear_screen() {
     unsafe {
         gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
@@ -14,6 +142,9 @@ pub fn set_clear_color(red: f32, green: f32, blue: f32) {
 }
 
 pub fn set_depth_test(state: bool) {
+    if !changed(|s| &mut s.depth_test, state) {
+        return;
+    }
     unsafe {
         if state {
             gl::Enable(gl::DEPTH_TEST);
@@ -25,6 +156,9 @@ pub fn set_depth_test(state: bool) {
 }
 
 pub fn set_cull_face(state: bool) {
+    if !changed(|s| &mut s.cull_face, state) {
+        return;
+    }
     unsafe {
         if state {
             gl::Enable(gl::CULL_FACE);
@@ -34,13 +168,55 @@ pub fn set_cull_face(state: bool) {
     }
 }
 
+///alpha blending (source alpha over the destination), other equations are set by set_blend_func after enabling it
+pub fn set_blend(state: bool) {
+    if changed(|s| &mut s.blend, state) {
+        unsafe {
+            if state {
+                gl::Enable(gl::BLEND);
+            } else {
+                gl::Disable(gl::BLEND);
+            }
+        }
+    }
+    if state {
+        set_blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    }
+}
+
+pub fn set_blend_func(source: GLenum, destination: GLenum, source_alpha: GLenum, destination_alpha: GLenum) {
+    if changed(|s| &mut s.blend_func, [source, destination, source_alpha, destination_alpha]) {
+        unsafe {
+            gl::BlendFuncSeparate(source, destination, source_alpha, destination_alpha);
+        }
+    }
+}
+
+///write the depth of the fragments passing the depth test (off for transparent geometry)
+pub fn set_depth_write(state: bool) {
+    if changed(|s| &mut s.depth_write, state) {
+        unsafe {
+            gl::DepthMask(if state { gl::TRUE } else { gl::FALSE });
+        }
+    }
+}
+
 pub fn set_draw_mode(side: Side, mode: RenderMode) {
+    match side {
+        Side::FrontAndBack => if !changed(|s| &mut s.polygon_mode, mode) {
+            return;
+        },
+        _ => STATE.with_borrow_mut(|state| state.polygon_mode = None)
+    }
     unsafe {
         gl::PolygonMode(side.into(), mode.into());
     }
 }
 
 pub fn get_draw_mode() -> (RenderMode, RenderMode) {
+    if let Some(mode) = STATE.with_borrow(|state| state.polygon_mode) {
+        return (mode, mode);
+    }
     let modes = get_int_array::<2>(gl::POLYGON_MODE);
     (RenderMode::try_from(modes[0] as GLenum).unwrap(), RenderMode::try_from(modes[1] as GLenum).unwrap())
 }
@@ -62,7 +238,9 @@ pub fn set_point_size(size: f32) {
     }
 }
 
-pub fn get_vao() -> GLuint { get_int(gl::ARRAY_BUFFER_BINDING) as GLuint }
+pub fn get_vao() -> GLuint {
+    STATE.with_borrow(|state| state.vao).unwrap_or_else(|| get_int(gl::VERTEX_ARRAY_BINDING) as GLuint)
+}
 
 pub fn get_int(query: GLenum) -> GLint {
     let mut v = 0;
@@ -78,4 +256,72 @@ pub fn get_int_array<const S: usize>(query: GLenum) -> [GLint; S] {
         gl::GetIntegerv(query, &mut v[0]);
     }
     v
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::safe_calls::{self, StateCounters};
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn redundant_calls() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        safe_calls::invalidate_state();
+        let (mut vao, mut texture) = (0, 0);
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenTextures(1, &mut texture);
+        }
+        safe_calls::take_state_counters();
+        for _ in 0..3 {
+            safe_calls::bind_vao(vao);
+            safe_calls::bind_texture(2, texture);
+            safe_calls::set_depth_test(true);
+            safe_calls::set_blend(false);
+        }
+        //the texture binding also selects the unit the first time
+        assert_eq!(safe_calls::take_state_counters(), StateCounters { issued: 5, skipped: 8 });
+        assert_eq!(safe_calls::get_int(gl::VERTEX_ARRAY_BINDING) as u32, vao);
+        assert_eq!(safe_calls::get_int(gl::ACTIVE_TEXTURE) as u32, gl::TEXTURE2);
+        assert_eq!(safe_calls::get_int(gl::TEXTURE_BINDING_2D) as u32, texture);
+        //gl unbinds deleted objects, the cache must not skip the next bind of the reused name
+        unsafe {
+            gl::DeleteVertexArrays(1, &vao);
+        }
+        safe_calls::forget_vao(vao);
+        safe_calls::bind_vao(0);
+        safe_calls::invalidate_state();
+        safe_calls::set_depth_test(true);
+        assert_eq!(safe_calls::take_state_counters(), StateCounters { issued: 2, skipped: 0 });
+    }
+
+    #[test]
+    fn texture_edits() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        safe_calls::invalidate_state();
+        let mut textures = [0; 3];
+        unsafe {
+            gl::GenTextures(3, textures.as_mut_ptr());
+        }
+        let [flat, other, cube] = textures;
+        safe_calls::bind_texture(0, flat);
+        safe_calls::bind_texture(3, other);
+        //flat is cached on unit 0, binding it again does not select unit 0
+        safe_calls::bind_texture(0, flat);
+        assert_eq!(safe_calls::get_int(gl::ACTIVE_TEXTURE) as u32, gl::TEXTURE3);
+        safe_calls::edit_texture(gl::TEXTURE_2D, flat);
+        assert_eq!(safe_calls::get_int(gl::ACTIVE_TEXTURE) as u32, gl::TEXTURE0);
+        assert_eq!(safe_calls::get_int(gl::TEXTURE_BINDING_2D) as u32, flat);
+        //the cache keys on the target: a cube map on the unit does not hide the 2d binding
+        safe_calls::bind_texture_target(3, gl::TEXTURE_CUBE_MAP, cube);
+        safe_calls::take_state_counters();
+        safe_calls::bind_texture(3, other);
+        assert_eq!(safe_calls::take_state_counters(), StateCounters { issued: 1, skipped: 1 });
+        assert_eq!(safe_calls::get_int(gl::TEXTURE_BINDING_2D) as u32, other);
+        assert_eq!(safe_calls::get_int(gl::TEXTURE_BINDING_CUBE_MAP) as u32, cube);
+        unsafe {
+            gl::DeleteTextures(3, textures.as_ptr());
+        }
+        textures.iter().for_each(|t| safe_calls::forget_texture(*t));
+    }
+}
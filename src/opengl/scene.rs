@@ -1,79 +1,84 @@
-use std::collections::HashSet;
-use std::os::raw::c_void;
-use gl::types::GLint;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use gl::types::GLuint;
 use crate::maths::matrix::{Mat4, Matrix};
 use crate::maths::transform::Transform;
-use crate::opengl::enums::Shaders;
+use crate::maths::vector::Vec3;
+use crate::opengl::bvh::Bvh;
+use crate::opengl::culling::{CullInstance, CullModel, GpuCulling};
+use crate::opengl::deferred::{DeferredRenderer, Renderer};
+use crate::opengl::indirect::{DrawStats, IndirectRenderer};
+use crate::opengl::instances::InstanceData;
+use crate::opengl::frustrum::Frustrum;
+use crate::opengl::lights::{Light, Lights};
+use crate::opengl::lights::shadows::{cascade_splits, fit_directional, frustum_slice, ShadowDraw};
 use crate::opengl::main_shader::MainShader;
+use crate::opengl::object::MultiPartModel;
+use crate::opengl::picking::{inside_polygon, PickingHandler};
+use crate::opengl::post::antialiasing::AntiAliasing;
+use crate::opengl::post::PostChain;
+use crate::opengl::ssao::{Ssao, SsaoSettings};
+use crate::opengl::ray::{Hit, Ray};
 use crate::opengl::safe_calls;
-use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
-use crate::opengl::uniform::Uniform;
+use crate::opengl::shader::ShaderProgram;
+use crate::opengl::transparency::{TransparencyMode, TransparentPass};
+use crate::opengl::visibility::Visibility;
+use crate::opengl::volume::Aabb;
 use crate::other::itermap::IterMap;
 use crate::other::resource_manager::ResourceManager;
 
+const NEAR: f32 = 0.01; //clip planes of the main camera
+const FAR: f32 = 1000.;
+
 #[derive(Debug)]
-struct PickingHandler {
-    shader: ShaderProgram,
-    camera_uniform: Uniform,
-    projection_uniform: Uniform,
-    instances_uniform: Uniform,
-    id_uniform: Uniform
+pub struct ObjectData {
+    transform: Transform,
+    raw_mat: [f32; 16],
+    flags: i32,
+    fade: f32, //0: colored faces, 1: textured faces
+    visible: bool,
+    dirty: bool, //modified since the last update of the scene (matrix, visibility and batches)
 }
 
-impl PickingHandler {
-    fn new() -> Self {
-        let shader = ShaderProgramBuilder::default()
-            .add_shader(Shaders::Vertex, include_str!("picking.vert"))
-            .add_shader(Shaders::Fragment, include_str!("picking.frag"))
-            .build().unwrap();
-        Self {
-            camera_uniform: shader.uniform("camera"),
-            projection_uniform: shader.uniform("projection"),
-            instances_uniform: shader.uniform("object"),
-            id_uniform: shader.uniform("id"),
-            shader,
-        }
+impl ObjectData {
+    pub fn with_flags(mut self, flags: i32) -> Self {
+        self.flags = flags;
+        self
     }
-}
 
-pub const MAX_BATCH_SIZE: usize = 128;
+    pub fn transform(&self) -> &Transform { &self.transform }
 
-#[derive(Debug)]
-pub struct Batch {
-    pub size: usize,
-    pub mat: [f32; MAX_BATCH_SIZE * 16],
-    pub rf: [i32; MAX_BATCH_SIZE],
-}
+    pub fn transform_mut(&mut self) -> &mut Transform {
+        self.dirty = true;
+        &mut self.transform
+    }
 
-impl Default for Batch {
-    fn default() -> Self {
-        Self {
-            size: 0,
-            mat: [0f32; MAX_BATCH_SIZE * 16],
-            rf: [0i32; MAX_BATCH_SIZE],
-        }
+    pub fn flags(&self) -> i32 { self.flags }
+
+    pub fn set_flags(&mut self, flags: i32) {
+        self.dirty |= self.flags != flags;
+        self.flags = flags;
     }
-}
 
-impl Batch {
-    pub fn bake(&self, shader: &MainShader) {
-        shader.object.raw_array_mat4(&self.mat);
-        shader.flags.array_int(&self.rf);
+    pub fn fade(&self) -> f32 { self.fade }
+
+    pub fn set_fade(&mut self, fade: f32) {
+        self.dirty |= self.fade != fade;
+        self.fade = fade;
     }
-}
 
-#[derive(Debug)]
-pub struct ObjectData {
-    pub transform: Transform,
-    pub raw_mat: [f32; 16],
-    pub flags: i32,
-    pub visible: bool,
-}
+    pub fn visible(&self) -> bool { self.visible }
 
-impl ObjectData {
-    pub fn with_flags(mut self, flags: i32) -> Self {
-        self.flags = flags;
-        self
+    pub fn set_visible(&mut self, visible: bool) {
+        self.dirty |= self.visible != visible;
+        self.visible = visible;
+    }
+
+    fn clean(&mut self) {
+        if self.dirty {
+            Mat4::from(&self.transform).raw_copy(&mut self.raw_mat);
+            self.dirty = false;
+        }
     }
 }
 
@@ -83,11 +88,23 @@ impl From<Transform> for ObjectData {
             raw_mat: Mat4::from(&value).raw_array(),
             transform: value,
             flags: 0,
-            visible: true
+            fade: 0.,
+            visible: true,
+            dirty: true
         }
     }
 }
 
+//instances of a model seen by the main camera sorted per lod level (one instanced draw per level), only rebuilt and uploaded when one of them changed
+#[derive(Debug, Default)]
+struct ModelBatches {
+    levels: Vec<Range<usize>>, //per lod level, range of the uploaded instances
+    ids: Vec<usize>, //instance of each uploaded slot
+    data: Vec<InstanceData>, //uploaded instances, kept for the indirect path
+    fresh: bool, //rebuilt since the indirect path last uploaded it
+    dirty: bool
+}
+
 #[derive(Debug)]
 pub struct Scene {
     camera: Transform,
@@ -96,7 +113,25 @@ pub struct Scene {
     instances: IterMap<usize, IterMap<usize, ObjectData>>,
     shader: MainShader,
     picking_handler: PickingHandler,
-    batch_storage: Vec<Batch>
+    views: Vec<Visibility>, //0 is the main camera
+    batches: HashMap<usize, ModelBatches>,
+    dirty: Vec<(usize, usize)>, //(model, instance) modified since the last update
+    owners: HashMap<usize, usize>, //instance -> model
+    bvh: Bvh, //world bounds of the instances
+    bounds_stale: bool,
+    lod_scale: f32, //inverse of the tangent of half the vertical fov
+    indirect: Option<IndirectRenderer>,
+    culling: Option<GpuCulling>, //replaces the culling and batching of the main camera, drawn by the indirect path
+    culling_stale: bool, //an instance changed since the last upload to the gpu culling
+    lights: Lights,
+    shadows_stale: bool, //an instance changed since the casters of the shadow maps were collected
+    cascade_debug: bool,
+    transparent: TransparentPass,
+    post: Option<PostChain>, //the scene is drawn into an hdr target resolved by the effects of the chain
+    antialiasing: AntiAliasing,
+    ssao: Option<Ssao>,
+    deferred: Option<DeferredRenderer>, //the opaque geometry is lit from a g-buffer
+    stats: DrawStats
 }
 
 impl Scene {
@@ -108,26 +143,44 @@ impl Scene {
             instances: IterMap::new(),
             shader: MainShader::new(shader),
             picking_handler: PickingHandler::new(),
-            batch_storage: Vec::new()
+            views: vec![Visibility::new(&Mat4::identity())],
+            batches: HashMap::new(),
+            dirty: Vec::new(),
+            owners: HashMap::new(),
+            bvh: Bvh::default(),
+            bounds_stale: false,
+            lod_scale: 1.,
+            indirect: None,
+            culling: None,
+            culling_stale: true,
+            lights: Lights::new(),
+            shadows_stale: true,
+            cascade_debug: false,
+            transparent: TransparentPass::default(),
+            post: Some(PostChain::default()),
+            antialiasing: AntiAliasing::None,
+            ssao: None,
+            deferred: None,
+            stats: DrawStats::default()
         }
     }
     
     pub fn set_projection(&mut self, fov: f32, aspect_ratio: f32) {
-        let proj = Matrix::projection(fov.to_radians(), aspect_ratio, 0.01, 1000.);
+        let proj = Matrix::projection(fov.to_radians(), aspect_ratio, NEAR, FAR);
+        self.lod_scale = 1. / (fov.to_radians() / 2.).tan();
         self.shader.program.set_active();
         self.shader.projection.mat4(proj);
         self.projection = proj;
-        self.picking_handler.shader.set_active();
-        self.picking_handler.projection_uniform.mat4(proj);
+        self.views[0].set_view_projection(&(proj * self.camera.as_view_matrix()));
     }
     
     pub fn set_camera(&mut self, camera: Transform) {
         let mat = camera.as_view_matrix();
         self.shader.program.set_active();
         self.shader.camera.mat4(mat);
-        self.picking_handler.shader.set_active();
-        self.picking_handler.camera_uniform.mat4(mat);
+        self.shader.eye.vec3(camera.pos);
         self.camera = camera;
+        self.views[0].set_view_projection(&(self.projection * mat));
     }
     
     pub fn get_camera(&self) -> Transform { self.camera }
@@ -137,113 +190,685 @@ impl Scene {
     pub fn spawn_object(&mut self, model: usize, data: ObjectData) -> usize {
         let v = self.instances.get_mut_or_insert(&model, |_| IterMap::new());
         v.insert(self.next_instance_id, data);
+        self.dirty.push((model, self.next_instance_id));
+        self.owners.insert(self.next_instance_id, model);
         self.next_instance_id += 1;
         self.next_instance_id - 1
     }
     
     pub fn despawn_object(&mut self, id: usize) {
-        let mut check = None;
-        for (k, v) in self.instances.iter_mut() {
-            if let Some(_) = v.remove(&id) {
-                check = Some(*k);
+        if let Some(model) = self.owners.remove(&id) {
+            let instances = self.instances.get_mut(&model).unwrap();
+            instances.remove(&id);
+            for view in &mut self.views {
+                view.remove(id);
+            }
+            self.bvh.remove(id);
+            self.batches.entry(model).or_default().dirty = true;
+            self.culling_stale = true;
+            self.shadows_stale = true;
+            if instances.len() == 0 {
+                self.instances.remove(&model);
+                self.batches.remove(&model);
+            }
+        }
+    }
+
+    ///only the first MAX_LIGHTS lights (in order of spawn) lit the scene
+    pub fn spawn_light(&mut self, light: Light) -> usize {
+        self.lights.spawn(light)
+    }
+
+    pub fn despawn_light(&mut self, id: usize) -> Option<Light> {
+        self.lights.despawn(id)
+    }
+
+    pub fn light(&self, id: usize) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    ///the lights are uploaded again at the next draw
+    pub fn light_mut(&mut self, id: usize) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    pub fn lights(&self) -> impl Iterator<Item = &(usize, Light)> {
+        self.lights.iter()
+    }
+
+    pub fn set_ambient_light(&mut self, color: Vec3) {
+        self.lights.set_ambient(color);
+    }
+
+    ///how the transparent parts (materials with a transparency or a transparency map) are blended
+    pub fn set_transparency(&mut self, mode: TransparencyMode) {
+        self.transparent.set_mode(mode);
+    }
+
+    pub fn transparency(&self) -> TransparencyMode { self.transparent.mode() }
+
+    ///draw through the post processing chain (default effects: tonemapping, vignette, gamma, color grading) or directly to the bound framebuffer
+    pub fn set_post_processing(&mut self, enabled: bool) {
+        if enabled != self.post.is_some() {
+            self.post = enabled.then(PostChain::default);
+            if let Some(post) = &mut self.post {
+                post.set_antialiasing(self.antialiasing);
+            }
+        }
+    }
+
+    pub fn post_processing(&self) -> Option<&PostChain> { self.post.as_ref() }
+
+    pub fn post_processing_mut(&mut self) -> Option<&mut PostChain> { self.post.as_mut() }
+
+    ///applied by the post processing chain, without it only the multisampling of the window (if created with samples) is left
+    pub fn set_antialiasing(&mut self, mode: AntiAliasing) {
+        self.antialiasing = mode;
+        if let Some(post) = &mut self.post {
+            post.set_antialiasing(mode);
+        }
+    }
+
+    pub fn antialiasing(&self) -> AntiAliasing { self.antialiasing }
+
+    ///screen space ambient occlusion of the opaque geometry, computed from a depth / normal prepass (None: disabled)
+    pub fn set_ambient_occlusion(&mut self, settings: Option<SsaoSettings>) {
+        match (settings, &mut self.ssao) {
+            (Some(settings), Some(ssao)) => ssao.set_settings(settings),
+            (settings, ssao) => *ssao = settings.map(Ssao::new)
+        }
+    }
+
+    pub fn ambient_occlusion(&self) -> Option<SsaoSettings> { self.ssao.as_ref().map(Ssao::settings) }
+
+    ///shading of the opaque geometry, the deferred programs are variants of the default shader of the resources (stays forward if they could not be built)
+    ///the multisampling only smooths the forward parts of the deferred renderer, the g-buffer has a sample per pixel
+    pub fn set_renderer(&mut self, resources: &mut ResourceManager, renderer: Renderer) {
+        if renderer != self.renderer() {
+            self.deferred = match renderer {
+                Renderer::Forward => None,
+                Renderer::Deferred => DeferredRenderer::new(resources)
+            };
+        }
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        if self.deferred.is_some() { Renderer::Deferred } else { Renderer::Forward }
+    }
+
+    ///tint the fragments by cascade of the first directional shadow (red, green, blue, yellow, white past the last one)
+    pub fn set_cascade_debug(&mut self, enabled: bool) {
+        self.cascade_debug = enabled;
+        self.shader.program.set_active();
+        self.shader.debug_cascades.int(enabled as i32);
+    }
+
+    pub fn cascade_debug(&self) -> bool { self.cascade_debug }
+
+    ///point lights casting shadows past this amount (in order of spawn) are lit without shadows, clamped to MAX_CUBE_SHADOW_MAPS
+    pub fn set_max_point_shadows(&mut self, max: usize) {
+        self.lights.set_max_point_shadows(max);
+    }
+
+    ///add a view (light, secondary camera, etc...) whose visible set is maintained with the main camera one
+    pub fn add_view(&mut self, vp: &Mat4) -> usize {
+        self.views.push(Visibility::new(vp));
+        self.views.len() - 1
+    }
+
+    pub fn set_view(&mut self, view: usize, vp: &Mat4) {
+        self.views[view].set_view_projection(vp);
+    }
+
+    ///instances seen by a view as of the last update (0 is the main camera)
+    pub fn visible_set(&self, view: usize) -> &HashSet<usize> {
+        self.views[view].visible()
+    }
+
+    ///force every view to test all the instances again (ex: after the models were rebuilt)
+    pub fn invalidate(&mut self) {
+        self.views.iter_mut().for_each(Visibility::invalidate);
+        self.bounds_stale = true;
+    }
+
+    ///apply the modifications since the last call: matrices of the modified instances, visible sets and batches of the main camera
+    ///with nothing modified, the cost is independent of the amount of instances
+    pub fn update(&mut self, resources: &ResourceManager) {
+        self.refresh(resources, true);
+    }
+
+    ///same as update, the full test of the main camera can be skipped when the gpu culls it
+    fn refresh(&mut self, resources: &ResourceManager, main_view: bool) {
+        if self.bounds_stale {
+            //models were rebuilt: every world box has to be recomputed
+            self.bounds_stale = false;
+            for (model, instances) in self.instances.iter() {
+                self.dirty.extend(instances.iter().map(|(id, _)| (*model, *id)));
+            }
+        }
+        self.culling_stale |= !self.dirty.is_empty();
+        self.shadows_stale |= !self.dirty.is_empty();
+        for (model, id) in std::mem::take(&mut self.dirty) {
+            let mpm = resources.get_multipart_model(model);
+            if let Some(data) = self.instances.get_mut(&model).and_then(|i| i.get_mut(&id)) {
+                data.clean();
+                if let Some(mpm) = mpm {
+                    self.bvh.update(id, &mpm.bounds().transformed(&data.transform));
+                } else {
+                    self.bvh.remove(id);
+                }
+                let mut changed = false;
+                for (i, view) in self.views.iter_mut().enumerate().filter(|(_, v)| !v.is_stale()) {
+                    changed |= view.test(id, mpm, data) && i == 0;
+                }
+                //entering/leaving the main view, or still in it with a new matrix or flags
+                if changed || self.views[0].contains(id) {
+                    self.batches.entry(model).or_default().dirty = true;
+                }
+            }
+        }
+        if self.views[0].is_stale() {
+            for (model, _) in self.instances.iter() {
+                self.batches.entry(*model).or_default().dirty = true;
+            }
+        }
+        for view in self.views.iter_mut().enumerate().filter(|(i, v)| v.is_stale() && (main_view || *i != 0)).map(|(_, v)| v) {
+            let mut visible = HashSet::new();
+            self.bvh.query_frustum(view.frustrum(), |id, inside| {
+                if let Some((model, data)) = self.owners.get(&id).and_then(|m| self.instances.get(m).and_then(|i| i.get(&id)).map(|d| (*m, d))) {
+                    if data.visible && (inside || resources.get_multipart_model(model).is_some_and(|m| m.visible(&data.transform, view.frustrum()))) {
+                        visible.insert(id);
+                    }
+                }
+            });
+            view.replace(visible);
+        }
+    }
+
+    ///visible instances whose bounds are crossed by a ray (direction does not need to be normalized), sorted by distance of entry in their box
+    pub fn instances_on_ray(&self, origin: &Vec3, direction: &Vec3) -> Vec<(usize, f32)> {
+        let mut out = Vec::new();
+        self.bvh.query_ray(origin, direction, f32::INFINITY, |id, d| {
+            if self.instance(id).is_some_and(|data| data.visible) {
+                out.push((id, d));
+            }
+        });
+        out.sort_by(|a, b| a.1.total_cmp(&b.1));
+        out
+    }
+
+    ///visible instances whose bounds (loose) intersect the sphere
+    pub fn instances_in_sphere(&self, center: &Vec3, radius: f32) -> Vec<usize> {
+        let mut out = Vec::new();
+        self.bvh.query_sphere(center, radius, |id| {
+            if self.instance(id).is_some_and(|data| data.visible) {
+                out.push(id);
+            }
+        });
+        out
+    }
+
+    ///visible instances whose bounds (loose) intersect the box
+    pub fn instances_in_box(&self, aabb: &Aabb) -> Vec<usize> {
+        let mut out = Vec::new();
+        self.bvh.query_box(aabb, |id| {
+            if self.instance(id).is_some_and(|data| data.visible) {
+                out.push(id);
+            }
+        });
+        out
+    }
+
+    ///ray of the main camera going through a pixel (origin at the bottom left of the viewport)
+    pub fn screen_ray(&self, pixel_x: f32, pixel_y: f32) -> Option<Ray> {
+        let (width, height) = safe_calls::get_size();
+        Ray::from_screen(&(self.projection * self.camera.as_view_matrix()), pixel_x, pixel_y, width as f32, height as f32)
+    }
+
+    ///closest triangle of the visible instances crossed by a ray, instances are tested in order of entry in their bounds until one is hit before the next entry
+    pub fn raycast(&mut self, resources: &ResourceManager, ray: &Ray) -> Option<Hit> {
+        self.update(resources);
+        let mut best: Option<Hit> = None;
+        for (id, entry) in self.instances_on_ray(&ray.origin, &ray.direction) {
+            if best.is_some_and(|b| b.distance < entry) {
                 break;
             }
+            let mpm = self.instance_model(id).and_then(|m| resources.get_multipart_model(m));
+            if let (Some(mpm), Some(data)) = (mpm, self.instance(id)) {
+                if let Some(hit) = mpm.raycast(ray, &data.transform, best.map_or(f32::INFINITY, |b| b.distance)) {
+                    best = Some(Hit { instance: id, ..hit });
+                }
+            }
         }
-        if let Some(c) = check {
-            if self.instances.get(&c).unwrap().len() == 0 {
-                self.instances.remove(&c);
+        best
+    }
+
+    ///cpu picking: closest instance triangle under a pixel, with the hit point and normal
+    pub fn pick_ray(&mut self, resources: &ResourceManager, pixel_x: f32, pixel_y: f32) -> Option<Hit> {
+        let ray = self.screen_ray(pixel_x, pixel_y)?;
+        self.raycast(resources, &ray)
+    }
+
+    pub fn instance(&self, id: usize) -> Option<&ObjectData> {
+        self.owners.get(&id).and_then(|m| self.instances.get(m)).and_then(|i| i.get(&id))
+    }
+
+    pub fn instance_model(&self, id: usize) -> Option<usize> {
+        self.owners.get(&id).copied()
+    }
+
+    ///fraction of the screen height covered by the bounding sphere of an instance
+    fn screen_size(transform: &Transform, radius: f32, eye: &Vec3, lod_scale: f32) -> f32 {
+        let radius = radius * transform.scale[0].abs().max(transform.scale[1].abs()).max(transform.scale[2].abs());
+        let d = transform.pos - *eye;
+        let distance = d.dot(&d).sqrt();
+        if distance <= radius {
+            f32::MAX
+        } else {
+            radius * lod_scale / distance
+        }
+    }
+
+    ///sort the instances per lod level in the cache
+    fn extract_batches<'a, L: Fn(&ObjectData) -> usize>(cache: &mut ModelBatches, levels: usize, instances: impl Iterator<Item = &'a (usize, ObjectData)>, lod: L) {
+        let mut sorted = instances.map(|(id, data)| (lod(data).min(levels - 1), *id, data)).collect::<Vec<_>>();
+        sorted.sort_by_key(|(level, ..)| *level);
+        cache.levels.clear();
+        cache.levels.resize(levels, 0..0);
+        cache.ids.clear();
+        cache.data.clear();
+        for (i, (level, id, data)) in sorted.into_iter().enumerate() {
+            let range = &mut cache.levels[level];
+            if range.start == range.end {
+                *range = i..i;
             }
+            range.end = i + 1;
+            cache.ids.push(id);
+            cache.data.push(InstanceData {
+                mat: data.raw_mat,
+                flags: data.flags,
+                fade: data.fade,
+                id: id as u32
+            });
         }
     }
 
-    fn extract_batches<'a>(storage: &mut Vec<Batch>, instances: impl Iterator<Item = &'a ObjectData>) {
-        storage.clear();
-        storage.push(Batch::default());
-        for ObjectData { transform, raw_mat, flags, visible } in instances {
-            let batch = {
-                if storage.last().unwrap().size == MAX_BATCH_SIZE {
-                    storage.push(Batch::default());
+    ///rebuild and upload the instances of a model if needed (an instance changed or the model has a different amount of lods)
+    fn model_batches<'a>(batches: &'a mut HashMap<usize, ModelBatches>, view: &Visibility, camera: &Transform, lod_scale: f32, model: usize, mpm: &MultiPartModel, instances: &IterMap<usize, ObjectData>) -> &'a mut ModelBatches {
+        let cache = batches.entry(model).or_default();
+        if cache.dirty || cache.levels.len() != mpm.lod_count() {
+            let radius = mpm.radius();
+            let lod = |data: &ObjectData| mpm.select_lod(Self::screen_size(&data.transform, radius, &camera.pos, lod_scale));
+            Self::extract_batches(cache, mpm.lod_count(), instances.iter().filter(|(id, _)| view.contains(*id)), lod);
+            mpm.upload_instances(&cache.data);
+            cache.fresh = true;
+            cache.dirty = false;
+        }
+        cache
+    }
+
+    ///instances of the casters sorted per model and lod level (model, lod, first, count), with the same lods as the main camera
+    ///so the shadows match the drawn silhouettes
+    fn shadow_batches(instances: &IterMap<usize, IterMap<usize, ObjectData>>, eye: &Vec3, lod_scale: f32, resources: &ResourceManager, casters: &HashSet<usize>) -> (Vec<ShadowDraw>, Vec<InstanceData>) {
+        let mut draws = Vec::new();
+        let mut data = Vec::new();
+        let mut cache = ModelBatches::default();
+        for (model, instances) in instances.iter() {
+            if let Some(mpm) = resources.get_multipart_model(*model) {
+                let radius = mpm.radius();
+                let lod = |d: &ObjectData| mpm.select_lod(Self::screen_size(&d.transform, radius, eye, lod_scale));
+                Self::extract_batches(&mut cache, mpm.lod_count(), instances.iter().filter(|(id, _)| casters.contains(id)), lod);
+                for (lod, range) in cache.levels.iter().enumerate().filter(|(_, r)| r.start < r.end) {
+                    draws.push((*model, lod, data.len() + range.start, range.len()));
                 }
-                storage.last_mut().unwrap()
-            };
-            for i in 0..16 {
-                batch.mat[batch.size * 16 + i] = raw_mat[i];
+                data.extend_from_slice(&cache.data);
             }
-            batch.rf[batch.size] = *flags;
-            batch.size += 1;
         }
+        (draws, data)
     }
 
-    pub fn pick(&mut self, resources: &ResourceManager, pixel_x: usize, pixel_y: usize, set: Option<&HashSet<usize>>) -> Option<usize> {
-        let mut acc_vec = Vec::new();
-        safe_calls::clear_screen();
-        self.picking_handler.shader.set_active();
-        for (model, instances) in self.instances.iter_mut() {
+    ///draw the ids of the instances seen by the main camera in the offscreen id buffer, returns the instance of each id (id - 1)
+    fn draw_ids(&mut self, resources: &ResourceManager) -> Vec<usize> {
+        self.update(resources);
+        let mut table = Vec::new();
+        self.picking_handler.begin(self.projection, self.camera.as_view_matrix());
+        for (model, instances) in self.instances.iter() {
             if let Some(mpm) = resources.get_multipart_model(*model) {
-                if let Some(set) = set {
-                    Self::extract_batches(&mut self.batch_storage, instances.iter().filter_map(|(id, v)| {
-                        if v.visible && set.contains(id) {
-                            Some(v)
-                        } else {
-                            None
+                let cache = Self::model_batches(&mut self.batches, &self.views[0], &self.camera, self.lod_scale, *model, mpm, instances);
+                for (lod, range) in cache.levels.iter().enumerate().filter(|(_, r)| r.start < r.end) {
+                    self.picking_handler.id_uniform.int(table.len() as i32);
+                    table.extend_from_slice(&cache.ids[range.clone()]);
+                    mpm.draw_instances(range.start, range.len(), None, lod);
+                }
+            }
+        }
+        self.picking_handler.end();
+        table
+    }
+
+    ///instances drawn on the pixels of a region (origin at the bottom left) for which the filter returns true, sorted and without duplicates
+    fn ids_in_region<F: Fn(usize, usize) -> bool>(&mut self, resources: &ResourceManager, x: usize, y: usize, width: usize, height: usize, filter: F) -> Vec<usize> {
+        let table = self.draw_ids(resources);
+        let (pixels, width, _) = self.picking_handler.read(x, y, width, height);
+        let mut out = pixels.iter().enumerate()
+            .filter(|(i, id)| **id > 0 && filter(x + i % width, y + i / width))
+            .filter_map(|(_, id)| table.get(*id as usize - 1).copied())
+            .collect::<Vec<_>>();
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+
+    ///gpu picking: instance drawn on a pixel (origin at the bottom left)
+    pub fn pick(&mut self, resources: &ResourceManager, pixel_x: usize, pixel_y: usize) -> Option<usize> {
+        self.ids_in_region(resources, pixel_x, pixel_y, 1, 1, |_, _| true).first().copied()
+    }
+
+    ///every instance with at least a pixel visible in a rectangle between two corners (inclusive, origin at the bottom left)
+    pub fn pick_rect(&mut self, resources: &ResourceManager, a: (usize, usize), b: (usize, usize)) -> Vec<usize> {
+        let (x, y) = (a.0.min(b.0), a.1.min(b.1));
+        self.ids_in_region(resources, x, y, a.0.max(b.0) - x + 1, a.1.max(b.1) - y + 1, |_, _| true)
+    }
+
+    ///every instance with at least a pixel visible inside a polygon (screen coordinates, origin at the bottom left)
+    pub fn pick_lasso(&mut self, resources: &ResourceManager, polygon: &[[f32; 2]]) -> Vec<usize> {
+        if polygon.len() < 3 {
+            return Vec::new();
+        }
+        let (min, max) = polygon.iter().fold(([f32::MAX; 2], [f32::MIN; 2]), |(min, max), p| ([min[0].min(p[0]), min[1].min(p[1])], [max[0].max(p[0]), max[1].max(p[1])]));
+        let (x, y) = (min[0].max(0.) as usize, min[1].max(0.) as usize);
+        let (width, height) = ((max[0].max(0.) as usize + 1).saturating_sub(x), (max[1].max(0.) as usize + 1).saturating_sub(y));
+        self.ids_in_region(resources, x, y, width, height, |px, py| inside_polygon([px as f32 + 0.5, py as f32 + 0.5], polygon))
+    }
+
+    ///draw every model with a single indirect multi draw (or a loop of draws on gl 3.3) instead of a draw per model and lod level
+    pub fn set_indirect(&mut self, enabled: bool) {
+        if enabled != self.indirect.is_some() {
+            self.indirect = if enabled { Some(IndirectRenderer::new()) } else { None };
+        }
+        if !enabled {
+            self.culling = None;
+        }
+    }
+
+    pub fn indirect(&self) -> Option<&IndirectRenderer> { self.indirect.as_ref() }
+
+    pub fn indirect_mut(&mut self) -> Option<&mut IndirectRenderer> { self.indirect.as_mut() }
+
+    ///cull and select the lods of the main camera on the gpu (enables the indirect path)
+    pub fn set_gpu_culling(&mut self, enabled: bool) {
+        if enabled != self.culling.is_some() {
+            if enabled {
+                self.set_indirect(true);
+                self.culling = Some(GpuCulling::new());
+                self.culling_stale = true;
+            } else {
+                self.culling = None;
+            }
+        }
+    }
+
+    pub fn gpu_culling(&self) -> Option<&GpuCulling> { self.culling.as_ref() }
+
+    pub fn gpu_culling_mut(&mut self) -> Option<&mut GpuCulling> { self.culling.as_mut() }
+
+    ///counters of the last call to draw
+    pub fn stats(&self) -> DrawStats { self.stats }
+
+    ///render the shadow maps of the lights (fitted to the part of the camera frustum they cover) and bind the lights for the main pass
+    ///the casters are collected and drawn again only when the light matrix or an instance changed
+    fn draw_shadows(&mut self, resources: &ResourceManager) {
+        self.lights.sync_shadows();
+        let stale = std::mem::take(&mut self.shadows_stale);
+        let vp = self.projection * self.camera.as_view_matrix();
+        let bounds = self.bvh.bounds();
+        let previous = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let viewport = safe_calls::get_int_array::<4>(gl::VIEWPORT);
+        let (program, shadows) = self.lights.shadows_mut();
+        for (direction, shadow) in shadows {
+            let distance = shadow.settings.distance.clamp(NEAR, FAR);
+            let splits = cascade_splits(NEAR, distance, shadow.cascades.len() as u32, shadow.settings.split_blend);
+            let mut from = NEAR;
+            for (layer, (cascade, split)) in shadow.cascades.iter_mut().zip(splits).enumerate() {
+                //each slice starts early enough to cover the end of the previous one, faded into it
+                let start = from;
+                from = split - (split - start) * shadow.settings.cascade_blend.clamp(0., 1.);
+                cascade.split = split;
+                let corners = frustum_slice(&vp, NEAR, FAR, start, split);
+                let light_space = fit_directional(&direction, &corners, bounds.as_ref(), shadow.settings.resolution);
+                if !stale && light_space == cascade.light_space {
+                    continue;
+                }
+                cascade.light_space = light_space;
+                let frustrum = Frustrum::from_vp(&light_space);
+                let mut casters = HashSet::new();
+                self.bvh.query_frustum(&frustrum, |id, inside| {
+                    if let Some((model, data)) = self.owners.get(&id).and_then(|m| self.instances.get(m).and_then(|i| i.get(&id)).map(|d| (*m, d))) {
+                        if data.visible && (inside || resources.get_multipart_model(model).is_some_and(|m| m.visible(&data.transform, &frustrum))) {
+                            casters.insert(id);
                         }
-                    }));
-                } else {
-                    Self::extract_batches(&mut self.batch_storage, instances.iter_values().filter(|v| v.visible));
+                    }
+                });
+                let (draws, data) = Self::shadow_batches(&self.instances, &self.camera.pos, self.lod_scale, resources, &casters);
+                cascade.casters.upload(draws, &data);
+                shadow.map.begin(layer as u32);
+                program.begin(cascade.light_space);
+                unsafe {
+                    gl::Enable(gl::POLYGON_OFFSET_FILL);
+                    gl::PolygonOffset(shadow.settings.slope_bias, shadow.settings.bias);
                 }
-                let l = acc_vec.len();
-                for (c, Batch { size, mat, rf }) in self.batch_storage.iter().enumerate() {
-                    // self.picking_handler.instances_uniform.raw_array_mat4(&mat[0..*size * 16]);
-                    self.picking_handler.id_uniform.int((l + c * MAX_BATCH_SIZE) as i32);
-                    mpm.draw_instances(*size, None);
+                cascade.casters.draw(resources);
+                unsafe {
+                    gl::Disable(gl::POLYGON_OFFSET_FILL);
                 }
-                acc_vec.extend(instances.iter().map(|(k, _)| *k));
             }
         }
-        let t = [0u8; 4];
+        let (program, shadows) = self.lights.point_shadows_mut();
+        for (position, far, shadow) in shadows {
+            if !stale && position == shadow.position && far == shadow.far {
+                continue;
+            }
+            shadow.position = position;
+            shadow.far = far;
+            let mut casters = HashSet::new();
+            self.bvh.query_sphere(&position, far, |id| {
+                if self.owners.get(&id).and_then(|m| self.instances.get(m)).and_then(|i| i.get(&id)).is_some_and(|data| data.visible) {
+                    casters.insert(id);
+                }
+            });
+            let (draws, data) = Self::shadow_batches(&self.instances, &self.camera.pos, self.lod_scale, resources, &casters);
+            shadow.casters.upload(draws, &data);
+            shadow.map.begin();
+            program.begin(shadow);
+            shadow.casters.draw(resources);
+        }
         unsafe {
-            gl::Flush();
-            gl::Finish();
-            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
-            gl::ReadPixels(pixel_x as GLint, pixel_y as GLint, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, t.as_ptr() as *mut f32 as *mut c_void);
-        }
-        let id = t[3] as usize | ((t[2] as usize) << 8) | ((t[1] as usize) << 16) | ((t[0] as usize) << 24);
-        if id > 0 && id <= acc_vec.len() {
-            Some(acc_vec[id - 1])
-        } else {
-            None
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
         }
+        self.lights.bind();
     }
-    
-    pub fn draw(&mut self, resources: &ResourceManager, set: Option<&HashSet<usize>>) {
+
+    ///upload every instance to the gpu culling when one changed, cull them and draw the kept ones
+    fn draw_culled(&mut self, resources: &ResourceManager, projection: Mat4) {
+        self.refresh(resources, false);
+        self.draw_shadows(resources);
+        let (Some(indirect), Some(culling)) = (&mut self.indirect, &mut self.culling) else { return; };
+        indirect.prepare(resources);
+        if self.culling_stale || culling.generation() != indirect.generation() {
+            self.culling_stale = false;
+            let models = self.instances.iter().filter_map(|(model, instances)| {
+                let mpm = resources.get_multipart_model(*model)?;
+                let (sphere, radius) = (mpm.bounding_sphere(), mpm.radius());
+                let instances = instances.iter().filter(|(_, data)| data.visible).map(|(id, data)| {
+                    let world = sphere.transformed(&data.transform);
+                    let s = data.transform.scale;
+                    let pos = data.transform.pos;
+                    CullInstance::new(
+                        [world.center[0], world.center[1], world.center[2], world.radius],
+                        [pos[0], pos[1], pos[2], radius * s[0].abs().max(s[1].abs()).max(s[2].abs())],
+                        InstanceData { mat: data.raw_mat, flags: data.flags, fade: data.fade, id: *id as u32 }
+                    )
+                }).collect();
+                Some(CullModel { batch: indirect.batch(*model), levels: indirect.levels(*model), lod_sizes: mpm.lod_sizes(), instances })
+            }).collect();
+            culling.upload(models, indirect.generation());
+        }
+        culling.cull(self.views[0].frustrum(), &self.camera.pos, self.lod_scale);
+        self.draw_ambient_occlusion(resources, projection);
+        self.stats = DrawStats::default();
+        self.begin_opaque(projection);
+        let (Some(indirect), Some(culling)) = (&self.indirect, &self.culling) else { return; };
+        self.deferred.as_ref().map_or(&self.shader.program, DeferredRenderer::program).set_active();
+        indirect.draw_culled(culling, &mut self.stats);
+        self.end_opaque();
+        self.draw_transparent(resources);
+    }
+
+    pub fn draw(&mut self, resources: &ResourceManager) {
+        //the temporal anti-aliasing moves the projection by a fraction of a pixel every frame
+        let jitter = self.post.as_ref().and_then(PostChain::jitter);
+        let projection = jitter.map_or(self.projection, |(x, y)| Mat4::from_pos(&Vec3::new(x, y, 0.)) * self.projection);
         self.shader.program.set_active();
-        for (model, instances) in self.instances.iter_mut() {
+        self.shader.projection.mat4(projection);
+        if let Some(post) = &mut self.post {
+            post.begin(self.projection * self.camera.as_view_matrix());
+        }
+        self.draw_scene(resources, projection);
+        if let Some(post) = &mut self.post {
+            post.resolve();
+        }
+    }
+
+    fn draw_scene(&mut self, resources: &ResourceManager, projection: Mat4) {
+        if self.culling.is_some() && self.indirect.is_some() {
+            self.draw_culled(resources, projection);
+            return;
+        }
+        self.update(resources);
+        self.draw_shadows(resources);
+        self.draw_ambient_occlusion(resources, projection);
+        self.stats = DrawStats::default();
+        self.begin_opaque(projection);
+        if let Some(indirect) = &mut self.indirect {
+            indirect.prepare(resources);
+            indirect.begin();
+            let mut layout = Vec::new();
+            let mut changed = false;
+            let mut base = 0;
+            for (model, instances) in self.instances.iter() {
+                if let Some(mpm) = resources.get_multipart_model(*model) {
+                    let cache = Self::model_batches(&mut self.batches, &self.views[0], &self.camera, self.lod_scale, *model, mpm, instances);
+                    changed |= std::mem::take(&mut cache.fresh);
+                    for (lod, range) in cache.levels.iter().enumerate().filter(|(_, r)| r.start < r.end) {
+                        indirect.push(*model, lod, base + range.start, range.len());
+                    }
+                    layout.push((*model, cache.data.len()));
+                    base += cache.data.len();
+                }
+            }
+            //the instances of all the models are uploaded in a single buffer, only when one of them changed
+            if indirect.set_layout(layout) || changed {
+                let mut data = Vec::with_capacity(base);
+                for (model, _) in self.instances.iter() {
+                    if let Some(cache) = self.batches.get(model).filter(|_| resources.get_multipart_model(*model).is_some()) {
+                        data.extend_from_slice(&cache.data);
+                    }
+                }
+                indirect.upload_instances(&data);
+            }
+            indirect.draw(&mut self.stats);
+            self.end_opaque();
+            self.draw_transparent(resources);
+            return;
+        }
+        for (model, instances) in self.instances.iter() {
             if let Some(mpm) = resources.get_multipart_model(*model) {
-                if let Some(set) = set {
-                    Self::extract_batches(&mut self.batch_storage, instances.iter().filter_map(|(id, v)| {
-                        if v.visible && set.contains(id) {
-                            Some(v)
-                        } else {
-                            None
-                        }
-                    }));
-                } else {
-                    Self::extract_batches(&mut self.batch_storage, instances.iter_values().filter(|v| v.visible));
+                let cache = Self::model_batches(&mut self.batches, &self.views[0], &self.camera, self.lod_scale, *model, mpm, instances);
+                for (lod, range) in cache.levels.iter().enumerate().filter(|(_, r)| r.start < r.end) {
+                    mpm.draw_instances(range.start, range.len(), Some(&self.shader), lod);
+                    self.stats.draw_calls += 1;
+                    self.stats.instances += range.len();
+                    self.stats.triangles += mpm.opaque_len(lod) / 3 * range.len();
                 }
-                for Batch { size, mat, rf } in &self.batch_storage {
-                    self.shader.object.raw_array_mat4(&mat[0..*size * 16]);
-                    self.shader.flags.array_int(&rf[0..*size]);
-                    mpm.draw_instances(*size, Some(&self.shader));
+            }
+        }
+        self.end_opaque();
+        self.draw_transparent(resources);
+    }
+
+    ///activate the program of the opaque geometry: the main shader, or the g-buffer of the deferred renderer
+    fn begin_opaque(&mut self, projection: Mat4) {
+        match &mut self.deferred {
+            Some(deferred) => deferred.begin(projection, &self.camera, self.cascade_debug, self.ssao.is_some()),
+            None => self.shader.program.set_active()
+        }
+    }
+
+    ///light the g-buffer of the deferred renderer into the target of the scene
+    fn end_opaque(&mut self) {
+        if let Some(deferred) = &self.deferred {
+            deferred.resolve(&self.lights, &mut self.stats);
+        }
+    }
+
+    ///opaque instances seen by the camera drawn in the prepass of the ambient occlusion (the output of the gpu culling when it is enabled, culled before this call)
+    fn draw_ambient_occlusion(&mut self, resources: &ResourceManager, projection: Mat4) {
+        if let (Some(ssao), Some(indirect), Some(culling)) = (&mut self.ssao, &self.indirect, &self.culling) {
+            ssao.begin(projection, self.camera.as_view_matrix());
+            indirect.draw_culled(culling, &mut DrawStats::default());
+            ssao.resolve();
+        } else if self.ssao.is_some() {
+            self.update(resources);
+            let ssao = self.ssao.as_mut().unwrap();
+            ssao.begin(projection, self.camera.as_view_matrix());
+            for (model, instances) in self.instances.iter() {
+                if let Some(mpm) = resources.get_multipart_model(*model) {
+                    let cache = Self::model_batches(&mut self.batches, &self.views[0], &self.camera, self.lod_scale, *model, mpm, instances);
+                    for (lod, range) in cache.levels.iter().enumerate().filter(|(_, r)| r.start < r.end) {
+                        mpm.draw_opaque_instances(range.start, range.len(), lod);
+                    }
                 }
             }
+            ssao.resolve();
+        }
+        self.shader.program.set_active();
+        self.shader.ambient_occlusion.int(self.ssao.is_some() as i32);
+    }
+
+    ///transparent parts of the instances in the camera frustum, sorted back to front and blended over the opaque geometry
+    fn draw_transparent(&mut self, resources: &ResourceManager) {
+        //the occlusion belongs to the opaque geometry behind them
+        self.shader.program.set_active();
+        self.shader.ambient_occlusion.int(0);
+        let models = self.instances.iter().map(|(model, _)| *model)
+            .filter(|model| resources.get_multipart_model(*model).is_some_and(|m| m.has_transparency()))
+            .collect::<HashSet<_>>();
+        let mut instances = Vec::new();
+        if !models.is_empty() {
+            let frustrum = Frustrum::from_vp(&(self.projection * self.camera.as_view_matrix()));
+            self.bvh.query_frustum(&frustrum, |id, inside| {
+                let Some(model) = self.owners.get(&id).filter(|m| models.contains(*m)) else { return; };
+                let (Some(data), Some(mpm)) = (self.instances.get(model).and_then(|i| i.get(&id)), resources.get_multipart_model(*model)) else { return; };
+                if data.visible && (inside || mpm.visible(&data.transform, &frustrum)) {
+                    let d = data.transform.pos - self.camera.pos;
+                    let lod = mpm.select_lod(Self::screen_size(&data.transform, mpm.radius(), &self.camera.pos, self.lod_scale));
+                    instances.push((d.dot(&d), *model, lod, InstanceData { mat: data.raw_mat, flags: data.flags, fade: data.fade, id: id as u32 }));
+                }
+            });
         }
+        self.transparent.upload(instances);
+        self.transparent.draw(resources, &self.shader, &mut self.stats);
     }
 
     pub fn run_on_instance<F: FnMut(usize, usize, &mut ObjectData)>(&mut self, id: usize, mut runner: F) {
-        for (model, v) in self.instances.iter_mut() {
-            if let Some(v) = v.get_mut(&id) {
-                runner(*model, id, v);
-                return;
+        if let Some(&model) = self.owners.get(&id) {
+            if let Some(v) = self.instances.get_mut(&model).and_then(|i| i.get_mut(&id)) {
+                let was_dirty = v.dirty;
+                runner(model, id, v);
+                if v.dirty && !was_dirty {
+                    self.dirty.push((model, id));
+                }
             }
         }
     }
@@ -251,8 +876,12 @@ impl Scene {
     pub fn run_on_instances<F: FnMut(usize, usize, &mut ObjectData)>(&mut self, mut runner: F) {
         for (model, v) in self.instances.iter_mut() {
             for (id, v) in v.iter_mut() {
+                let was_dirty = v.dirty;
                 runner(*model, *id, v);
+                if v.dirty && !was_dirty {
+                    self.dirty.push((*model, *id));
+                }
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 use gl::{*, types::*};
 use crate::opengl::enums::Shaders;
+use crate::opengl::safe_calls;
 use crate::opengl::uniform::Uniform;
 use crate::other::resource_manager::ResourceManager;
 
@@ -16,7 +17,13 @@ pub struct ShaderProgram {
 
 impl ShaderProgram {
     pub fn from_resources(resources: &mut ResourceManager, name: &str) -> Option<Self> {
+        Self::from_resources_with(resources, name, &[])
+    }
+
+    ///variant of a program of the resources, the names are defined in each of its shaders
+    pub fn from_resources_with(resources: &mut ResourceManager, name: &str, defines: &[&str]) -> Option<Self> {
         let mut builder = ShaderProgramBuilder::default();
+        builder.defines(defines);
         builder.add_shader(Shaders::Vertex, resources.load_text(format!("{name}.vert")).map(|(_, v)| v)?.as_str());
         builder.add_shader(Shaders::Fragment, resources.load_text(format!("{name}.frag")).map(|(_, v)| v)?.as_str());
         if let Some((_, geo)) = resources.load_text(format!("{name}.geom")){
@@ -30,23 +37,63 @@ impl ShaderProgram {
     pub fn id(&self) -> GLuint { self.id }
 
     pub fn uniform(&self, name: &str) -> Uniform { Uniform::new(self, name) }
-    
-    pub fn set_active(&self) {
+
+    ///assign a binding point to a uniform block (ignored if the program does not use the block)
+    pub fn bind_uniform_block(&self, name: &str, binding: GLuint) {
         unsafe {
-            UseProgram(self.id);
+            let index = GetUniformBlockIndex(self.id, format!("{name}\0").as_ptr() as *const GLchar);
+            if index != INVALID_INDEX {
+                UniformBlockBinding(self.id, index, binding);
+            }
         }
     }
+
+    pub fn set_active(&self) {
+        safe_calls::use_program(self.id);
+    }
 }
 
 #[derive(Default)]
 pub struct ShaderProgramBuilder {
     shaders: Vec<GLuint>,
+    varyings: Vec<String>,
+    defines: Vec<String>,
     error: GLint
 }
 
+///insert a #define of each name after the #version line of a source
+pub fn with_defines(source: &str, defines: &[&str]) -> String {
+    let defines = defines.iter().map(|d| format!("#define {d}\n")).collect::<String>();
+    if !source.starts_with("#version") {
+        return defines + source;
+    }
+    match source.split_once('\n') {
+        Some((version, rest)) => format!("{version}\n{defines}{rest}"),
+        None => format!("{source}\n{defines}")
+    }
+}
+
 impl ShaderProgramBuilder {
+    ///outputs of the last vertex stage captured (interleaved, in this order) in the transform feedback buffer
+    pub fn feedback_varyings(&mut self, varyings: &[&str]) -> &mut Self {
+        self.varyings = varyings.iter().map(|v| format!("{v}\0")).collect();
+        self
+    }
+
+    ///names defined in the shaders added after this call (ex: variants of a shader)
+    pub fn defines(&mut self, defines: &[&str]) -> &mut Self {
+        self.defines = defines.iter().map(|d| d.to_string()).collect();
+        self
+    }
 
     pub fn add_shader(&mut self, kind: Shaders, source: &str) -> &mut Self {
+        let defined;
+        let source = if self.defines.is_empty() {
+            source
+        } else {
+            defined = with_defines(source, &self.defines.iter().map(String::as_str).collect::<Vec<_>>());
+            defined.as_str()
+        };
         unsafe {
             let shader = gl::CreateShader(kind.into());
             if shader == 0 {
@@ -83,6 +130,10 @@ impl ShaderProgramBuilder {
             for shader in self.shaders.iter() {
                 AttachShader(shader_program, *shader);
             }
+            if !self.varyings.is_empty() {
+                let names = self.varyings.iter().map(|v| v.as_ptr() as *const GLchar).collect::<Vec<_>>();
+                TransformFeedbackVaryings(shader_program, names.len() as GLsizei, names.as_ptr(), INTERLEAVED_ATTRIBS);
+            }
             LinkProgram(shader_program);
             let mut success = 0;
             GetProgramiv(shader_program, LINK_STATUS, &mut success);
@@ -90,7 +141,7 @@ impl ShaderProgramBuilder {
                 for shader in self.shaders.iter() {
                     DeleteShader(*shader);
                 }
-                UseProgram(shader_program);
+                safe_calls::use_program(shader_program);
                 gl::Enable(PROGRAM_POINT_SIZE);
                 Some(ShaderProgram::new(shader_program))
             } else {
@@ -108,4 +159,16 @@ impl ShaderProgramBuilder {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::shader::with_defines;
+
+    #[test]
+    fn defines() {
+        assert_eq!(with_defines("#version 330 core\nvoid main() {}", &["A", "B"]), "#version 330 core\n#define A\n#define B\nvoid main() {}");
+        assert_eq!(with_defines("#version 330 core", &["A"]), "#version 330 core\n#define A\n");
+        assert_eq!(with_defines("void main() {}", &["A"]), "#define A\nvoid main() {}");
+    }
+}
@@ -1,6 +1,5 @@
-use crate::maths::vector::Vec3;
 use crate::opengl::buffers::{GPUBuffers, VertexType};
-use crate::opengl::frustrum::Volume;
+use crate::opengl::volume::Volume;
 
 pub struct VaoObject {
     len: usize,
@@ -13,12 +12,9 @@ impl VaoObject {
         let mut buffers = GPUBuffers::new().unwrap();
         buffers.new_vbo(0, VertexType::Vec3);
         let len = indices.len();
-        buffers.set_ebo(indices);
-        let mut volume = Volume::default();
-        for v in &vertices {
-            volume.expand(&Vec3::from(*v));
-        }
-        buffers.set_vbo(0, vertices);
+        buffers.set_ebo(&indices);
+        let volume = Volume::from_points(&vertices);
+        buffers.set_vbo(0, &vertices);
         Self {
             len,
             buffers,
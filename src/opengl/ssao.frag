@@ -0,0 +1,48 @@
+#version 330 core
+
+#define MAX_SAMPLES 64
+
+uniform sampler2D depth; //depth texture of the prepass
+uniform sampler2D geometry; //view space normals
+uniform sampler2D noise; //random rotations of the kernel, tiled over the screen
+uniform mat4 projection;
+uniform mat4 inverse_projection;
+uniform vec3 kernel[MAX_SAMPLES]; //hemisphere around +z, denser near the center
+uniform int samples;
+uniform float radius; //view space distance of the occluders
+uniform float bias;
+uniform float strength;
+
+in vec2 uv;
+
+out float output_occlusion; //1: unoccluded
+
+vec3 view_position(vec2 p) {
+    vec4 v = inverse_projection * vec4(p * 2. - 1., texture(depth, p).r * 2. - 1., 1.);
+    return v.xyz / v.w;
+}
+
+void main() {
+    if (texture(depth, uv).r == 1.) { //background
+        output_occlusion = 1.;
+        return;
+    }
+    vec3 p = view_position(uv);
+    vec3 n = normalize(texture(geometry, uv).xyz);
+    vec2 noise_scale = vec2(textureSize(depth, 0)) / vec2(textureSize(noise, 0));
+    vec3 r = texture(noise, uv * noise_scale).xyz;
+    //gram-schmidt: the kernel is oriented along the normal and rotated around it by the noise
+    vec3 t = normalize(r - n * dot(r, n));
+    mat3 tbn = mat3(t, cross(n, t), n);
+    float occlusion = 0.;
+    for (int i = 0; i < min(samples, MAX_SAMPLES); ++i) {
+        vec3 s = p + tbn * kernel[i] * radius;
+        vec4 o = projection * vec4(s, 1.);
+        vec2 suv = o.xy / o.w * 0.5 + 0.5;
+        float occluder = view_position(suv).z;
+        //occluders far outside of the radius (ex: a silhouette in front of a wall) fade out
+        float range = smoothstep(0., 1., radius / abs(p.z - occluder));
+        occlusion += (occluder >= s.z + bias ? 1. : 0.) * range;
+    }
+    output_occlusion = pow(1. - occlusion / float(max(min(samples, MAX_SAMPLES), 1)), strength);
+}
@@ -0,0 +1,220 @@
+use std::ffi::c_void;
+use gl::types::{GLint, GLsizei, GLuint};
+use crate::maths::matrix::Mat4;
+use crate::opengl::enums::Shaders;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::lights::shadows::{CUBE_SHADOW_MAPS_UNIT, MAX_CUBE_SHADOW_MAPS};
+use crate::opengl::post::FullscreenPass;
+use crate::opengl::safe_calls;
+use crate::opengl::shader::{ShaderProgram, ShaderProgramBuilder};
+use crate::opengl::uniform::Uniform;
+
+pub const AMBIENT_OCCLUSION_UNIT: usize = CUBE_SHADOW_MAPS_UNIT + MAX_CUBE_SHADOW_MAPS; //texture unit of the blurred occlusion, read by the main shader
+pub const MAX_SSAO_SAMPLES: usize = 64;
+pub const NOISE_SIZE: usize = 4; //width and height of the tiled rotations (and of the blur)
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SsaoSettings {
+    pub radius: f32, //view space distance of the occluders
+    pub samples: usize, //points of the hemisphere tested per pixel (1 to MAX_SSAO_SAMPLES)
+    pub strength: f32, //exponent of the occlusion, above 1 darkens the creases
+    pub bias: f32, //depth offset against the self occlusion of flat surfaces
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            samples: 16,
+            strength: 1.,
+            bias: 0.025
+        }
+    }
+}
+
+///deterministic random numbers in [0, 1[ (xorshift)
+fn random(seed: &mut u64) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    (*seed >> 40) as f32 / (1u64 << 24) as f32
+}
+
+///points in the unit hemisphere around +z, scaled to gather more of them near the origin
+pub fn hemisphere_kernel(count: usize) -> Vec<[f32; 3]> {
+    let mut seed = 0x9E3779B97F4A7C15u64;
+    (0..count).map(|i| {
+        let v = [random(&mut seed) * 2. - 1., random(&mut seed) * 2. - 1., random(&mut seed).max(0.01)];
+        let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt().max(0.0001);
+        let t = i as f32 / count as f32;
+        let scale = random(&mut seed) * (0.1 + 0.9 * t * t) / length;
+        v.map(|c| c * scale)
+    }).collect()
+}
+
+///random rotations of the kernel around the normal (xy plane)
+pub fn rotation_noise(count: usize) -> Vec<[f32; 3]> {
+    let mut seed = 0xD1B54A32D192ED03u64;
+    (0..count).map(|_| [random(&mut seed) * 2. - 1., random(&mut seed) * 2. - 1., 0.]).collect()
+}
+
+//screen space ambient occlusion: the opaque geometry of the camera is drawn in a depth / normal prepass, the occlusion of each pixel is estimated from the depth around it then blurred
+#[derive(Debug)]
+pub struct Ssao {
+    settings: SsaoSettings,
+    prepass: ShaderProgram,
+    camera_uniform: Uniform,
+    projection_uniform: Uniform,
+    geometry: Framebuffer, //view space normals and linear depth, depth texture
+    occlusion: Framebuffer,
+    blurred: Framebuffer,
+    pass: FullscreenPass,
+    blur: FullscreenPass,
+    pass_projection_uniform: Uniform,
+    inverse_projection_uniform: Uniform,
+    samples_uniform: Uniform,
+    radius_uniform: Uniform,
+    bias_uniform: Uniform,
+    strength_uniform: Uniform,
+    kernel_uniform: Uniform,
+    noise: GLuint,
+    target: GLuint, //framebuffer bound before begin
+    viewport: [GLint; 4]
+}
+
+impl Ssao {
+    pub fn new(settings: SsaoSettings) -> Self {
+        let prepass = ShaderProgramBuilder::default()
+            .add_shader(Shaders::Vertex, include_str!("ssao_prepass.vert"))
+            .add_shader(Shaders::Fragment, include_str!("ssao_prepass.frag"))
+            .build().unwrap();
+        let pass = FullscreenPass::new(include_str!("ssao.frag"));
+        pass.uniform("geometry").int(1);
+        pass.uniform("noise").int(2);
+        let blur = FullscreenPass::new(include_str!("ssao_blur.frag"));
+        blur.uniform("geometry").int(1);
+        let mut noise = 0;
+        let data = rotation_noise(NOISE_SIZE * NOISE_SIZE);
+        unsafe {
+            gl::GenTextures(1, &mut noise);
+            safe_calls::edit_texture(gl::TEXTURE_2D, noise);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB16F as GLint, NOISE_SIZE as GLsizei, NOISE_SIZE as GLsizei, 0, gl::RGB, gl::FLOAT, data.as_ptr() as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+        }
+        let out = Self {
+            settings,
+            camera_uniform: prepass.uniform("camera"),
+            projection_uniform: prepass.uniform("projection"),
+            prepass,
+            geometry: RenderTarget::default().color(gl::RGBA16F).depth_texture(gl::DEPTH_COMPONENT24).build((1, 1)),
+            occlusion: RenderTarget::default().color(gl::R8).build((1, 1)),
+            blurred: RenderTarget::default().color(gl::R8).build((1, 1)),
+            pass_projection_uniform: pass.uniform("projection"),
+            inverse_projection_uniform: pass.uniform("inverse_projection"),
+            samples_uniform: pass.uniform("samples"),
+            radius_uniform: pass.uniform("radius"),
+            bias_uniform: pass.uniform("bias"),
+            strength_uniform: pass.uniform("strength"),
+            kernel_uniform: pass.uniform("kernel"),
+            pass,
+            blur,
+            noise,
+            target: 0,
+            viewport: [0; 4]
+        };
+        out.upload_kernel();
+        out
+    }
+
+    pub fn settings(&self) -> SsaoSettings { self.settings }
+
+    pub fn set_settings(&mut self, settings: SsaoSettings) {
+        let resample = settings.samples != self.settings.samples;
+        self.settings = settings;
+        if resample {
+            self.upload_kernel();
+        }
+    }
+
+    ///the kernel is generated for the sample count, its points reach further along the array
+    fn upload_kernel(&self) {
+        self.pass.program.set_active();
+        self.kernel_uniform.array3f(&hemisphere_kernel(self.settings.samples.clamp(1, MAX_SSAO_SAMPLES)));
+    }
+
+    ///bind and clear the prepass target resized to the viewport, following draws (without materials) write the depth and normals
+    pub fn begin(&mut self, projection: Mat4, camera: Mat4) {
+        self.target = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        self.viewport = safe_calls::get_int_array::<4>(gl::VIEWPORT);
+        let size = (self.viewport[2].max(1) as u32, self.viewport[3].max(1) as u32);
+        self.geometry.resize(size);
+        self.occlusion.resize(size);
+        self.blurred.resize(size);
+        self.geometry.bind();
+        unsafe {
+            gl::ClearBufferfv(gl::COLOR, 0, [0., 0., 1., 0.].as_ptr());
+            gl::ClearBufferfv(gl::DEPTH, 0, &1f32);
+        }
+        self.pass.program.set_active();
+        self.pass_projection_uniform.mat4(projection);
+        self.inverse_projection_uniform.mat4(projection.inverse().unwrap_or_else(Mat4::identity));
+        self.prepass.set_active();
+        self.projection_uniform.mat4(projection);
+        self.camera_uniform.mat4(camera);
+    }
+
+    ///compute and blur the occlusion, bound to AMBIENT_OCCLUSION_UNIT, then restore the framebuffer bound before begin
+    pub fn resolve(&self) {
+        safe_calls::set_depth_test(false);
+        self.occlusion.bind();
+        self.geometry.bind_depth(0);
+        self.geometry.bind_color(0, 1);
+        safe_calls::bind_texture(2, self.noise);
+        self.pass.program.set_active();
+        self.samples_uniform.int(self.settings.samples.clamp(1, MAX_SSAO_SAMPLES) as i32);
+        self.radius_uniform.float(self.settings.radius.max(0.0001));
+        self.bias_uniform.float(self.settings.bias);
+        self.strength_uniform.float(self.settings.strength.max(0.));
+        self.pass.draw();
+        self.blurred.bind();
+        self.occlusion.bind_color(0, 0);
+        self.blur.draw();
+        safe_calls::set_depth_test(true);
+        self.blurred.bind_color(0, AMBIENT_OCCLUSION_UNIT);
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.target);
+            gl::Viewport(self.viewport[0], self.viewport[1], self.viewport[2], self.viewport[3]);
+        }
+    }
+}
+
+impl Drop for Ssao {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.noise);
+        }
+        safe_calls::forget_texture(self.noise);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::opengl::ssao::{hemisphere_kernel, rotation_noise};
+
+    #[test]
+    fn kernel() {
+        let kernel = hemisphere_kernel(64);
+        assert_eq!(kernel.len(), 64);
+        for v in &kernel {
+            assert!(v[2] >= 0.);
+            assert!(v[0] * v[0] + v[1] * v[1] + v[2] * v[2] <= 1.0001);
+        }
+        //the later samples reach further
+        let length = |v: &[f32; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        assert!(kernel[..16].iter().map(length).sum::<f32>() < kernel[48..].iter().map(length).sum::<f32>());
+        assert!(rotation_noise(16).iter().all(|v| v[2] == 0. && v[0].abs() <= 1. && v[1].abs() <= 1.));
+    }
+}
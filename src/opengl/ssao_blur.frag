@@ -0,0 +1,26 @@
+#version 330 core
+
+uniform sampler2D occlusion;
+uniform sampler2D geometry; //w: linear view depth
+
+in vec2 uv;
+
+out float output_occlusion;
+
+//bilateral: 4x4 average (the size of the noise) of the samples at a depth close to the center one, the edges of the objects stay sharp
+void main() {
+    ivec2 center = ivec2(gl_FragCoord.xy);
+    ivec2 last = textureSize(occlusion, 0) - 1;
+    float depth = texelFetch(geometry, center, 0).w;
+    float sum = 0.;
+    float weight = 0.;
+    for (int x = -2; x < 2; ++x) {
+        for (int y = -2; y < 2; ++y) {
+            ivec2 p = clamp(center + ivec2(x, y), ivec2(0), last);
+            float w = max(1. - abs(texelFetch(geometry, p, 0).w - depth) / max(depth * 0.1, 0.0001), 0.);
+            sum += texelFetch(occlusion, p, 0).r * w;
+            weight += w;
+        }
+    }
+    output_occlusion = weight > 0. ? sum / weight : texelFetch(occlusion, center, 0).r;
+}
@@ -0,0 +1,12 @@
+#version 330 core
+
+in vec3 view_pos;
+in vec3 normal;
+
+out vec4 output_geometry; //xyz: view space normal, w: linear view depth
+
+void main() {
+    //same fallback to the face normal as the main shader for the models without normals
+    vec3 n = dot(normal, normal) > 0.000001 ? normalize(gl_FrontFacing ? normal : -normal) : normalize(cross(dFdx(view_pos), dFdy(view_pos)));
+    output_geometry = vec4(n, -view_pos.z);
+}
@@ -0,0 +1,19 @@
+#version 330 core
+
+layout (location = 0) in vec3 v_pos;
+layout (location = 3) in vec3 v_normal;
+
+layout (location = 6) in mat4 i_mat;
+
+out vec3 view_pos;
+out vec3 normal;
+
+uniform mat4 projection;
+uniform mat4 camera;
+
+void main() {
+    vec4 p = camera * i_mat * vec4(v_pos, 1.0);
+    gl_Position = projection * p;
+    view_pos = p.xyz;
+    normal = mat3(camera) * mat3(transpose(inverse(i_mat))) * v_normal;
+}
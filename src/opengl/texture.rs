@@ -1,7 +1,8 @@
 use std::ffi::c_void;
-use gl::{ActiveTexture, BindTexture, CLAMP_TO_BORDER, GenerateMipmap, GenTextures, LINEAR, RGB, TexImage2D, TexParameteri, TEXTURE0, TEXTURE_2D, TEXTURE_MAG_FILTER, TEXTURE_MIN_FILTER, TEXTURE_WRAP_S, TEXTURE_WRAP_T, UNSIGNED_BYTE};
-use gl::types::{GLenum, GLint, GLsizei, GLuint};
+use gl::{CLAMP_TO_BORDER, GenerateMipmap, GenTextures, LINEAR, RGB, TexImage2D, TexParameteri, TEXTURE_2D, TEXTURE_MAG_FILTER, TEXTURE_MIN_FILTER, TEXTURE_WRAP_S, TEXTURE_WRAP_T, UNSIGNED_BYTE};
+use gl::types::{GLint, GLsizei, GLuint};
 use crate::maths::vector::Vec3;
+use crate::opengl::safe_calls;
 use crate::opengl::uniform::Uniform;
 
 #[derive(Default, Debug, Clone)]
@@ -50,7 +51,7 @@ impl Texture {
                     return;
                 }
                 //set this texture active for all subsequent functions
-                BindTexture(TEXTURE_2D, self.name);
+                safe_calls::edit_texture(TEXTURE_2D, self.name);
                 //set repeating texture mode to none (will be stretched to fit)
                 TexParameteri(TEXTURE_2D, TEXTURE_WRAP_S, CLAMP_TO_BORDER as i32);
                 TexParameteri(TEXTURE_2D, TEXTURE_WRAP_T, CLAMP_TO_BORDER as i32);
@@ -75,16 +76,17 @@ impl Texture {
         }
     }
 
+    ///bind to a texture unit (the samplers already point to the units)
+    pub fn bind_unit(&self, unit: usize) {
+        safe_calls::bind_texture(unit, self.name);
+    }
+
     pub fn bind(&self, tex_offset: usize, sampler: Uniform) {
-        unsafe {
-            if self.name != 0 {
-                //set this texture active for all subsequent functions
-                BindTexture(TEXTURE_2D, self.name);
-                //take the texture and bind it to the texture indexed by the given offset
-                ActiveTexture(TEXTURE0 + tex_offset as GLenum);
-                //bind the texture sampler to the uniform location 'tex<offset>'
-                sampler.int(tex_offset as i32);
-            }
+        if self.name != 0 {
+            //take the texture and bind it to the texture indexed by the given offset
+            safe_calls::bind_texture(tex_offset, self.name);
+            //bind the texture sampler to the uniform location 'tex<offset>'
+            sampler.int(tex_offset as i32);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,226 @@
+use gl::types::GLuint;
+use crate::opengl::framebuffer::{Framebuffer, RenderTarget};
+use crate::opengl::indirect::DrawStats;
+use crate::opengl::instances::{InstanceBuffer, InstanceData};
+use crate::opengl::main_shader::MainShader;
+use crate::opengl::post::FullscreenPass;
+use crate::opengl::safe_calls;
+use crate::opengl::uniform::Uniform;
+use crate::other::resource_manager::ResourceManager;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TransparencyMode {
+    #[default]
+    Sorted, //blended over the opaque geometry, instances sorted back to front
+    WeightedBlended, //order independent approximation (weighted blended oit), for heavily overlapping geometry
+}
+
+type Draw = (usize, usize, usize, usize); //model, lod, first instance, count
+
+//transparent parts of the instances seen by the camera, drawn after the opaque geometry without writing the depth
+#[derive(Debug, Default)]
+pub struct TransparentPass {
+    mode: TransparencyMode,
+    instances: InstanceBuffer,
+    draws: Vec<Draw>, //in drawing order
+    weighted: Option<WeightedTargets>
+}
+
+impl TransparentPass {
+    pub fn mode(&self) -> TransparencyMode { self.mode }
+
+    pub fn set_mode(&mut self, mode: TransparencyMode) {
+        self.mode = mode;
+        if mode != TransparencyMode::WeightedBlended {
+            self.weighted = None;
+        }
+    }
+
+    ///instances (squared distance to the camera, model, lod, data), drawn back to front
+    pub fn upload(&mut self, instances: Vec<(f32, usize, usize, InstanceData)>) {
+        let (draws, data) = Self::order(instances);
+        self.draws = draws;
+        self.instances.upload(&data);
+    }
+
+    ///sort the instances back to front, consecutive instances of the same model and lod share a draw
+    fn order(mut instances: Vec<(f32, usize, usize, InstanceData)>) -> (Vec<Draw>, Vec<InstanceData>) {
+        instances.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut draws: Vec<Draw> = Vec::new();
+        for (i, (_, model, lod, _)) in instances.iter().enumerate() {
+            match draws.last_mut() {
+                Some((m, l, _, count)) if m == model && l == lod => *count += 1,
+                _ => draws.push((*model, *lod, i, 1))
+            }
+        }
+        (draws, instances.into_iter().map(|(.., data)| data).collect())
+    }
+
+    ///blend the uploaded instances over the bound framebuffer (its depth is tested, not written)
+    pub fn draw(&mut self, resources: &ResourceManager, shader: &MainShader, stats: &mut DrawStats) {
+        if self.draws.is_empty() {
+            return;
+        }
+        if self.mode == TransparencyMode::WeightedBlended {
+            self.weighted.get_or_insert_with(WeightedTargets::new).begin();
+            shader.program.set_active(); //building the resolve program activated it
+            shader.weighted_transparency.int(1);
+            safe_calls::set_blend(true);
+            //colors and weights are summed, the alpha of the first target is the product of the transparencies
+            safe_calls::set_blend_func(gl::ONE, gl::ONE, gl::ZERO, gl::ONE_MINUS_SRC_ALPHA);
+        } else {
+            safe_calls::set_blend(true);
+        }
+        safe_calls::set_depth_write(false);
+        for &(model, lod, first, count) in &self.draws {
+            if let Some(mpm) = resources.get_multipart_model(model) {
+                mpm.draw_transparent_from(&self.instances, first, count, lod);
+                stats.draw_calls += 1;
+                stats.instances += count;
+                stats.triangles += mpm.transparent_triangle_count(lod) * count;
+            }
+        }
+        if let Some(weighted) = self.weighted.as_ref().filter(|_| self.mode == TransparencyMode::WeightedBlended) {
+            shader.weighted_transparency.int(0);
+            weighted.resolve();
+            shader.program.set_active();
+        }
+        safe_calls::set_depth_write(true);
+        safe_calls::set_blend(false);
+    }
+}
+
+//accumulation targets of the weighted blended transparency, sharing a copy of the depth of the opaque geometry
+#[derive(Debug)]
+struct WeightedTargets {
+    targets: Option<Framebuffer>, //RGBA16F (rgb: sum of the weighted colors, a: product of the transparencies), no emissive, R16F (sum of the weighted alphas), depth in the format of the target
+    target: GLuint, //framebuffer drawn before the pass, resolved into
+    resolve: FullscreenPass,
+    accumulation_uniform: Uniform,
+    weights_uniform: Uniform
+}
+
+impl WeightedTargets {
+    fn new() -> Self {
+        let resolve = FullscreenPass::new(include_str!("oit.frag"));
+        Self {
+            targets: None,
+            target: 0,
+            accumulation_uniform: resolve.uniform("accumulation"),
+            weights_uniform: resolve.uniform("weights"),
+            resolve
+        }
+    }
+
+    ///copy the depth of the bound framebuffer and clear the accumulation
+    fn begin(&mut self) {
+        self.target = safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint;
+        let size = safe_calls::get_size();
+        let format = Framebuffer::depth_format(self.target);
+        let description = RenderTarget::default().color(gl::RGBA16F).skip_color().color(gl::R16F).depth(format.unwrap_or(gl::DEPTH_COMPONENT24));
+        if self.targets.as_ref().is_none_or(|t| *t.target() != description) {
+            self.targets = Some(description.build(size));
+        }
+        let targets = self.targets.as_mut().unwrap();
+        targets.resize(size);
+        if format.is_some() {
+            targets.blit_depth_from(self.target);
+        } else {
+            targets.bind();
+            unsafe {
+                gl::Clear(gl::DEPTH_BUFFER_BIT);
+            }
+        }
+        unsafe {
+            gl::ClearBufferfv(gl::COLOR, 0, [0., 0., 0., 1.].as_ptr());
+            gl::ClearBufferfv(gl::COLOR, 2, [0.; 4].as_ptr());
+        }
+    }
+
+    ///average the accumulated colors and blend them over the target by their coverage
+    fn resolve(&self) {
+        let Some(targets) = &self.targets else { return; };
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.target);
+        }
+        self.resolve.program.set_active();
+        targets.bind_color(0, 0);
+        targets.bind_color(2, 1);
+        self.accumulation_uniform.int(0);
+        self.weights_uniform.int(1);
+        safe_calls::set_blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        safe_calls::set_depth_test(false);
+        self.resolve.draw();
+        safe_calls::set_depth_test(true);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gl::types::{GLboolean, GLuint};
+    use crate::maths::transform::Transform;
+    use crate::maths::vector::Vec3;
+    use crate::opengl::framebuffer::RenderTarget;
+    use crate::opengl::instances::InstanceData;
+    use crate::opengl::safe_calls;
+    use crate::opengl::scene::{ObjectData, Scene};
+    use crate::opengl::shader::ShaderProgram;
+    use crate::opengl::transparency::{TransparencyMode, TransparentPass};
+    use crate::other::resource_manager::ResourceManager;
+    use crate::other::window::offscreen_context;
+
+    #[test]
+    fn back_to_front_draws() {
+        let instance = |id: u32| InstanceData { id, ..Default::default() };
+        //(squared distance, model, lod)
+        let instances = [(1., 0, 0), (9., 0, 0), (4., 1, 0), (16., 0, 0), (2., 0, 1), (3., 0, 1)];
+        let (draws, data) = TransparentPass::order(instances.iter().enumerate().map(|(id, (d, m, l))| (*d, *m, *l, instance(id as u32))).collect());
+        assert_eq!(data.iter().map(|d| d.id).collect::<Vec<_>>(), vec![3, 1, 2, 5, 4, 0]);
+        //the two farthest share a draw, the model 0 at lod 0 is drawn again after the others
+        assert_eq!(draws, vec![(0, 0, 0, 2), (1, 0, 2, 1), (0, 1, 3, 2), (0, 0, 5, 1)]);
+        assert!(TransparentPass::order(Vec::new()).0.is_empty());
+    }
+
+    #[test]
+    fn weighted_blended_restores_state() {
+        let _context = offscreen_context().expect("needs a mesa gl context (egl surfaceless or osmesa)");
+        let directory = std::env::temp_dir().join(format!("scop_transparency_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("glass.obj"), "mtllib glass.mtl\nv -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nvn 0 0 1\nusemtl glass\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n").unwrap();
+        std::fs::write(directory.join("glass.mtl"), "newmtl glass\nKa 0 0 0\nKe 1 0 0\nd 0.5\n").unwrap();
+        let mut resources = ResourceManager::default();
+        resources.register_hints(&["resources", "resources/shaders", directory.to_str().unwrap()]);
+        let program = ShaderProgram::from_resources(&mut resources, "default").unwrap();
+        let mut scene = Scene::new(program);
+        let (glass, _) = resources.load_multipart_model("glass").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        scene.set_post_processing(false);
+        scene.spawn_object(glass, ObjectData::from(Transform::from_look_towards(Vec3::default(), -Vec3::Z)));
+        scene.set_camera(Transform::from_look_at(Vec3::new(0., 0., 3.), Vec3::default()));
+        scene.set_projection(80., 1.);
+        let size = 32;
+        let target = RenderTarget::default().color(gl::RGBA8).depth(gl::DEPTH_COMPONENT24).build((size, size));
+        target.bind();
+        safe_calls::set_clear_color(0., 0., 0.);
+        safe_calls::set_depth_test(true);
+        for mode in [TransparencyMode::WeightedBlended, TransparencyMode::Sorted] {
+            scene.set_transparency(mode);
+            safe_calls::clear_screen();
+            scene.draw(&resources);
+            assert_eq!(unsafe { gl::GetError() }, gl::NO_ERROR);
+            let mut pixel = [0u8; 4];
+            let mut depth_write: GLboolean = 0;
+            unsafe {
+                gl::ReadPixels(size as i32 / 2, size as i32 / 2, 1, 1, gl::RGBA, gl::UNSIGNED_BYTE, pixel.as_mut_ptr().cast());
+                gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_write);
+            }
+            //the glass is blended over the black background, and the pass leaves the state of the opaque geometry
+            assert!(pixel[0] > 50 && pixel[0] < 250 && pixel[1] < 50, "{mode:?}: {pixel:?}");
+            assert_eq!(safe_calls::get_int(gl::FRAMEBUFFER_BINDING) as GLuint, target.id());
+            assert_eq!(depth_write, gl::TRUE);
+            assert_eq!(unsafe { gl::IsEnabled(gl::BLEND) }, gl::FALSE);
+            let func = [gl::BLEND_SRC_RGB, gl::BLEND_DST_RGB, gl::BLEND_SRC_ALPHA, gl::BLEND_DST_ALPHA].map(|query| safe_calls::get_int(query) as GLuint);
+            assert_eq!(func, [gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA, gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA], "{mode:?}");
+        }
+    }
+}
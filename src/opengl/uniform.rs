@@ -22,6 +22,12 @@ impl Uniform {
         }
     }
     
+    pub fn int2(&self, x: i32, y: i32) {
+        unsafe {
+            gl::Uniform2i(self.0, x, y);
+        }
+    }
+
     pub fn float(&self, value: f32) {
         unsafe {
             gl::Uniform1f(self.0, value);
@@ -70,6 +76,18 @@ impl Uniform {
         }
     }
     
+    pub fn array_float(&self, value: &[f32]) {
+        unsafe {
+            gl::Uniform1fv(self.0, value.len() as GLsizei, value.as_ptr());
+        }
+    }
+
+    pub fn array_vec4(&self, value: &[Vec4]) {
+        unsafe {
+            gl::Uniform4fv(self.0, value.len() as GLsizei, value.iter().flat_map(|v| v.0).collect::<Vec<f32>>().as_ptr());
+        }
+    }
+
     pub fn array_int(&self, value: &[i32]) {
         unsafe {
             gl::Uniform1iv(self.0, value.len() as GLsizei, value.as_ptr());
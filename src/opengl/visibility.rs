@@ -0,0 +1,60 @@
+use std::collections::HashSet;
+use crate::maths::matrix::Mat4;
+use crate::opengl::frustrum::Frustrum;
+use crate::opengl::object::MultiPartModel;
+use crate::opengl::scene::ObjectData;
+
+//set of the instances seen by a view (camera, light, etc...), kept up to date incrementally by the scene
+#[derive(Debug)]
+pub struct Visibility {
+    frustrum: Frustrum,
+    visible: HashSet<usize>,
+    stale: bool //the view moved: every instance has to be tested again
+}
+
+impl Visibility {
+    pub fn new(vp: &Mat4) -> Self {
+        Self {
+            frustrum: Frustrum::from_vp(vp),
+            visible: HashSet::new(),
+            stale: true
+        }
+    }
+
+    pub fn set_view_projection(&mut self, vp: &Mat4) {
+        self.frustrum = Frustrum::from_vp(vp);
+        self.stale = true;
+    }
+
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn is_stale(&self) -> bool { self.stale }
+
+    pub fn frustrum(&self) -> &Frustrum { &self.frustrum }
+
+    ///result of a full test of the instances
+    pub(crate) fn replace(&mut self, visible: HashSet<usize>) {
+        self.visible = visible;
+        self.stale = false;
+    }
+
+    pub fn visible(&self) -> &HashSet<usize> { &self.visible }
+
+    pub fn contains(&self, id: usize) -> bool { self.visible.contains(&id) }
+
+    ///test an instance against this view, returns true if it entered or left the set
+    pub fn test(&mut self, id: usize, model: Option<&MultiPartModel>, data: &ObjectData) -> bool {
+        if data.visible() && model.is_some_and(|m| m.visible(data.transform(), &self.frustrum)) {
+            self.visible.insert(id)
+        } else {
+            self.visible.remove(&id)
+        }
+    }
+
+    ///returns true if the instance was in the set
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.visible.remove(&id)
+    }
+}
@@ -0,0 +1,420 @@
+use crate::maths::matrix::Mat4;
+use crate::maths::transform::Transform;
+use crate::maths::vector::{Vec3, Vector};
+
+type Vec3d = Vector<3, f64>;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+
+//oriented box stored as a center and 3 half axes (axis * half extent), stays exact under any affine transform (becomes a parallelepiped)
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Obb {
+    pub center: Vec3,
+    pub half_axes: [Vec3; 3]
+}
+
+//composite volume in model space, tested from the cheapest to the tightest: sphere -> aabb -> obb
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Volume {
+    pub sphere: Sphere,
+    pub aabb: Aabb,
+    pub obb: Obb
+}
+
+impl Volume {
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        Self {
+            sphere: Sphere::from_points(points),
+            aabb: Aabb::from_points(points),
+            obb: Obb::from_points(points)
+        }
+    }
+
+    ///radius of a sphere centered on the model origin containing this volume
+    pub fn radius(&self) -> f32 {
+        self.sphere.center.dot(&self.sphere.center).sqrt() + self.sphere.radius
+    }
+}
+
+fn distance(a: &Vec3d, b: &Vec3d) -> f64 {
+    let d = a - b;
+    d.dot(&d).sqrt()
+}
+
+fn to_f64(p: &[f32; 3]) -> Vec3d {
+    Vector::from(p.map(|c| c as f64))
+}
+
+//spheres are computed in f64, containment is checked with a relative tolerance
+fn contains(center: &Vec3d, radius: f64, p: &Vec3d) -> bool {
+    distance(center, p) <= radius * (1. + 1e-9) + 1e-12
+}
+
+///smallest sphere with all the support points on its surface (exact for up to 4 points in general position)
+fn circumsphere(support: &[Vec3d]) -> (Vec3d, f64) {
+    match support {
+        [] => (Vec3d::default(), -1.),
+        [a] => (*a, 0.),
+        [a, b] => ((a + b) * 0.5, distance(a, b) * 0.5),
+        [a, b, c] => {
+            let (ab, ac) = (b - a, c - a);
+            let n = ab.cross_product(&ac);
+            let l = n.dot(&n);
+            if l <= 1e-24 {
+                //colinear: the farthest pair defines the sphere
+                return [(a, b), (a, c), (b, c)].into_iter().map(|(x, y)| circumsphere(&[*x, *y])).max_by(|x, y| x.1.total_cmp(&y.1)).unwrap();
+            }
+            let o = (n.cross_product(&ab) * ac.dot(&ac) + ac.cross_product(&n) * ab.dot(&ab)) / (2. * l);
+            (*a + o, o.dot(&o).sqrt())
+        }
+        [a, b, c, d] => {
+            let (ab, ac, ad) = (b - a, c - a, d - a);
+            let det = ab.dot(&ac.cross_product(&ad));
+            if det.abs() <= 1e-18 {
+                //coplanar: the largest of the triangle spheres
+                return [[*a, *b, *c], [*a, *b, *d], [*a, *c, *d], [*b, *c, *d]].iter().map(|t| circumsphere(t)).max_by(|x, y| x.1.total_cmp(&y.1)).unwrap();
+            }
+            let o = (ac.cross_product(&ad) * ab.dot(&ab) + ad.cross_product(&ab) * ac.dot(&ac) + ab.cross_product(&ac) * ad.dot(&ad)) / (2. * det);
+            (*a + o, o.dot(&o).sqrt())
+        }
+        _ => unreachable!(),
+    }
+}
+
+//iterative welzl: each level of recursion adds a point to the support, so the depth is bounded by 4
+fn welzl(points: &[Vec3d], support: &mut Vec<Vec3d>) -> (Vec3d, f64) {
+    let (mut center, mut radius) = circumsphere(support);
+    if support.len() == 4 {
+        return (center, radius);
+    }
+    for (i, p) in points.iter().enumerate() {
+        if radius < 0. || !contains(&center, radius, p) {
+            support.push(*p);
+            (center, radius) = welzl(&points[..i], support);
+            support.pop();
+        }
+    }
+    (center, radius)
+}
+
+impl Sphere {
+    ///minimal bounding sphere (welzl on a shuffled copy of the points)
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        if points.is_empty() {
+            return Self::default();
+        }
+        let mut shuffled = points.iter().map(to_f64).collect::<Vec<_>>();
+        //deterministic shuffle (xorshift), the expected linear time of welzl relies on a random order
+        let mut seed = 0x9E3779B97F4A7C15u64;
+        for i in (1..shuffled.len()).rev() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            shuffled.swap(i, (seed % (i as u64 + 1)) as usize);
+        }
+        let (center, mut radius) = welzl(&shuffled, &mut Vec::with_capacity(4));
+        //absorb the rounding errors so every point is contained once converted back to f32
+        let c = Vec3::from(center.0.map(|v| v as f32));
+        for p in points {
+            radius = radius.max(distance(&to_f64(&c.0), &to_f64(p)));
+        }
+        Self {
+            center: c,
+            radius: (radius * (1. + 1e-6)) as f32
+        }
+    }
+
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let s = transform.scale;
+        Self {
+            center: (Mat4::from(transform) * self.center.extend(1.)).resize(),
+            radius: self.radius * s[0].abs().max(s[1].abs()).max(s[2].abs())
+        }
+    }
+}
+
+impl Aabb {
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let mut it = points.iter().map(|p| Vec3::from(*p));
+        if let Some(first) = it.next() {
+            it.fold(Self { min: first, max: first }, |acc, p| Self { min: acc.min.min(p), max: acc.max.max(p) })
+        } else {
+            Self::default()
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max)
+        }
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] <= other.min[i] && self.max[i] >= other.max[i])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|i| self.min[i] <= other.max[i] && self.max[i] >= other.min[i])
+    }
+
+    ///squared distance from a point to the box (0 inside)
+    pub fn distance_squared(&self, point: &Vec3) -> f32 {
+        (0..3).map(|i| {
+            let d = (self.min[i] - point[i]).max(point[i] - self.max[i]).max(0.);
+            d * d
+        }).sum()
+    }
+
+    ///distance along the ray (direction does not need to be normalized) to the entry point in the box, 0 if the origin is inside (slab test)
+    pub fn ray_distance(&self, origin: &Vec3, direction: &Vec3) -> Option<f32> {
+        let mut near = 0f32;
+        let mut far = f32::INFINITY;
+        for i in 0..3 {
+            if direction[i] == 0. {
+                if origin[i] < self.min[i] || origin[i] > self.max[i] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1. / direction[i];
+            let (a, b) = ((self.min[i] - origin[i]) * inv, (self.max[i] - origin[i]) * inv);
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2. * (d[0] * d[1] + d[1] * d[2] + d[2] * d[0])
+    }
+
+    pub fn expanded(&self, margin: f32) -> Self {
+        Self {
+            min: self.min - margin,
+            max: self.max + margin
+        }
+    }
+
+    ///world aabb containing the transformed box
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let m = Mat4::from(transform);
+        let center: Vec3 = (m * self.center().extend(1.)).resize();
+        let e = self.half_extents();
+        let mut extents = Vec3::default();
+        for r in 0..3 {
+            extents[r] = (0..3).map(|c| m[(c, r)].abs() * e[c]).sum();
+        }
+        Self {
+            min: center - extents,
+            max: center + extents
+        }
+    }
+}
+
+//eigen decomposition of a symmetric 3x3 matrix (jacobi rotations), returns the eigenvectors as columns
+fn jacobi(mut a: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut v = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+    for _ in 0..32 {
+        let (mut p, mut q) = (0, 1);
+        for (i, j) in [(0, 2), (1, 2)] {
+            if a[i][j].abs() > a[p][q].abs() {
+                (p, q) = (i, j);
+            }
+        }
+        if a[p][q].abs() <= 1e-15 * (a[0][0].abs() + a[1][1].abs() + a[2][2].abs()).max(1e-300) {
+            break;
+        }
+        let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+        let t = if theta == 0. { 1. } else { t };
+        let c = 1. / (t * t + 1.).sqrt();
+        let s = t * c;
+        for row in &mut a {
+            let (akp, akq) = (row[p], row[q]);
+            row[p] = c * akp - s * akq;
+            row[q] = s * akp + c * akq;
+        }
+        let (rp, rq) = (a[p], a[q]);
+        for k in 0..3 {
+            a[p][k] = c * rp[k] - s * rq[k];
+            a[q][k] = s * rp[k] + c * rq[k];
+        }
+        for row in &mut v {
+            let (vp, vq) = (row[p], row[q]);
+            row[p] = c * vp - s * vq;
+            row[q] = s * vp + c * vq;
+        }
+    }
+    v
+}
+
+impl Obb {
+    ///box aligned on the principal axes of the points, falls back to the aabb if it is not smaller
+    pub fn from_points(points: &[[f32; 3]]) -> Self {
+        let aabb = Obb::from(Aabb::from_points(points));
+        if points.len() < 4 {
+            return aabb;
+        }
+        let mean = points.iter().map(to_f64).fold(Vec3d::default(), |acc, p| acc + p) / points.len() as f64;
+        let mut covariance = [[0f64; 3]; 3];
+        for p in points {
+            let d = to_f64(p) - mean;
+            for (i, row) in covariance.iter_mut().enumerate() {
+                for (j, c) in row.iter_mut().enumerate() {
+                    *c += d[i] * d[j];
+                }
+            }
+        }
+        let v = jacobi(covariance);
+        let axes = [0, 1, 2].map(|c| Vec3d::from([v[0][c], v[1][c], v[2][c]]).normalize());
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for p in points {
+            let p = to_f64(p);
+            for (i, axis) in axes.iter().enumerate() {
+                let d = p.dot(axis);
+                min[i] = min[i].min(d);
+                max[i] = max[i].max(d);
+            }
+        }
+        let center = (0..3).fold(Vec3d::default(), |acc, i| acc + axes[i] * ((min[i] + max[i]) * 0.5));
+        let pca = Self {
+            center: Vec3::from(center.0.map(|c| c as f32)),
+            //slightly inflated so the points stay inside despite the f32 conversion
+            half_axes: [0, 1, 2].map(|i| Vec3::from((axes[i] * ((max[i] - min[i]) * 0.5 * (1. + 1e-5) + 1e-6)).0.map(|c| c as f32)))
+        };
+        if pca.volume() < aabb.volume() { pca } else { aabb }
+    }
+
+    pub fn volume(&self) -> f32 {
+        8. * self.half_axes[0].dot(&self.half_axes[1].cross_product(&self.half_axes[2])).abs()
+    }
+
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let m = Mat4::from(transform);
+        Self {
+            center: (m * self.center.extend(1.)).resize(),
+            half_axes: self.half_axes.map(|a| (m * a.extend(0.)).resize())
+        }
+    }
+}
+
+impl From<Aabb> for Obb {
+    fn from(value: Aabb) -> Self {
+        let e = value.half_extents();
+        Self {
+            center: value.center(),
+            half_axes: [Vec3::X * e[0], Vec3::Y * e[1], Vec3::Z * e[2]]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::maths::vector::Vec3;
+    use crate::opengl::volume::{circumsphere, distance, to_f64, Aabb, Obb, Sphere, Volume};
+
+    fn cloud(count: usize, seed: u64) -> Vec<[f32; 3]> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        //stretched and offset cloud, so neither the origin nor the axes are special
+        (0..count).map(|_| [next() * 8. + 3., next() * 2. - 5., next() * 0.5 + next() * 3.]).collect()
+    }
+
+    fn in_obb(obb: &Obb, p: Vec3) -> bool {
+        let d = p - obb.center;
+        obb.half_axes.iter().all(|a| d.dot(a).abs() <= a.dot(a) * (1. + 1e-4) + 1e-5)
+    }
+
+    //minimal enclosing sphere by trying every support of 2, 3 and 4 points: pair midpoints and circumspheres, without the welzl code
+    fn brute_force_radius(points: &[[f32; 3]]) -> f64 {
+        let p = points.iter().map(to_f64).collect::<Vec<_>>();
+        let n = p.len();
+        let mut candidates = Vec::new();
+        for a in 0..n {
+            for b in a + 1..n {
+                candidates.push(((p[a] + p[b]) * 0.5, distance(&p[a], &p[b]) * 0.5));
+                for c in b + 1..n {
+                    candidates.push(circumsphere(&[p[a], p[b], p[c]]));
+                    for d in c + 1..n {
+                        candidates.push(circumsphere(&[p[a], p[b], p[c], p[d]]));
+                    }
+                }
+            }
+        }
+        candidates.into_iter()
+            .filter(|(center, radius)| p.iter().all(|v| distance(center, v) <= radius * (1. + 1e-6)))
+            .map(|(_, radius)| radius)
+            .fold(f64::MAX, f64::min)
+    }
+
+    #[test]
+    fn volumes_contain_all_points() {
+        for seed in 0..8 {
+            let points = cloud(500, seed);
+            let Volume { sphere, aabb, obb } = Volume::from_points(&points);
+            for p in points.iter().map(|p| Vec3::from(*p)) {
+                let d = p - sphere.center;
+                assert!(d.dot(&d).sqrt() <= sphere.radius);
+                assert!((0..3).all(|i| p[i] >= aabb.min[i] && p[i] <= aabb.max[i]));
+                assert!(in_obb(&obb, p));
+            }
+            //the aabb is tight and does not include the origin anymore
+            for i in 0..3 {
+                assert_eq!(aabb.min[i], points.iter().map(|p| p[i]).fold(f32::MAX, f32::min));
+                assert_eq!(aabb.max[i], points.iter().map(|p| p[i]).fold(f32::MIN, f32::max));
+            }
+            assert!(obb.volume() <= Obb::from(aabb).volume());
+        }
+    }
+
+    #[test]
+    fn sphere_is_minimal() {
+        for seed in 0..16 {
+            let points = cloud(12, seed);
+            let sphere = Sphere::from_points(&points);
+            let expected = brute_force_radius(&points);
+            assert!((sphere.radius as f64 - expected).abs() <= expected * 1e-4, "{} != {expected}", sphere.radius);
+        }
+    }
+
+    #[test]
+    fn rotated_box_gets_a_tight_obb() {
+        //unit cube corners rotated 45 degrees around z: the aabb is twice as big as the pca box
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let mut points = Vec::new();
+        for i in 0..8 {
+            let (x, y, z) = ((i & 1) as f32, ((i >> 1) & 1) as f32 * 2., ((i >> 2) & 1) as f32 * 3.);
+            points.push([(x - y) * s, (x + y) * s, z]);
+        }
+        let obb = Obb::from_points(&points);
+        assert!((obb.volume() - 6.).abs() < 1e-3, "{}", obb.volume());
+        assert!(Obb::from(Aabb::from_points(&points)).volume() > 6.5);
+    }
+}
@@ -5,9 +5,9 @@ use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 use crate::opengl::material::Material;
-use crate::opengl::object::MultiPartModel;
+use crate::opengl::object::{LodSettings, MultiPartModel};
 use crate::opengl::texture::Texture;
-use crate::parser::{ParsedMaterialLib, ParsedObject, ParsedTexture};
+use crate::parser::{ParsedMaterialLib, ParsedObject, ParsedTexture, RepairOptions, SubdivisionOptions, ValidationReport};
 
 #[derive(Default, Debug)]
 pub struct ResourceManager {
@@ -22,6 +22,11 @@ pub struct ResourceManager {
     maps: HashMap<usize, Texture>,
     texts: HashMap<usize, String>,
     models: HashMap<usize, MultiPartModel>,
+    reports: HashMap<usize, ValidationReport>, //validation (or repair) of the object of each model
+    generation: usize, //incremented each time a model is added, rebuilt or removed
+    repair: Option<RepairOptions>,
+    lod: Option<LodSettings>,
+    subdivision: Option<SubdivisionOptions>,
 }
 
 impl ResourceManager {
@@ -267,9 +272,18 @@ impl ResourceManager {
         if let Some(p) = self.resolve_full_path(&key, &["obj"]) {
             let id = self.resolve_id(&p);
             if !self.models.contains_key(&id) {
-                if let Some(obj) = self.load_object(key).map(|(id, v)| v.clone()) {
-                    let model = MultiPartModel::new(self, &obj);
+                //the parsed object is moved out while the model borrows the manager, instead of cloning it
+                if let Some(mut obj) = self.load_object(key).map(|(id, _)| id).and_then(|id| self.objects.remove(&id).map(|v| (id, v))) {
+                    let report = if let Some(options) = self.repair {
+                        obj.1.repair(options)
+                    } else {
+                        obj.1.validate()
+                    };
+                    self.reports.insert(id, report);
+                    let model = self.build_model(&obj.1);
+                    self.objects.insert(obj.0, obj.1);
                     self.models.insert(id, model);
+                    self.generation += 1;
                 }
             }
             self.models.get(&id).map(|v| (id, v))
@@ -278,13 +292,71 @@ impl ResourceManager {
         }
     }
 
+    ///repairs applied to the objects before they are turned into models (validation only if none)
+    pub fn set_repair_options(&mut self, options: Option<RepairOptions>) {
+        self.repair = options;
+    }
+
+    fn build_model(&mut self, obj: &ParsedObject) -> MultiPartModel {
+        let mut model = if let Some(options) = self.subdivision.filter(|o| o.levels > 0) {
+            MultiPartModel::new(self, &obj.subdivide(options))
+        } else {
+            MultiPartModel::new(self, obj)
+        };
+        if let Some(settings) = self.lod {
+            model.generate_lods(settings);
+        }
+        model
+    }
+
+    ///subdivision applied to the (repaired) objects before they are turned into models, see rebuild_multipart_models to apply it to the already loaded ones
+    pub fn set_subdivision(&mut self, options: Option<SubdivisionOptions>) {
+        self.subdivision = options;
+    }
+
+    pub fn subdivision(&self) -> Option<SubdivisionOptions> {
+        self.subdivision
+    }
+
+    ///rebuild all the loaded models from their parsed objects (ids are kept, so the instances in scenes are preserved)
+    pub fn rebuild_multipart_models(&mut self) {
+        for id in self.models.keys().copied().collect::<Vec<_>>() {
+            if let Some(obj) = self.objects.remove(&id) {
+                let model = self.build_model(&obj);
+                self.objects.insert(id, obj);
+                self.models.insert(id, model);
+            }
+        }
+        self.generation += 1;
+    }
+
+    ///simplified levels generated for the models loaded after this call (full detail only if none)
+    pub fn set_lod_settings(&mut self, settings: Option<LodSettings>) {
+        self.lod = settings;
+    }
+
+    ///issues found (and repaired if enabled) when the object of a model was loaded
+    pub fn get_validation_report(&self, id: usize) -> Option<&ValidationReport> {
+        self.reports.get(&id)
+    }
+
     pub fn get_multipart_model(&self, id: usize) -> Option<&MultiPartModel> {
         self.models.get(&id)
     }
 
     pub fn get_multipart_model_mut(&mut self, id: usize) -> Option<&mut MultiPartModel> {
+        self.generation += 1;
         self.models.get_mut(&id)
     }
+
+    pub fn multipart_models(&self) -> impl Iterator<Item = (usize, &MultiPartModel)> {
+        self.models.iter().map(|(id, m)| (*id, m))
+    }
+
+    ///changes each time the set of models (or their geometry) might have changed
+    pub fn models_generation(&self) -> usize {
+        self.generation
+    }
     
     pub fn unload_key<S: Into<String>>(&mut self, key: S) {
         if let Some(p) = self.resolve_full_path(key, &["obj", "mtl", "bmp", "txt", "frag", "vert", "geom"]) {
@@ -296,6 +368,8 @@ impl ResourceManager {
             self.maps.remove(&id);
             self.texts.remove(&id);
             self.models.remove(&id);
+            self.reports.remove(&id);
+            self.generation += 1;
             self.ids.remove(&p);
             self.map.retain(|_, v| *v != p);
         }
@@ -309,6 +383,8 @@ impl ResourceManager {
         self.maps.remove(&id);
         self.texts.remove(&id);
         self.models.remove(&id);
+        self.reports.remove(&id);
+        self.generation += 1;
         let mut p = "".to_string();
         self.ids.retain(|k, v| if *v == id {
             p = k.clone();
@@ -10,6 +10,155 @@ pub struct GlWindow {
     focused: bool,
 }
 
+///headless gl context of the tests touching the gpu, kept current on the calling thread while alive
+#[cfg(test)]
+pub enum OffscreenContext {
+    #[cfg(target_os = "linux")]
+    Egl(egl::Surfaceless),
+    #[cfg(target_os = "linux")]
+    OsMesa(glutin::Context<PossiblyCurrent>),
+}
+
+///mesa egl surfaceless context (llvmpipe without display), then osmesa, None if neither is installed
+#[cfg(all(test, target_os = "linux"))]
+pub fn offscreen_context() -> Option<OffscreenContext> {
+    use glutin::{Api, GlProfile, GlRequest};
+    use glutin::platform::unix::HeadlessContextExt;
+    for version in [(4, 3), (3, 3)] {
+        if let Some(context) = egl::Surfaceless::new(version) {
+            return Some(OffscreenContext::Egl(context));
+        }
+    }
+    for version in [(4, 3), (3, 3)] {
+        let context = ContextBuilder::new()
+            .with_gl(GlRequest::Specific(Api::OpenGl, version))
+            .with_gl_profile(GlProfile::Core)
+            .build_osmesa(PhysicalSize::new(16, 16));
+        if let Ok(Ok(context)) = context.map(|c| unsafe { c.make_current() }) {
+            gl::load_with(|s| context.get_proc_address(s) as *const _);
+            return Some(OffscreenContext::OsMesa(context));
+        }
+    }
+    None
+}
+
+#[cfg(all(test, not(target_os = "linux")))]
+pub fn offscreen_context() -> Option<OffscreenContext> { None }
+
+///minimal egl loaded at runtime (no link time dependency on libEGL)
+#[cfg(all(test, target_os = "linux"))]
+mod egl {
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::ptr::null_mut;
+
+    type Handle = *mut c_void;
+
+    const PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+    const OPENGL_API: u32 = 0x30A2;
+    const RENDERABLE_TYPE: i32 = 0x3040;
+    const OPENGL_BIT: i32 = 0x0008;
+    const SURFACE_TYPE: i32 = 0x3033;
+    const CONTEXT_MAJOR_VERSION: i32 = 0x3098;
+    const CONTEXT_MINOR_VERSION: i32 = 0x30FB;
+    const CONTEXT_OPENGL_PROFILE_MASK: i32 = 0x30FD;
+    const CONTEXT_OPENGL_CORE_PROFILE_BIT: i32 = 0x0001;
+    const NONE: i32 = 0x3038;
+
+    extern "C" {
+        fn dlopen(name: *const c_char, flags: c_int) -> Handle;
+        fn dlsym(handle: Handle, name: *const c_char) -> Handle;
+    }
+
+    ///context without any surface, the default framebuffer is replaced by a 16x16 framebuffer object
+    pub struct Surfaceless {
+        display: Handle,
+        context: Handle,
+        make_current: extern "C" fn(Handle, Handle, Handle, Handle) -> u32,
+        destroy_context: extern "C" fn(Handle, Handle) -> u32,
+    }
+
+    impl Surfaceless {
+        pub fn new((major, minor): (i32, i32)) -> Option<Self> {
+            unsafe {
+                let library = dlopen(c"libEGL.so.1".as_ptr(), 2);
+                if library.is_null() {
+                    return None;
+                }
+                let get_proc_address = dlsym(library, c"eglGetProcAddress".as_ptr());
+                if get_proc_address.is_null() {
+                    return None;
+                }
+                let get_proc_address: extern "C" fn(*const c_char) -> Handle = std::mem::transmute(get_proc_address);
+                let load = |name: &str| {
+                    let name = CString::new(name).unwrap();
+                    get_proc_address(name.as_ptr())
+                };
+                macro_rules! function {
+                    ($name:literal, $signature:ty) => {{
+                        let function = load($name);
+                        if function.is_null() {
+                            return None;
+                        }
+                        std::mem::transmute::<Handle, $signature>(function)
+                    }};
+                }
+                let get_platform_display = function!("eglGetPlatformDisplay", extern "C" fn(u32, Handle, *const isize) -> Handle);
+                let initialize = function!("eglInitialize", extern "C" fn(Handle, *mut i32, *mut i32) -> u32);
+                let bind_api = function!("eglBindAPI", extern "C" fn(u32) -> u32);
+                let choose_config = function!("eglChooseConfig", extern "C" fn(Handle, *const i32, *mut Handle, i32, *mut i32) -> u32);
+                let create_context = function!("eglCreateContext", extern "C" fn(Handle, Handle, Handle, *const i32) -> Handle);
+                let make_current = function!("eglMakeCurrent", extern "C" fn(Handle, Handle, Handle, Handle) -> u32);
+                let destroy_context = function!("eglDestroyContext", extern "C" fn(Handle, Handle) -> u32);
+
+                let display = get_platform_display(PLATFORM_SURFACELESS_MESA, null_mut(), std::ptr::null());
+                if display.is_null() || initialize(display, null_mut(), null_mut()) == 0 || bind_api(OPENGL_API) == 0 {
+                    return None;
+                }
+                let attributes = [RENDERABLE_TYPE, OPENGL_BIT, SURFACE_TYPE, 0, NONE];
+                let (mut config, mut count) = (null_mut(), 0);
+                if choose_config(display, attributes.as_ptr(), &mut config, 1, &mut count) == 0 || count == 0 {
+                    return None;
+                }
+                let attributes = [
+                    CONTEXT_MAJOR_VERSION, major,
+                    CONTEXT_MINOR_VERSION, minor,
+                    CONTEXT_OPENGL_PROFILE_MASK, CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                    NONE
+                ];
+                let context = create_context(display, config, null_mut(), attributes.as_ptr());
+                if context.is_null() {
+                    return None;
+                }
+                let surfaceless = Self { display, context, make_current, destroy_context };
+                if make_current(display, null_mut(), null_mut(), context) == 0 {
+                    return None;
+                }
+                gl::load_with(|s| load(s) as *const _);
+                let (mut framebuffer, mut color, mut depth) = (0, 0, 0);
+                gl::GenFramebuffers(1, &mut framebuffer);
+                gl::GenRenderbuffers(1, &mut color);
+                gl::GenRenderbuffers(1, &mut depth);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, color);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, 16, 16);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, 16, 16);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth);
+                gl::Viewport(0, 0, 16, 16);
+                Some(surfaceless)
+            }
+        }
+    }
+
+    impl Drop for Surfaceless {
+        fn drop(&mut self) {
+            (self.make_current)(self.display, null_mut(), null_mut(), null_mut());
+            (self.destroy_context)(self.display, self.context);
+        }
+    }
+}
+
 pub fn spawn_single_window(builder: WindowBuilder) -> Option<(Ctx, EventLoop<()>)> {
     let event_loop = EventLoop::new();
     let window_context = ContextBuilder::new()
@@ -25,9 +174,11 @@ pub fn spawn_single_window(builder: WindowBuilder) -> Option<(Ctx, EventLoop<()>
 }
 
 impl GlWindow {
-    pub fn new(builder: WindowBuilder) -> Option<(Self, EventLoop<()>)> {
+    ///samples of the default framebuffer (0: no multisampling)
+    pub fn new(builder: WindowBuilder, samples: u16) -> Option<(Self, EventLoop<()>)> {
         let event_loop = EventLoop::new();
         let window_context = ContextBuilder::new()
+            .with_multisampling(samples)
             .build_windowed(builder, &event_loop)
             .ok()?;
         let context = unsafe {
@@ -4,6 +4,8 @@ mod object;
 mod material;
 mod texture;
 mod point;
+mod validation;
+mod subdivision;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Point {
@@ -62,10 +64,10 @@ impl Default for ParsedMaterial {
         Self {
             specular_exponent: 0.,
             density: 1.,
-            transparency: 0.,
+            transparency: 1.,
             filter: [1., 1., 1.],
-            ambient: [0., 0., 0.],
-            diffuse: [0., 0., 0.],
+            ambient: [0.2, 0.2, 0.2], //defaults of the mtl format
+            diffuse: [0.8, 0.8, 0.8],
             specular: [0., 0., 0.],
             emissive: [0., 0., 0.],
             illum: 2,
@@ -90,4 +92,47 @@ pub struct ParsedTexture {
     pub width: usize,
     pub height: usize,
     pub data: Vec<u8>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReferenceKind {
+    Vertex,
+    Uv,
+    Normal
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshIssue {
+    OutOfRange { face: usize, corner: usize, kind: ReferenceKind, index: usize }, //reference to an element that was never declared
+    Degenerate { face: usize }, //repeated vertex or (almost) zero area
+    Duplicate { face: usize, original: usize }, //same vertices as a previous face, in any order
+    NonManifoldEdge { edge: [usize; 2], faces: usize }, //edge shared by more than 2 faces
+    InconsistentWinding { edge: [usize; 2], faces: [usize; 2] }, //both faces walk the shared edge in the same direction
+    UnusedVertex { vertex: usize },
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ValidationReport {
+    pub issues: Vec<MeshIssue>,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RepairOptions {
+    pub drop_degenerates: bool, //also drops faces with out of range vertex references
+    pub drop_duplicates: bool,
+    pub unify_winding: bool,
+    pub weld_epsilon: Option<f32>, //merge vertices closer than this distance (and remove the unused ones)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubdivisionScheme {
+    Loop, //triangles only, other faces are fanned first
+    CatmullClark //any polygon, outputs quads
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SubdivisionOptions {
+    pub scheme: Option<SubdivisionScheme>, //None picks Loop for pure triangle meshes, Catmull-Clark otherwise
+    pub levels: usize,
+    pub crease_materials: bool, //keep the edges between faces of different materials sharp
+}
@@ -1,5 +1,10 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread;
 use crate::maths::matrix::Mat3;
 use crate::maths::quaternion::Quaternion;
 use crate::maths::vector::{Vec3, Vector};
@@ -60,36 +65,207 @@ impl ParsedObject {
     }
     
     pub fn parse(resources: &mut ResourceManager, file: File) -> Option<Self> {
+        Self::parse_chunked(resources, file, CHUNK_SIZE)
+    }
+
+    ///split the reader in line aligned blocks of roughly `chunk_size` bytes, parse them on worker threads and merge them back in order
+    ///(only the blocks in flight and the parsed chunks waiting for their predecessors are kept in memory)
+    pub fn parse_chunked<R: Read + Send>(resources: &mut ResourceManager, reader: R, chunk_size: usize) -> Option<Self> {
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk_size = chunk_size.max(1);
+        let abort = AtomicBool::new(false);
+        let (block_sender, block_receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(workers * 2);
+        let block_receiver = Mutex::new(block_receiver);
+        let (chunk_sender, chunk_receiver) = mpsc::channel::<(usize, Option<ParsedChunk>)>();
+        thread::scope(|scope| {
+            let abort = &abort;
+            scope.spawn(move || Self::split_blocks(reader, chunk_size, block_sender, abort));
+            for _ in 0..workers {
+                let block_receiver = &block_receiver;
+                let chunk_sender = chunk_sender.clone();
+                scope.spawn(move || loop {
+                    let block = block_receiver.lock().ok().and_then(|r| r.recv().ok());
+                    if let Some((index, block)) = block {
+                        let chunk = if abort.load(Ordering::Relaxed) { None } else { ParsedChunk::parse(&block) };
+                        if chunk_sender.send((index, chunk)).is_err() {
+                            return;
+                        }
+                    } else {
+                        return;
+                    }
+                });
+            }
+            drop(chunk_sender);
+            let mut out = Self {
+                libs: ParsedMaterialLib::with_default_material(),
+                ..Self::default()
+            };
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            let mut valid = true;
+            for (index, chunk) in chunk_receiver.iter() {
+                if !valid {
+                    continue; //keep draining so the workers can finish
+                }
+                pending.insert(index, chunk);
+                while let Some(chunk) = pending.remove(&next) {
+                    next += 1;
+                    if !chunk.is_some_and(|chunk| out.merge_chunk(resources, chunk)) {
+                        valid = false;
+                        abort.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+            if valid { out.finish() } else { None }
+        })
+    }
+
+    fn split_blocks<R: Read>(mut reader: R, chunk_size: usize, sender: SyncSender<(usize, Vec<u8>)>, abort: &AtomicBool) {
+        let mut index = 0;
+        let mut carry = Vec::new();
+        loop {
+            let mut block = std::mem::take(&mut carry);
+            let start = block.len();
+            block.resize(start + chunk_size, 0);
+            let mut filled = start;
+            while filled < block.len() {
+                match reader.read(&mut block[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            block.truncate(filled);
+            let eof = filled < start + chunk_size;
+            if !eof {
+                //the unfinished line at the end of the block is kept for the next one
+                if let Some(cut) = block.iter().rposition(|b| *b == b'\n') {
+                    carry = block.split_off(cut + 1);
+                } else {
+                    carry = block;
+                    continue;
+                }
+            }
+            if abort.load(Ordering::Relaxed) || (!block.is_empty() && sender.send((index, block)).is_err()) {
+                return;
+            }
+            index += 1;
+            if eof {
+                return;
+            }
+        }
+    }
+
+    ///append a chunk to this object, resolving the directives and the relative references in the order of the original file
+    fn merge_chunk(&mut self, resources: &mut ResourceManager, chunk: ParsedChunk) -> bool {
+        let base = [self.vertexes.len() as isize, self.uvs.len() as isize, self.normals.len() as isize];
+        let mut faces = chunk.faces;
+        for (face, corner, component, local) in chunk.relative {
+            let t = base[component] + local;
+            if t < 1 {
+                return false; //error: looping back reference
+            }
+            faces[face][corner][component] = t as usize;
+        }
+        self.vertexes.extend(chunk.vertexes);
+        self.uvs.extend(chunk.uvs);
+        self.normals.extend(chunk.normals);
+        let mut faces = faces.into_iter();
+        let mut consumed = 0;
+        for (at, directive) in chunk.directives {
+            self.push_faces(faces.by_ref().take(at - consumed));
+            consumed = at;
+            match directive {
+                Directive::Mtllib(libs) => {
+                    for lib in libs.iter().filter_map(|f| resources.load_material_lib(f.as_str()).map(|(_, v)| v.clone())) {
+                        self.libs.merge(&lib);
+                        for name in lib.0.keys() {
+                            if !self.materials.contains(name) {
+                                self.material_index.insert(name.clone(), self.materials.len());
+                                self.materials.push(name.clone());
+                            }
+                        }
+                    }
+                }
+                Directive::Usemtl(name) => {
+                    if let Some(id) = self.material_index.get(&name) {
+                        let l = self.groups.len();
+                        if l > 0 && !self.faces.is_empty() {
+                            self.groups[l - 1][2] = self.faces.len() - 1;
+                        }
+                        self.groups.push([*id, self.faces.len(), self.faces.len()]);
+                    } else {
+                        //invalid material reference error
+                    }
+                }
+            }
+        }
+        self.push_faces(faces);
+        true
+    }
+
+    fn push_faces(&mut self, faces: impl Iterator<Item = Vec<[usize; 3]>>) {
+        let l = self.faces.len();
+        self.faces.extend(faces);
+        let g = self.groups.len();
+        if g > 0 && self.faces.len() > l {
+            self.groups[g - 1][2] = self.faces.len() - 1;
+        }
+    }
+
+    fn finish(mut self) -> Option<Self> {
+        if !self.faces.is_empty() && !self.vertexes.is_empty() {
+            if self.materials.is_empty() {
+                self.materials.push("default".to_string());
+            }
+            if self.groups.is_empty() { //fix missing / undeclared groups
+                self.groups.push([0, 0, self.faces.len() - 1]);
+            }
+            Some(self)
+        } else {
+            None
+        }
+    }
+}
+
+//size of the blocks of text handed to the parsing threads
+const CHUNK_SIZE: usize = 4 << 20;
+
+enum Directive {
+    Mtllib(Vec<String>),
+    Usemtl(String)
+}
+
+//partial object parsed from a block of lines, references are either absolute or relative to the start of the block
+#[derive(Default)]
+struct ParsedChunk {
+    vertexes: Vec<Point>,
+    uvs: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    faces: Vec<Vec<[usize; 3]>>,
+    relative: Vec<(usize, usize, usize, isize)>, //face, corner, component, reference counted from the start of the block
+    directives: Vec<(usize, Directive)>, //amount of faces of this block preceding the directive, directive
+}
+
+impl ParsedChunk {
+    fn parse(block: &[u8]) -> Option<Self> {
         let mut out = Self::default();
-        out.libs = ParsedMaterialLib::with_default_material();
-        for line in BufReader::new(file).lines().filter_map(|l| l.ok()) {
-            let columns = line.split_whitespace().collect::<Vec<&str>>();
+        let mut columns = Vec::new();
+        for line in block.split(|b| *b == b'\n').filter_map(|l| std::str::from_utf8(l).ok()) {
+            columns.clear();
+            columns.extend(line.split_whitespace());
             if columns.len() >= 2 {
                 match columns[0] {
                     "mtllib" => {
-                        for lib in columns[1..].iter().filter_map(|f| resources.load_material_lib(*f).map(|(_, v)| v.clone())) {
-                            out.libs.merge(&lib);
-                            for name in lib.0.keys() {
-                                if !out.materials.contains(name) {
-                                    out.material_index.insert(name.clone(), out.materials.len());
-                                    out.materials.push(name.clone());
-                                }
-                            }
-                        }
+                        out.directives.push((out.faces.len(), Directive::Mtllib(columns[1..].iter().map(|s| s.to_string()).collect())));
                     }
                     "usemtl" if columns.len() == 2 => {
-                        if let Some(id) = out.material_index.get(columns[1]) {
-                            let l = out.groups.len();
-                            if l > 0 && out.faces.len() > 0 {
-                                out.groups[l - 1][2] = out.faces.len() - 1;
-                            }
-                            out.groups.push([*id, out.faces.len(), out.faces.len()]);
-                        } else {
-                            //invalid material reference error
-                        }
+                        out.directives.push((out.faces.len(), Directive::Usemtl(columns[1].to_string())));
                     }
                     "v" if columns.len() >= 4 && columns.len() <= 8 => {
-                        if let Some(point) = Point::parse(columns) {
+                        if let Some(point) = Point::parse(&columns) {
                             out.vertexes.push(point);
                         } else {
                             //invalid vertex definition error
@@ -119,30 +295,27 @@ impl ParsedObject {
                         }
                     }
                     "f" => {
-                        let mut f = Vec::new();
+                        let mut f = Vec::with_capacity(columns.len() - 1);
                         let mut sm = 0;
-                        for tc in columns[1..].iter().map(|c| c.split('/')) {
+                        let face = out.faces.len();
+                        for (corner, tc) in columns[1..].iter().map(|c| c.split('/')).enumerate() {
                             let mut r = [0usize; 3];
-                            let mut i = 0;
                             let mut mask = 0;
-                            for s in tc {
-                                if i < 3 && s.len() > 0 {
-                                    let mut t: isize = s.parse().ok()?;
+                            for (i, s) in tc.enumerate() {
+                                if i < 3 && !s.is_empty() {
+                                    let t: isize = s.parse().ok()?;
                                     if t < 0 {
-                                        t = match i {
+                                        let local = match i {
                                             0 => out.vertexes.len(),
                                             1 => out.uvs.len(),
-                                            2 => out.normals.len(),
-                                            _ => 0,
-                                        } as isize + t;
-                                    }
-                                    if t < 0 {
-                                        return None; //error: looping back reference
+                                            _ => out.normals.len(),
+                                        } as isize + t + 1; //-1 is the last element declared
+                                        out.relative.push((face, corner, i, local));
+                                    } else {
+                                        r[i] = t as usize;
                                     }
-                                    r[i] = t as usize;
                                     mask |= 1 << i;
                                 }
-                                i += 1;
                             }
                             if sm == 0 {
                                 sm = mask;
@@ -152,10 +325,6 @@ impl ParsedObject {
                             f.push(r);
                         }
                         if f.len() >= 3 {
-                            let l = out.groups.len();
-                            if l > 0 {
-                                out.groups[l - 1][2] = out.faces.len();
-                            }
                             out.faces.push(f);
                         } else {
                             return None; //face too short (require at least 3 references)
@@ -165,16 +334,35 @@ impl ParsedObject {
                 }
             }
         }
-        if out.faces.len() > 0 && out.vertexes.len() > 0 {
-            if out.materials.len() == 0 {
-                out.materials.push("default".to_string());
-            }
-            if out.groups.len() == 0 { //fix missing / undeclared groups
-                out.groups.push([0, 0, out.faces.len() - 1]);
-            }
-            Some(out)
-        } else {
-            None
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::other::resource_manager::ResourceManager;
+    use super::ParsedObject;
+
+    const QUADS: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\nf -4/-2 -3/-1 -2/-1 -1/-2\nv 0 0 1\nv 1 0 1\nv 1 1 1\nf 1/1 -3/2 -2/2 -1/1\n";
+
+    #[test]
+    fn chunks_preserve_relative_references() {
+        let mut resources = ResourceManager::default();
+        let whole = ParsedObject::parse_chunked(&mut resources, Cursor::new(QUADS), 1 << 20).unwrap();
+        for chunk_size in [1, 7, 16, 33] {
+            let split = ParsedObject::parse_chunked(&mut resources, Cursor::new(QUADS), chunk_size).unwrap();
+            assert_eq!(split.vertexes.len(), 7);
+            assert_eq!(split.faces, whole.faces);
+            assert_eq!(split.groups, whole.groups);
         }
+        assert_eq!(whole.faces[0], vec![[1, 1, 0], [2, 2, 0], [3, 2, 0], [4, 1, 0]]);
+        assert_eq!(whole.faces[1], vec![[1, 1, 0], [5, 2, 0], [6, 2, 0], [7, 1, 0]]);
+    }
+
+    #[test]
+    fn rejects_looping_back_references() {
+        let mut resources = ResourceManager::default();
+        assert!(ParsedObject::parse_chunked(&mut resources, Cursor::new("v 0 0 0\nv 1 0 0\nf 1 2 -3\n"), 8).is_none());
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,7 @@
 use super::Point;
 
 impl Point {
-    pub fn parse(columns: Vec<&str>) -> Option<Self> {
+    pub fn parse(columns: &[&str]) -> Option<Self> {
         let mut out = Self::default();
         match columns.len() - 1 {
             3 => {
@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use crate::parser::{ParsedObject, Point, SubdivisionOptions, SubdivisionScheme};
+
+//topology of the valid faces of an object, rebuilt for each level
+struct Topology {
+    faces: Vec<Vec<[usize; 2]>>, //0 based (vertex, uv + 1) corners, uv 0 means missing
+    materials: Vec<usize>, //per face, usize::MAX if not in a group
+    edges: HashMap<[usize; 2], usize>, //sorted vertex pair -> edge
+    edge_faces: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    fn new(faces: Vec<Vec<[usize; 2]>>, materials: Vec<usize>) -> Self {
+        let mut edges = HashMap::new();
+        let mut edge_faces: Vec<Vec<usize>> = Vec::new();
+        for (f, face) in faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let e = *edges.entry(Self::key(face[i][0], face[(i + 1) % face.len()][0])).or_insert_with(|| {
+                    edge_faces.push(Vec::new());
+                    edge_faces.len() - 1
+                });
+                edge_faces[e].push(f);
+            }
+        }
+        Self { faces, materials, edges, edge_faces }
+    }
+
+    fn key(a: usize, b: usize) -> [usize; 2] {
+        [a.min(b), a.max(b)]
+    }
+
+    fn edge(&self, a: usize, b: usize) -> usize {
+        self.edges[&Self::key(a, b)]
+    }
+
+    ///border, non manifold or (optionally) material boundary
+    fn is_crease(&self, edge: usize, materials: bool) -> bool {
+        let faces = &self.edge_faces[edge];
+        faces.len() != 2 || (materials && self.materials[faces[0]] != self.materials[faces[1]])
+    }
+
+    ///per vertex: the other end of each crease edge, and the neighbours through smooth edges
+    fn vertex_rings(&self, vertices: usize, materials: bool) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+        let mut creases = vec![Vec::new(); vertices];
+        let mut ring = vec![Vec::new(); vertices];
+        for (&[a, b], &e) in &self.edges {
+            let target = if self.is_crease(e, materials) { &mut creases } else { &mut ring };
+            target[a].push(b);
+            target[b].push(a);
+        }
+        (creases, ring)
+    }
+}
+
+//position and color are smoothed together
+type Attributes = [f32; 6];
+
+fn attributes(p: &Point) -> Attributes {
+    [p.pos[0], p.pos[1], p.pos[2], p.color[0], p.color[1], p.color[2]]
+}
+
+fn point(a: Attributes) -> Point {
+    Point {
+        pos: [a[0], a[1], a[2]],
+        color: [a[3], a[4], a[5]],
+        w: 1.
+    }
+}
+
+fn weighted(terms: &[(f32, Attributes)]) -> Attributes {
+    let mut out = [0.; 6];
+    for (w, a) in terms {
+        for i in 0..6 {
+            out[i] += w * a[i];
+        }
+    }
+    out
+}
+
+fn average<'a>(values: impl Iterator<Item = &'a Attributes>) -> Attributes {
+    let mut out = [0.; 6];
+    let mut count = 0.;
+    for a in values {
+        for i in 0..6 {
+            out[i] += a[i];
+        }
+        count += 1.;
+    }
+    out.map(|v| if count > 0. { v / count } else { v })
+}
+
+//face varying uvs are interpolated linearly, edge uvs are shared by the faces using the same pair of uvs
+struct Uvs<'a> {
+    uvs: &'a mut Vec<[f32; 3]>,
+    edges: HashMap<[usize; 2], usize>,
+}
+
+impl Uvs<'_> {
+    fn mix(&mut self, corners: &[usize]) -> usize {
+        if corners.contains(&0) {
+            return 0;
+        }
+        let mut out = [0.; 3];
+        for uv in corners {
+            for (o, v) in out.iter_mut().zip(self.uvs[uv - 1]) {
+                *o += v / corners.len() as f32;
+            }
+        }
+        self.uvs.push(out);
+        self.uvs.len()
+    }
+
+    fn edge(&mut self, a: usize, b: usize) -> usize {
+        let key = Topology::key(a, b);
+        if let Some(uv) = self.edges.get(&key) {
+            return *uv;
+        }
+        let uv = self.mix(&[a, b]);
+        self.edges.insert(key, uv);
+        uv
+    }
+}
+
+impl SubdivisionScheme {
+    ///Loop for pure triangle meshes, Catmull-Clark otherwise
+    pub fn for_object(object: &ParsedObject) -> Self {
+        if object.faces.iter().all(|f| f.len() == 3) {
+            Self::Loop
+        } else {
+            Self::CatmullClark
+        }
+    }
+}
+
+impl ParsedObject {
+    ///subdivided copy of this object, faces referencing missing vertexes are dropped, normals are recomputed (smooth) if the object had any
+    pub fn subdivide(&self, options: SubdivisionOptions) -> ParsedObject {
+        let scheme = options.scheme.unwrap_or_else(|| SubdivisionScheme::for_object(self));
+        let mut face_materials = vec![usize::MAX; self.faces.len()];
+        for &[material, start, end] in &self.groups {
+            for m in &mut face_materials[start..=end.min(self.faces.len().saturating_sub(1))] {
+                *m = material;
+            }
+        }
+        //first level works on the parsed faces, keeping track of how many faces each one became to remap the groups
+        let mut origin = Vec::new(); //per face of the current level: index of the parsed face it comes from
+        let mut faces = Vec::new();
+        let mut materials = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            if face.len() < 3 || face.iter().any(|c| c[0] == 0 || c[0] > self.vertexes.len()) {
+                continue;
+            }
+            let corners = face.iter().map(|c| [c[0] - 1, if c[1] <= self.uvs.len() { c[1] } else { 0 }]).collect::<Vec<_>>();
+            let fan = if scheme == SubdivisionScheme::Loop { corners.len() - 2 } else { 1 };
+            for i in 0..fan {
+                faces.push(if scheme == SubdivisionScheme::Loop { vec![corners[0], corners[i + 1], corners[i + 2]] } else { corners.clone() });
+                materials.push(face_materials[f]);
+                origin.push(f);
+            }
+        }
+        let mut vertexes = self.vertexes.iter().map(attributes).collect::<Vec<_>>();
+        let mut uvs = self.uvs.clone();
+        for _ in 0..options.levels {
+            let topology = Topology::new(faces, materials);
+            let (v, f, m, o) = match scheme {
+                SubdivisionScheme::Loop => loop_level(&topology, &vertexes, &mut uvs, options.crease_materials),
+                SubdivisionScheme::CatmullClark => catmull_clark_level(&topology, &vertexes, &mut uvs, options.crease_materials),
+            };
+            vertexes = v;
+            faces = f;
+            materials = m;
+            origin = o.into_iter().map(|f| origin[f]).collect();
+        }
+        let mut out = ParsedObject {
+            libs: self.libs.clone(),
+            vertexes: vertexes.into_iter().map(point).collect(),
+            uvs,
+            normals: Vec::new(),
+            materials: self.materials.clone(),
+            material_index: self.material_index.clone(),
+            groups: Vec::new(),
+            faces: faces.into_iter().map(|f| f.into_iter().map(|[v, uv]| [v + 1, uv, 0]).collect()).collect(),
+            normalized: self.normalized,
+        };
+        //faces stay sorted by the parsed face they come from, so each group maps to a contiguous range
+        for &[material, start, end] in &self.groups {
+            let first = origin.partition_point(|f| *f < start);
+            let last = origin.partition_point(|f| *f <= end);
+            if last > first {
+                out.groups.push([material, first, last - 1]);
+            }
+        }
+        if !self.normals.is_empty() {
+            out.smooth_normals();
+        }
+        out
+    }
+
+    ///area weighted normal per vertex, referenced by the faces with the same index as the vertex
+    fn smooth_normals(&mut self) {
+        let mut normals = vec![[0f32; 3]; self.vertexes.len()];
+        for face in &self.faces {
+            //newell's method, also works for non planar polygons
+            let mut n = [0.; 3];
+            for i in 0..face.len() {
+                let a = self.vertexes[face[i][0] - 1].pos;
+                let b = self.vertexes[face[(i + 1) % face.len()][0] - 1].pos;
+                n[0] += (a[1] - b[1]) * (a[2] + b[2]);
+                n[1] += (a[2] - b[2]) * (a[0] + b[0]);
+                n[2] += (a[0] - b[0]) * (a[1] + b[1]);
+            }
+            for c in face {
+                for i in 0..3 {
+                    normals[c[0] - 1][i] += n[i];
+                }
+            }
+        }
+        self.normals = normals.into_iter().map(|n| {
+            let l = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if l > 0. { n.map(|c| c / l) } else { n }
+        }).collect();
+        for face in &mut self.faces {
+            for c in face {
+                c[2] = c[0];
+            }
+        }
+    }
+}
+
+type Level = (Vec<Attributes>, Vec<Vec<[usize; 2]>>, Vec<usize>, Vec<usize>);
+
+fn catmull_clark_level(topology: &Topology, vertexes: &[Attributes], uvs: &mut Vec<[f32; 3]>, crease_materials: bool) -> Level {
+    let mut uvs = Uvs { uvs, edges: HashMap::new() };
+    let face_points = topology.faces.iter().map(|f| average(f.iter().map(|c| &vertexes[c[0]]))).collect::<Vec<_>>();
+    let edge_base = vertexes.len();
+    let face_base = edge_base + topology.edge_faces.len();
+    let mut out = vec![[0.; 6]; face_base];
+    out.extend_from_slice(&face_points);
+    for (&[a, b], &e) in &topology.edges {
+        out[edge_base + e] = if topology.is_crease(e, crease_materials) {
+            weighted(&[(0.5, vertexes[a]), (0.5, vertexes[b])])
+        } else {
+            let faces = &topology.edge_faces[e];
+            average([vertexes[a], vertexes[b], face_points[faces[0]], face_points[faces[1]]].iter())
+        };
+    }
+    let (creases, ring) = topology.vertex_rings(vertexes.len(), crease_materials);
+    let mut vertex_faces = vec![Vec::new(); vertexes.len()];
+    for (f, face) in topology.faces.iter().enumerate() {
+        face.iter().for_each(|c| vertex_faces[c[0]].push(f));
+    }
+    for (v, p) in vertexes.iter().enumerate() {
+        out[v] = match creases[v].len() {
+            0 | 1 if !vertex_faces[v].is_empty() => {
+                //(Q + 2R + (n - 3)P) / n, Q: average of the face points, R: average of the edge midpoints
+                let n = (creases[v].len() + ring[v].len()) as f32;
+                let q = average(vertex_faces[v].iter().map(|f| &face_points[*f]));
+                let r = average(creases[v].iter().chain(&ring[v]).map(|o| weighted(&[(0.5, *p), (0.5, vertexes[*o])])).collect::<Vec<_>>().iter());
+                weighted(&[(1. / n, q), (2. / n, r), ((n - 3.) / n, *p)])
+            }
+            2 => weighted(&[(0.75, *p), (0.125, vertexes[creases[v][0]]), (0.125, vertexes[creases[v][1]])]),
+            _ => *p,
+        };
+    }
+    let mut faces = Vec::new();
+    let mut materials = Vec::new();
+    let mut origin = Vec::new();
+    for (f, face) in topology.faces.iter().enumerate() {
+        let center = [face_base + f, uvs.mix(&face.iter().map(|c| c[1]).collect::<Vec<_>>())];
+        let mids = (0..face.len()).map(|i| {
+            let (a, b) = (face[i], face[(i + 1) % face.len()]);
+            [edge_base + topology.edge(a[0], b[0]), uvs.edge(a[1], b[1])]
+        }).collect::<Vec<_>>();
+        for i in 0..face.len() {
+            faces.push(vec![face[i], mids[i], center, mids[(i + face.len() - 1) % face.len()]]);
+            materials.push(topology.materials[f]);
+            origin.push(f);
+        }
+    }
+    (out, faces, materials, origin)
+}
+
+fn loop_level(topology: &Topology, vertexes: &[Attributes], uvs: &mut Vec<[f32; 3]>, crease_materials: bool) -> Level {
+    let mut uvs = Uvs { uvs, edges: HashMap::new() };
+    let edge_base = vertexes.len();
+    let mut out = vec![[0.; 6]; edge_base + topology.edge_faces.len()];
+    //vertex of a face that is not on the edge a-b (faces are fanned to triangles first, so only the third corner)
+    let opposite = |f: usize, a: usize, b: usize| topology.faces[f].iter().map(|c| c[0]).find(|v| *v != a && *v != b).unwrap_or(a);
+    for (&[a, b], &e) in &topology.edges {
+        out[edge_base + e] = if topology.is_crease(e, crease_materials) {
+            weighted(&[(0.5, vertexes[a]), (0.5, vertexes[b])])
+        } else {
+            let faces = &topology.edge_faces[e];
+            let (c, d) = (opposite(faces[0], a, b), opposite(faces[1], a, b));
+            weighted(&[(0.375, vertexes[a]), (0.375, vertexes[b]), (0.125, vertexes[c]), (0.125, vertexes[d])])
+        };
+    }
+    let (creases, ring) = topology.vertex_rings(vertexes.len(), crease_materials);
+    for (v, p) in vertexes.iter().enumerate() {
+        out[v] = match creases[v].len() {
+            0 | 1 if !ring[v].is_empty() => {
+                let n = (creases[v].len() + ring[v].len()) as f32;
+                let beta = if n == 3. { 3. / 16. } else { 3. / (8. * n) };
+                let mut terms = vec![(1. - n * beta, *p)];
+                terms.extend(creases[v].iter().chain(&ring[v]).map(|o| (beta, vertexes[*o])));
+                weighted(&terms)
+            }
+            2 => weighted(&[(0.75, *p), (0.125, vertexes[creases[v][0]]), (0.125, vertexes[creases[v][1]])]),
+            _ => *p,
+        };
+    }
+    let mut faces = Vec::new();
+    let mut materials = Vec::new();
+    let mut origin = Vec::new();
+    for (f, face) in topology.faces.iter().enumerate() {
+        let mid = |uvs: &mut Uvs, a: [usize; 2], b: [usize; 2]| [edge_base + topology.edge(a[0], b[0]), uvs.edge(a[1], b[1])];
+        let (x, y, z) = (face[0], face[1], face[2]);
+        let (xy, yz, zx) = (mid(&mut uvs, x, y), mid(&mut uvs, y, z), mid(&mut uvs, z, x));
+        for tri in [[x, xy, zx], [y, yz, xy], [z, zx, yz], [xy, yz, zx]] {
+            faces.push(tri.to_vec());
+            materials.push(topology.materials[f]);
+            origin.push(f);
+        }
+    }
+    (out, faces, materials, origin)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::parser::{ParsedObject, Point, SubdivisionOptions, SubdivisionScheme};
+
+    fn cube() -> ParsedObject {
+        let mut out = ParsedObject::default();
+        for i in 0..8 {
+            out.vertexes.push(Point { pos: [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32], ..Default::default() });
+        }
+        for f in [[1, 3, 4, 2], [5, 6, 8, 7], [1, 2, 6, 5], [3, 7, 8, 4], [1, 5, 7, 3], [2, 4, 8, 6]] {
+            out.faces.push(f.iter().map(|v| [*v, 0, 0]).collect());
+        }
+        out.groups = vec![[0, 0, 2], [1, 3, 5]];
+        out
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let options = SubdivisionOptions { scheme: None, levels: 2, crease_materials: false };
+        let cube = cube();
+        let out = cube.subdivide(options);
+        assert_eq!(out.faces.len(), 6 * 16);
+        assert_eq!(out.groups, vec![[0, 0, 47], [1, 48, 95]]);
+        //closed smooth surface: every vertex moved strictly inside the cube and around its center
+        for v in &out.vertexes {
+            assert!(v.pos.iter().all(|c| *c > 0. && *c < 1.), "{:?}", v.pos);
+        }
+        //the material boundary is kept as a crease: the midpoints of those edges stay on the edges of the cube
+        let creased = cube.subdivide(SubdivisionOptions { levels: 1, crease_materials: true, ..options });
+        let on_edge = creased.vertexes.iter().filter(|v| v.pos.iter().filter(|c| **c == 0. || **c == 1.).count() >= 2).count();
+        assert!(on_edge > 0);
+    }
+
+    #[test]
+    fn loop_triangle_counts() {
+        let mut tetra = ParsedObject::default();
+        for pos in [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [0., 0., 1.]] {
+            tetra.vertexes.push(Point { pos, ..Default::default() });
+        }
+        tetra.uvs = vec![[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]];
+        for f in [[1, 3, 2], [1, 2, 4], [1, 4, 3], [2, 3, 4]] {
+            tetra.faces.push(f.iter().enumerate().map(|(i, v)| [*v, i + 1, 0]).collect());
+        }
+        tetra.groups = vec![[0, 0, 3]];
+        assert_eq!(SubdivisionScheme::for_object(&tetra), SubdivisionScheme::Loop);
+        let out = tetra.subdivide(SubdivisionOptions { scheme: None, levels: 3, crease_materials: true });
+        assert_eq!(out.faces.len(), 4 * 64);
+        //euler characteristic of a closed genus 0 surface: V - E + F = 2 with E = 3F / 2
+        assert_eq!(out.vertexes.len() as isize - (out.faces.len() * 3 / 2) as isize + out.faces.len() as isize, 2);
+        //uvs are linearly interpolated, so they stay in the triangle of the original uvs
+        for uv in &out.uvs {
+            assert!(uv[0] >= 0. && uv[1] >= 0. && uv[0] + uv[1] <= 1. + 1e-6);
+        }
+    }
+}
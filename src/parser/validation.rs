@@ -0,0 +1,367 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use crate::maths::vector::Vec3;
+use super::{MeshIssue, ParsedObject, ReferenceKind, RepairOptions, ValidationReport};
+
+//maximum amount of issues of the same kind listed when printing a report
+const PRINTED_ISSUES: usize = 8;
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl MeshIssue {
+    fn label(&self) -> &'static str {
+        match self {
+            MeshIssue::OutOfRange { .. } => "out of range references",
+            MeshIssue::Degenerate { .. } => "degenerate faces",
+            MeshIssue::Duplicate { .. } => "duplicate faces",
+            MeshIssue::NonManifoldEdge { .. } => "non manifold edges",
+            MeshIssue::InconsistentWinding { .. } => "inconsistent windings",
+            MeshIssue::UnusedVertex { .. } => "unused vertices",
+        }
+    }
+}
+
+impl Display for MeshIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MeshIssue::OutOfRange { face, corner, kind, index } => f.write_fmt(format_args!("face {face} corner {corner}: {kind:?} {index} does not exist")),
+            MeshIssue::Degenerate { face } => f.write_fmt(format_args!("face {face} has no area")),
+            MeshIssue::Duplicate { face, original } => f.write_fmt(format_args!("face {face} repeats face {original}")),
+            MeshIssue::NonManifoldEdge { edge, faces } => f.write_fmt(format_args!("edge {}-{} is shared by {faces} faces", edge[0], edge[1])),
+            MeshIssue::InconsistentWinding { edge, faces } => f.write_fmt(format_args!("faces {} and {} walk edge {}-{} in the same direction", faces[0], faces[1], edge[0], edge[1])),
+            MeshIssue::UnusedVertex { vertex } => f.write_fmt(format_args!("vertex {vertex} is never used")),
+        }
+    }
+}
+
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_clean() {
+            return f.write_str("no issue found");
+        }
+        let mut labels: Vec<&str> = Vec::new();
+        for issue in &self.issues {
+            if !labels.contains(&issue.label()) {
+                labels.push(issue.label());
+            }
+        }
+        for label in labels {
+            let issues = self.issues.iter().filter(|i| i.label() == label).collect::<Vec<_>>();
+            f.write_fmt(format_args!("{} {label}:\n", issues.len()))?;
+            for issue in issues.iter().take(PRINTED_ISSUES) {
+                f.write_fmt(format_args!("  {issue}\n"))?;
+            }
+            if issues.len() > PRINTED_ISSUES {
+                f.write_fmt(format_args!("  ... and {} more\n", issues.len() - PRINTED_ISSUES))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ParsedObject {
+    ///check every face and vertex of this object, references in the report are 0 based indexes
+    pub fn validate(&self) -> ValidationReport {
+        let mut out = ValidationReport::default();
+        let mut used = vec![false; self.vertexes.len()];
+        let mut seen: HashMap<Vec<usize>, usize> = HashMap::new();
+        for (face, corners) in self.faces.iter().enumerate() {
+            for (corner, r) in corners.iter().enumerate() {
+                for (kind, index, len) in [
+                    (ReferenceKind::Vertex, r[0], self.vertexes.len()),
+                    (ReferenceKind::Uv, r[1], self.uvs.len()),
+                    (ReferenceKind::Normal, r[2], self.normals.len())
+                ] {
+                    //uvs and normals are optional (0), vertices are not
+                    if index > len || (index == 0 && kind == ReferenceKind::Vertex) {
+                        out.issues.push(MeshIssue::OutOfRange { face, corner, kind, index });
+                    }
+                }
+            }
+            if !self.has_valid_vertices(face) {
+                continue;
+            }
+            corners.iter().for_each(|r| used[r[0] - 1] = true);
+            if self.is_degenerate(face) {
+                out.issues.push(MeshIssue::Degenerate { face });
+            }
+            let mut key = corners.iter().map(|r| r[0]).collect::<Vec<usize>>();
+            key.sort_unstable();
+            if let Some(original) = seen.get(&key) {
+                out.issues.push(MeshIssue::Duplicate { face, original: *original });
+            } else {
+                seen.insert(key, face);
+            }
+        }
+        let mut edges = self.edges().into_iter().collect::<Vec<_>>();
+        edges.sort_unstable_by_key(|(edge, _)| *edge);
+        for (edge, faces) in edges {
+            let vertices = [edge[0] - 1, edge[1] - 1];
+            if faces.len() > 2 {
+                out.issues.push(MeshIssue::NonManifoldEdge { edge: vertices, faces: faces.len() });
+            } else if faces.len() == 2 && faces[0].1 == faces[1].1 {
+                out.issues.push(MeshIssue::InconsistentWinding { edge: vertices, faces: [faces[0].0, faces[1].0] });
+            }
+        }
+        for (vertex, used) in used.into_iter().enumerate() {
+            if !used {
+                out.issues.push(MeshIssue::UnusedVertex { vertex });
+            }
+        }
+        out
+    }
+
+    ///apply the requested repairs (weld, then drop, then winding) and return the validation of the result
+    pub fn repair(&mut self, options: RepairOptions) -> ValidationReport {
+        if let Some(epsilon) = options.weld_epsilon {
+            self.weld(epsilon);
+        }
+        if options.drop_degenerates || options.drop_duplicates {
+            let report = self.validate();
+            let mut keep = vec![true; self.faces.len()];
+            for issue in &report.issues {
+                match issue {
+                    MeshIssue::OutOfRange { face, kind: ReferenceKind::Vertex, .. } | MeshIssue::Degenerate { face } if options.drop_degenerates => keep[*face] = false,
+                    MeshIssue::Duplicate { face, .. } if options.drop_duplicates => keep[*face] = false,
+                    _ => {}
+                }
+            }
+            self.retain_faces(&keep);
+        }
+        if options.unify_winding {
+            self.unify_winding();
+        }
+        self.validate()
+    }
+
+    fn has_valid_vertices(&self, face: usize) -> bool {
+        self.faces[face].iter().all(|r| r[0] > 0 && r[0] <= self.vertexes.len())
+    }
+
+    fn position(&self, reference: usize) -> Vec3 {
+        Vec3::from(self.vertexes[reference - 1].pos)
+    }
+
+    ///non normalized normal of a polygon using Newell's method (length is twice the area)
+    fn face_normal(&self, face: usize) -> Vec3 {
+        let corners = &self.faces[face];
+        let mut n = Vec3::default();
+        for (i, r) in corners.iter().enumerate() {
+            let a = self.position(r[0]);
+            let b = self.position(corners[(i + 1) % corners.len()][0]);
+            n += Vec3::new((a[1] - b[1]) * (a[2] + b[2]), (a[2] - b[2]) * (a[0] + b[0]), (a[0] - b[0]) * (a[1] + b[1]));
+        }
+        n
+    }
+
+    fn is_degenerate(&self, face: usize) -> bool {
+        let corners = &self.faces[face];
+        for (i, r) in corners.iter().enumerate() {
+            if corners[i + 1..].iter().any(|o| o[0] == r[0]) {
+                return true;
+            }
+        }
+        let longest = corners.iter().enumerate().fold(0f32, |acc, (i, r)| {
+            let d = self.position(corners[(i + 1) % corners.len()][0]) - self.position(r[0]);
+            acc.max(d.dot(&d))
+        });
+        let n = self.face_normal(face).dot(&self.face_normal(face)).sqrt();
+        n.is_nan() || n <= longest * 1e-6
+    }
+
+    ///undirected edges (sorted 1 based vertex references) -> faces using them, and if the face walks the edge from the lowest reference to the highest
+    fn edges(&self) -> HashMap<[usize; 2], Vec<(usize, bool)>> {
+        let mut out: HashMap<[usize; 2], Vec<(usize, bool)>> = HashMap::new();
+        for (face, corners) in self.faces.iter().enumerate() {
+            if !self.has_valid_vertices(face) {
+                continue;
+            }
+            for (i, r) in corners.iter().enumerate() {
+                let a = r[0];
+                let b = corners[(i + 1) % corners.len()][0];
+                if a != b {
+                    out.entry([a.min(b), a.max(b)]).or_default().push((face, a < b));
+                }
+            }
+        }
+        out
+    }
+
+    ///remove the faces not flagged in keep, and shrink/remove the material groups accordingly
+    fn retain_faces(&mut self, keep: &[bool]) {
+        let mut remap = vec![None; self.faces.len()];
+        let mut next = 0;
+        for (face, slot) in remap.iter_mut().enumerate() {
+            if keep[face] {
+                *slot = Some(next);
+                next += 1;
+            }
+        }
+        let mut groups = Vec::with_capacity(self.groups.len());
+        for &[material, start, end] in &self.groups {
+            let kept = (start..=end.min(self.faces.len().saturating_sub(1))).filter_map(|f| remap.get(f).copied().flatten()).collect::<Vec<usize>>();
+            if let (Some(first), Some(last)) = (kept.first(), kept.last()) {
+                groups.push([material, *first, *last]);
+            }
+        }
+        let mut faces = std::mem::take(&mut self.faces).into_iter();
+        self.faces = keep.iter().filter_map(|k| {
+            let face = faces.next();
+            if *k { face } else { None }
+        }).collect();
+        self.groups = groups;
+    }
+
+    ///merge the vertices closer than epsilon, the first declared vertex of a cluster is kept and unused vertices are removed
+    fn weld(&mut self, epsilon: f32) {
+        let epsilon = epsilon.max(f32::MIN_POSITIVE);
+        let cell = |p: [f32; 3]| [(p[0] / epsilon).floor() as i64, (p[1] / epsilon).floor() as i64, (p[2] / epsilon).floor() as i64];
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut target = Vec::with_capacity(self.vertexes.len());
+        for (i, v) in self.vertexes.iter().enumerate() {
+            let c = cell(v.pos);
+            let mut found = None;
+            'search: for x in -1..=1 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        if let Some(candidates) = grid.get(&[c[0] + x, c[1] + y, c[2] + z]) {
+                            for &o in candidates {
+                                let d = Vec3::from(self.vertexes[o].pos) - Vec3::from(v.pos);
+                                if d.dot(&d) <= epsilon * epsilon {
+                                    found = Some(o);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(o) = found {
+                target.push(o);
+            } else {
+                grid.entry(c).or_default().push(i);
+                target.push(i);
+            }
+        }
+        let mut used = vec![false; self.vertexes.len()];
+        for face in &mut self.faces {
+            for r in face.iter_mut() {
+                if r[0] > 0 && r[0] <= target.len() {
+                    r[0] = target[r[0] - 1] + 1;
+                    used[r[0] - 1] = true;
+                }
+            }
+        }
+        let mut compact = vec![0; self.vertexes.len()];
+        let mut next = 0;
+        for (i, u) in used.iter().enumerate() {
+            if *u {
+                compact[i] = next + 1;
+                next += 1;
+            }
+        }
+        let mut i = 0;
+        self.vertexes.retain(|_| {
+            i += 1;
+            used[i - 1]
+        });
+        for face in &mut self.faces {
+            for r in face.iter_mut() {
+                if r[0] > 0 && r[0] <= compact.len() {
+                    r[0] = compact[r[0] - 1];
+                }
+            }
+        }
+    }
+
+    ///flood each connected surface from its first face, flipping the neighbours that walk a shared edge in the same direction
+    fn unify_winding(&mut self) {
+        let edges = self.edges();
+        let mut neighbours: Vec<Vec<(usize, bool)>> = vec![Vec::new(); self.faces.len()];
+        for faces in edges.values() {
+            if faces.len() == 2 {
+                let same = faces[0].1 == faces[1].1;
+                neighbours[faces[0].0].push((faces[1].0, same));
+                neighbours[faces[1].0].push((faces[0].0, same));
+            }
+        }
+        let mut flip: Vec<Option<bool>> = vec![None; self.faces.len()];
+        let mut queue = VecDeque::new();
+        for start in 0..self.faces.len() {
+            if flip[start].is_some() {
+                continue;
+            }
+            flip[start] = Some(false);
+            queue.push_back(start);
+            while let Some(face) = queue.pop_front() {
+                let flipped = flip[face].unwrap();
+                for &(other, same) in &neighbours[face] {
+                    if flip[other].is_none() {
+                        flip[other] = Some(flipped ^ same);
+                        queue.push_back(other);
+                    }
+                }
+            }
+        }
+        for (face, flip) in self.faces.iter_mut().zip(flip) {
+            if flip == Some(true) {
+                face.reverse();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use crate::other::resource_manager::ResourceManager;
+    use crate::parser::{MeshIssue, ParsedObject, RepairOptions};
+
+    //unit cube with a flipped face, a degenerate face, a duplicated face, an unused vertex and a vertex to weld
+    const BROKEN_CUBE: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+v 5 5 5
+v 1.00001 1 1
+f 1 4 3 2
+f 5 6 10 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f 4 1 5 8
+f 6 2 3 7
+f 1 2 2
+";
+
+    #[test]
+    fn reports_and_repairs() {
+        let mut resources = ResourceManager::default();
+        let mut object = ParsedObject::parse_chunked(&mut resources, Cursor::new(BROKEN_CUBE), 64).unwrap();
+        object.faces[4].reverse();
+        let report = object.validate();
+        assert!(report.issues.contains(&MeshIssue::Duplicate { face: 6, original: 3 }));
+        assert!(report.issues.contains(&MeshIssue::Degenerate { face: 7 }));
+        assert!(report.issues.contains(&MeshIssue::UnusedVertex { vertex: 8 }));
+        assert!(report.issues.iter().any(|i| matches!(i, MeshIssue::InconsistentWinding { .. })));
+        let report = object.repair(RepairOptions {
+            drop_degenerates: true,
+            drop_duplicates: true,
+            unify_winding: true,
+            weld_epsilon: Some(0.001),
+        });
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(object.vertexes.len(), 8);
+        assert_eq!(object.faces.len(), 6);
+        assert_eq!(object.groups, vec![[0, 0, 5]]);
+    }
+}